@@ -2,21 +2,58 @@
 use std::cmp;
 use super::character::*;
 use super::battlefield::*;
+use super::event::*;
+use super::element::*;
+use super::target::*;
+use super::spell::*;
+use super::skill::*;
+use super::item::*;
+use super::reaction::*;
+use super::battle_generator;
+use super::initiative::TurnOrder;
+
+use super::dice;
 
 extern crate rand;
-// use rand::random;
 
 
 // The Character direct references here are bad and wrong,
 // because what happens if a character dies before an attack goes off?
 // They need to be some sort of indirect reference so we can check
 // whether or not it's valid.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum Action {
-    Attack(CharSpecifier, CharSpecifier),
+    Attack(CharSpecifier, Target),
     Defend(CharSpecifier),
+    ChangeRow(CharSpecifier),
+    Cast(CharSpecifier, Spell, Target),
+    UseItem(CharSpecifier, Item, Target),
+    /// Calls in whoever's at that spot in their summon list.
+    Summon(CharSpecifier, usize),
+    UseSkill(CharSpecifier, Skill, Target),
+    PsycheUp(CharSpecifier),
+}
+
+/// An action someone's busy winding up, and how many more
+/// rounds (or turns, in conditional-turn-based battles) it'll take.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Charge {
+    pub action: Action,
+    pub rounds_left: u32,
 }
 
+/// How much of their max HP someone has to lose in one hit,
+/// in percent, for it to break their concentration.
+pub const HEAVY_HIT_PERCENT: u32 = 25;
+
+/// How much tension one Psyche Up is good for.
+pub const PSYCHE_UP_TENSION: u32 = 40;
+
+/// How much tension getting hurt builds up, in tension
+/// per percent of max HP lost.  So losing half your HP
+/// gets you a quarter of the way there.
+pub const TENSION_PER_HP_PERCENT: u32 = 1;
+
 impl Action {
     /// Defines the priority of actions, the order
     /// in which they will be taken.
@@ -25,6 +62,21 @@ impl Action {
         match *self {
             Action::Attack(..) => 0,
             Action::Defend(_) => 10,
+            Action::ChangeRow(_) => 10,
+            Action::Cast(..) => 0,
+            Action::UseItem(..) => 0,
+            Action::Summon(..) => 0,
+            Action::UseSkill(..) => 0,
+            Action::PsycheUp(_) => 0,
+        }
+    }
+
+    /// How many rounds it takes to get ready before it happens.
+    pub fn windup(&self) -> u32 {
+        match *self {
+            Action::Cast(_, spell, _) => spell.chant_rounds(),
+            Action::UseSkill(_, skill, _) => skill.windup(),
+            _ => 0,
         }
     }
 
@@ -32,6 +84,12 @@ impl Action {
         match *self {
             Action::Attack(from, _) => from,
             Action::Defend(who) => who,
+            Action::ChangeRow(who) => who,
+            Action::Cast(who, _, _) => who,
+            Action::UseItem(who, _, _) => who,
+            Action::Summon(who, _) => who,
+            Action::UseSkill(who, _, _) => who,
+            Action::PsycheUp(who) => who,
         }
     }
 }
@@ -39,108 +97,557 @@ impl Action {
 
 /// Does exactly what it says on the tin.
 /// If the 'to' character specified is not alive,
-/// choose another target at random (that isn't on the same team)
+/// choose another target at random (that the attacker is hostile to)
 /// and returns a CharSpecifier referring to it.
+/// Returns None if there's nobody left to pick.
 pub fn choose_new_target_if_target_is_dead(field: &mut Battlefield,
                                            from: CharSpecifier,
                                            to: CharSpecifier)
-                                           -> CharSpecifier {
+                                           -> Option<CharSpecifier> {
     let fromteam = field.get(from).unwrap().team;
     let tochar_is_alive = field.get(to).unwrap().is_alive();
     if !tochar_is_alive {
         // Now we need to get opponents and select one at random.
-        // We check if the battle is over before every action, so
-        // there should always be at least *one* opponent to choose from.
-
-        // After writing all these nice iterators we can't use them 'cause
-        // we need to get the character *index*, so we need to stick an
-        // enumerate() in there.
-        // Sigh.
-        let living_enemies = field.chars
-            .iter()
-            .enumerate()
-            .filter(|&(_, chr)| chr.team != fromteam)
+        let living_enemies = field.get_opponents_enumerate(fromteam)
             .filter(|&(_, chr)| chr.is_alive());
 
-        let mut rng = rand::thread_rng();
-        let sample = rand::sample(&mut rng, living_enemies, 1);
-        // We always check for victory before each action, so,
-        // there should always be at least opponent available to
-        // choose from.
-        let (i, _) = sample[0];
-        i as CharSpecifier
+        // With more than two teams around, the battle can still
+        // be going on without this particular attacker having
+        // anyone left to hit.
+        match dice::with(|rng| rand::seq::sample_iter(rng, living_enemies, 1)) {
+            Ok(ref sample) if !sample.is_empty() => Some(sample[0].0),
+            _ => None,
+        }
+    } else {
+        Some(to)
+    }
+}
+
+/// Like `choose_new_target_if_target_is_dead`, but for melee attacks,
+/// which also can't reach the back row while the front row is standing.
+pub fn choose_melee_target(field: &mut Battlefield,
+                           from: CharSpecifier,
+                           to: CharSpecifier)
+                           -> Option<CharSpecifier> {
+    if field.in_reach(to) {
+        return Some(to);
+    }
+    let fromteam = field.get(from).unwrap().team;
+    let reachable: Vec<CharSpecifier> = field.get_opponents_enumerate(fromteam)
+        .map(|(i, _)| i)
+        .filter(|&i| field.in_reach(i))
+        .collect();
+    match dice::with(|rng| rand::seq::sample_iter(rng, reachable, 1)) {
+        Ok(ref sample) if !sample.is_empty() => Some(sample[0]),
+        _ => None,
+    }
+}
+
+/// Attacks whoever the target turns out to be.
+/// Each one hit gets their own damage roll and their own events.
+/// Single and random attacks go for someone else if they can't
+/// reach who they were aimed at; everything else just skips anyone
+/// that's out of reach or already dead.
+pub fn do_attack(field: &mut Battlefield,
+                 from: CharSpecifier,
+                 to: Target,
+                 events: &mut Vec<BattleEvent>) {
+    strike(field, from, to, 1, events);
+}
+
+/// An attack that goes around some number of times.
+fn strike(field: &mut Battlefield,
+          from: CharSpecifier,
+          to: Target,
+          times: u32,
+          events: &mut Vec<BattleEvent>) {
+    // A full meter goes into every hit of the attack, and then
+    // it's gone.  Anything less gets saved up for later, and so
+    // does a full one if there turns out to be nobody to hit.
+    let psyched = field.get(from).is_some_and(|chr| chr.fully_psyched());
+    let boost = if psyched {
+        field.get(from).unwrap().tension_boost()
     } else {
-        to
+        100
+    };
+    let mut hits = Vec::new();
+    let mut hit_anyone = false;
+    for _ in 0..times {
+        hit_anyone |= hit_all(field, from, to, boost, &mut hits);
+    }
+    if psyched && hit_anyone {
+        events.push(BattleEvent::Unleashed(from));
+        field.get_mut(from).unwrap().spend_tension();
     }
+    events.extend(hits);
 }
 
-pub fn do_attack(field: &mut Battlefield, from: CharSpecifier, to: CharSpecifier) {
+/// One go at everyone the target covers.  False if there
+/// wasn't anyone it could reach.
+fn hit_all(field: &mut Battlefield,
+           from: CharSpecifier,
+           to: Target,
+           boost: u32,
+           events: &mut Vec<BattleEvent>)
+           -> bool {
+    let mut hit_anyone = false;
+    for defender in to.resolve(field, from) {
+        // Earlier hits may have killed them off already.
+        let defender = if to.retargets() {
+            match choose_melee_target(field, from, defender) {
+                Some(idx) => idx,
+                None => break,
+            }
+        } else if field.in_reach(defender) {
+            defender
+        } else {
+            continue;
+        };
+        let defender = cover(field, from, defender, events);
+        swing(field, from, defender, boost, events);
+        hit_anyone = true;
+    }
+    hit_anyone
+}
+
+/// One plain attack at one character, no questions asked about
+/// whether they can be reached.  Tension doesn't come into it.
+pub fn attack_one(field: &mut Battlefield,
+                  from: CharSpecifier,
+                  defender_idx: CharSpecifier,
+                  events: &mut Vec<BattleEvent>) {
+    swing(field, from, defender_idx, 100, events);
+}
+
+/// `attack_one`, with the damage scaled by `boost` percent.
+fn swing(field: &mut Battlefield,
+         from: CharSpecifier,
+         defender_idx: CharSpecifier,
+         boost: u32,
+         events: &mut Vec<BattleEvent>) {
     // For now, damage equation is just:
     // damage dealt = atk/2 + [0:atk) - soak
     // soak = [0:def)
     // TODO: Better error handling here than unwrap()
-    let atk;
-    let attacker_name;
-    {
+    let (atk, element, poisonous) = {
         let attacker = field.get(from).unwrap();
-        atk = attacker.atk;
-        // This clone is a little dumb, and we could get around
-        // it by breaking the following println!() up into parts.
-        // But that would be dumb and is not worth it.
-        // Since we can't have a reference to attacker and defender
-        // at the same time.
-        attacker_name = attacker.name.clone()
-    }
-    let damage = (rand::random::<u32>() % atk) + (atk / 2);
+        (attacker.atk, attacker.attack_element, attacker.has_passive(Passive::PoisonTouch))
+    };
+    // Stats shouldn't ever be 0, but if one is, don't divide by it.
+    let damage = ((dice::random::<u32>() % atk.max(1)) + (atk / 2)) * boost / 100;
 
-    let defender_idx = choose_new_target_if_target_is_dead(field, from, to);
-    let defender = field.get_mut(defender_idx).unwrap();
-    let soak = rand::random::<u32>() % defender.def;
+    let defender = field.get(defender_idx).unwrap();
+    let soak = dice::random::<u32>() % defender.def.max(1);
 
     let defending = defender.has_buff(BuffType::Defend);
-    let divider = if defending { 2 } else { 1 };
+    let mut divider = if defending { 2 } else { 1 };
+    if defender.row == Row::Back {
+        divider *= 2;
+    }
     // Not sure whether the saturating_sub is perfect or inelegant...
     // either way it's exactly what we want.
     let resulting_damage = (damage / divider).saturating_sub(soak);
-    print!("{} attacked {}!  ", attacker_name, defender.name);
+    events.push(BattleEvent::Attacked(from, defender_idx));
     if resulting_damage == 0 {
         if defending {
-            println!("{} warded them off!", defender.name);
+            events.push(BattleEvent::WardedOff(defender_idx));
         } else {
-            println!("Did no damage!");
+            events.push(BattleEvent::NoDamage(defender_idx));
         }
     } else {
-        println!("Hit!  Did {} damage!", resulting_damage);
-        defender.take_damage(resulting_damage);
-        if !defender.is_alive() {
-            println!("{} perished!", defender.name);
+        let before = events.len();
+        deal_damage(field, defender_idx, resulting_damage, element, events);
+        let hurt = events[before..].iter().any(|e| matches!(*e, BattleEvent::Damaged(..)));
+        let defender = field.get_mut(defender_idx).unwrap();
+        if poisonous && hurt && defender.is_alive() {
+            defender.add_buff(BuffType::Poison, POISON_ROUNDS);
+            events.push(BattleEvent::Buffed(defender_idx, BuffType::Poison));
         }
     }
 }
 
-pub fn do_defend(field: &mut Battlefield, who: CharSpecifier) {
+/// Hits someone for some amount of damage of the given element,
+/// after taking their resistances and such into account.
+/// Attacks, spells, breath, etc. should all go through this
+/// once they've figured out how hard they hit.
+pub fn deal_damage(field: &mut Battlefield,
+                   to: CharSpecifier,
+                   damage: u32,
+                   element: Element,
+                   events: &mut Vec<BattleEvent>) {
+    let defender = field.get_mut(to).unwrap();
+    let affinity = defender.affinity(element);
+    let amount = affinity.apply(damage);
+    match affinity {
+        Affinity::Immune => {
+            events.push(BattleEvent::Immune(to));
+            return;
+        }
+        Affinity::Absorb => {
+            let healed = defender.hp.gain(amount);
+            events.push(BattleEvent::Absorbed(to, healed.applied));
+            return;
+        }
+        Affinity::Weak => events.push(BattleEvent::SuperEffective(to)),
+        Affinity::Resist => events.push(BattleEvent::Resisted(to)),
+        Affinity::Normal => (),
+    }
+    let was_asleep = defender.is_asleep();
+    let hit = defender.take_damage(amount);
+    events.push(BattleEvent::Damaged(to, hit.applied, hit.overflow));
+    if !defender.is_alive() {
+        events.push(BattleEvent::Died(to));
+        return;
+    }
+    if was_asleep {
+        events.push(BattleEvent::WokeUp(to));
+    }
+    defender.tension += amount * 100 / cmp::max(1, defender.hp.max) * TENSION_PER_HP_PERCENT;
+    if amount * 100 >= defender.hp.max * HEAVY_HIT_PERCENT && defender.interrupt() {
+        events.push(BattleEvent::Interrupted(to));
+    }
+}
+
+/// Damage from spells and skills, rather than plain attacks:
+/// no defense or rows to get in the way, just affinities.
+/// Spells can get reflected or shrugged off on the way; skills can't.
+fn blast(field: &mut Battlefield,
+         from: CharSpecifier,
+         to: Target,
+         spell: Option<Spell>,
+         power: u32,
+         element: Element,
+         events: &mut Vec<BattleEvent>) {
+    for c in to.resolve(field, from) {
+        let c = match spell {
+            Some(spell) => {
+                match spell_lands_on(field, from, c, spell, events) {
+                    Some(c) => c,
+                    None => continue,
+                }
+            }
+            None => c,
+        };
+        let damage = dice::random::<u32>() % power.max(1) + power / 2;
+        deal_damage(field, c, damage, element, events);
+    }
+}
+
+pub fn do_psyche_up(field: &mut Battlefield, who: CharSpecifier, events: &mut Vec<BattleEvent>) {
+    let chr = field.get_mut(who).unwrap();
+    chr.tension += PSYCHE_UP_TENSION;
+    events.push(BattleEvent::PsychedUp(who, chr.tension.val));
+}
+
+pub fn do_defend(field: &mut Battlefield, who: CharSpecifier, events: &mut Vec<BattleEvent>) {
     // TODO: Better error handling here.
-    let mut whochar = field.get_mut(who).unwrap();
-    println!("{} defended themselves!", whochar.name);
+    let whochar = field.get_mut(who).unwrap();
     whochar.add_buff(BuffType::Defend, 0);
+    events.push(BattleEvent::Defended(who));
+}
+
+
+
+
+pub fn do_change_row(field: &mut Battlefield, who: CharSpecifier, events: &mut Vec<BattleEvent>) {
+    let whochar = field.get_mut(who).unwrap();
+    whochar.row = whochar.row.swap();
+    events.push(BattleEvent::ChangedRow(who, whochar.row));
+}
+
+
+/// Casts a spell, if they've got the MP for it.
+pub fn do_cast(field: &mut Battlefield,
+               from: CharSpecifier,
+               spell: Spell,
+               to: Target,
+               events: &mut Vec<BattleEvent>) {
+    {
+        let caster = field.get_mut(from).unwrap();
+        if caster.mp.val < spell.mp_cost() {
+            events.push(BattleEvent::NotEnoughMp(from));
+            return;
+        }
+        caster.mp -= spell.mp_cost();
+    }
+    events.push(BattleEvent::CastSpell(from, spell));
+    if let Some(revival) = spell.revival() {
+        do_revive(field, from, to, revival, events);
+    }
+    if let Some((buff, turns)) = spell.buff() {
+        for c in to.resolve(field, from) {
+            let c = match spell_lands_on(field, from, c, spell, events) {
+                Some(c) => c,
+                None => continue,
+            };
+            let chr = field.get_mut(c).unwrap();
+            chr.add_buff(buff, turns);
+            events.push(BattleEvent::Buffed(c, buff));
+            if buff == BuffType::Sleep && chr.interrupt() {
+                events.push(BattleEvent::Interrupted(c));
+            }
+        }
+    }
+    if let Some((power, element)) = spell.damage() {
+        blast(field, from, to, Some(spell), power, element, events);
+    }
+}
+
+/// Uses a skill, if it's cooled down and they can pay for it.
+/// Ones with a delay just get set up here, and do their thing
+/// later on in `battle::set_off_delayed`.
+pub fn do_use_skill(field: &mut Battlefield,
+                    from: CharSpecifier,
+                    skill: Skill,
+                    to: Target,
+                    events: &mut Vec<BattleEvent>) {
+    let round = field.round;
+    {
+        let chr = field.get_mut(from).unwrap();
+        if chr.cooling_down(skill, round) {
+            events.push(BattleEvent::CoolingDown(from, skill));
+            return;
+        }
+        let cost = skill.cost();
+        if !chr.can_afford(cost) {
+            events.push(match cost {
+                Cost::Mp(_) => BattleEvent::NotEnoughMp(from),
+                Cost::Hp(_) => BattleEvent::NotEnoughHp(from),
+                Cost::Tension(_) | Cost::Free => BattleEvent::NotPsyched(from),
+            });
+            return;
+        }
+        chr.pay(cost);
+        chr.start_cooldown(skill, round);
+    }
+    events.push(BattleEvent::UsedSkill(from, skill));
+    if skill.delay() > 0 {
+        let round = field.round + skill.delay();
+        field.delayed.push(Delayed {
+            round,
+            from,
+            skill,
+            target: to,
+        });
+        return;
+    }
+    skill_effect(field, from, skill, to, events);
+}
+
+/// What a skill actually does once it lands.
+pub fn skill_effect(field: &mut Battlefield,
+                    from: CharSpecifier,
+                    skill: Skill,
+                    to: Target,
+                    events: &mut Vec<BattleEvent>) {
+    if let Some((power, element)) = skill.damage() {
+        blast(field, from, to, None, power, element, events);
+        return;
+    }
+    match skill {
+        Skill::DoubleSlash => strike(field, from, to, 2, events),
+        Skill::Sweep => strike(field, from, Target::AllEnemies, 1, events),
+        Skill::Steal => do_steal(field, from, to, events),
+        Skill::Whistle => do_whistle(field, from, events),
+        Skill::FireBreath | Skill::TimeBomb | Skill::Gigaslash => (),
+    }
+}
+
+/// Percent chance of a steal working.
+pub const STEAL_CHANCE: u32 = 50;
+
+/// Tries to take something out of the target's team's bag
+/// and put it in the thief's.
+fn do_steal(field: &mut Battlefield,
+            from: CharSpecifier,
+            to: Target,
+            events: &mut Vec<BattleEvent>) {
+    let victim = match to.resolve(field, from).first() {
+        Some(&c) => field.get(c).unwrap().team,
+        None => return,
+    };
+    let thief = field.get(from).unwrap().team;
+    let loot: Vec<Item> = field.inventory(victim)
+        .map(|inv| inv.items().iter().map(|e| e.0).collect())
+        .unwrap_or_default();
+    let item = match dice::choose(&loot) {
+        Some(&item) if dice::random::<u32>() % 100 < STEAL_CHANCE => item,
+        _ => {
+            events.push(BattleEvent::StealFailed(from));
+            return;
+        }
+    };
+    field.inventory_mut(victim).take(item);
+    field.inventory_mut(thief).add(item, 1);
+    events.push(BattleEvent::Stole(from, item));
+}
+
+/// Lures some random monster into the fight, whichever
+/// side the whistler's on.
+fn do_whistle(field: &mut Battlefield, from: CharSpecifier, events: &mut Vec<BattleEvent>) {
+    let mut monster = battle_generator::random_monster();
+    monster.row = Row::Front;
+    if field.spawn(monster, events).is_none() {
+        events.push(BattleEvent::NobodyCame(from));
+    }
 }
 
+/// Uses up one of the item from the user's team's bag.
+/// Somebody else on the team might have used the last one
+/// earlier in the round, in which case nothing happens.
+pub fn do_use_item(field: &mut Battlefield,
+                   from: CharSpecifier,
+                   item: Item,
+                   to: Target,
+                   events: &mut Vec<BattleEvent>) {
+    let team = field.get(from).unwrap().team;
+    if !field.inventory_mut(team).take(item) {
+        events.push(BattleEvent::OutOfItem(from, item));
+        return;
+    }
+    events.push(BattleEvent::UsedItem(from, item));
+    if let Some(revival) = item.revival() {
+        do_revive(field, from, to, revival, events);
+    }
+}
+
+/// How many rounds an ally summoned by the party sticks around.
+pub const SUMMON_ROUNDS: u32 = 3;
+
+/// Calls for help.  Monsters get another monster for good;
+/// the party just borrows a friend for a few rounds, who then
+/// fights on their own.
+pub fn do_summon(field: &mut Battlefield,
+                 who: CharSpecifier,
+                 n: usize,
+                 events: &mut Vec<BattleEvent>) {
+    let (name, team) = {
+        let chr = field.get(who).unwrap();
+        match chr.summons.get(n) {
+            Some(name) => (name.clone(), chr.team),
+            None => return,
+        }
+    };
+    events.push(BattleEvent::CalledForHelp(who));
+    let helper = battle_generator::template(&name).map(|mut helper| {
+        helper.team = team;
+        helper.row = Row::Front;
+        if team == Team::Player {
+            helper.lifetime = Some(SUMMON_ROUNDS);
+        }
+        helper
+    });
+    let spawned = match helper {
+        Some(helper) => field.spawn(helper, events),
+        None => None,
+    };
+    if spawned.is_none() {
+        events.push(BattleEvent::NobodyCame(who));
+    }
+}
+
+/// Tries to get whoever's knocked out among the targets back up.
+/// Each one gets their own roll.
+pub fn do_revive(field: &mut Battlefield,
+                 from: CharSpecifier,
+                 to: Target,
+                 revival: Revival,
+                 events: &mut Vec<BattleEvent>) {
+    let targets = to.resolve_fallen(field, from);
+    if targets.is_empty() {
+        // Probably already back up, somehow.
+        if let Target::Single(c) = to {
+            events.push(BattleEvent::NoEffect(c));
+        }
+        return;
+    }
+    for c in targets {
+        let chr = field.get_mut(c).unwrap();
+        if revival.succeeds() && chr.revive(revival.hp_percent) {
+            events.push(BattleEvent::Revived(c));
+        } else {
+            events.push(BattleEvent::NoEffect(c));
+        }
+    }
+}
 
 
+/// Keeps track of actions that take a while to get going.
+/// Starts the character winding up if it's that sort of action,
+/// or counts down if they're already at it.  Returns true
+/// once it's actually time for the action to happen.
+fn charge_up(field: &mut Battlefield, action: &Action, events: &mut Vec<BattleEvent>) -> bool {
+    let who = action.source();
+    let chr = field.get_mut(who).unwrap();
+    if let Some(charge) = chr.charging {
+        if charge.action == *action {
+            if charge.rounds_left > 1 {
+                chr.charging = Some(Charge {
+                    rounds_left: charge.rounds_left - 1,
+                    ..charge
+                });
+                events.push(BattleEvent::StillCharging(who));
+                return false;
+            }
+            chr.charging = None;
+            return true;
+        }
+    }
+    let windup = action.windup();
+    if windup == 0 {
+        return true;
+    }
+    match *action {
+        // No point chanting a spell they can't pay for;
+        // let it go ahead and fail.
+        Action::Cast(_, spell, _) if chr.mp.val < spell.mp_cost() => return true,
+        Action::Cast(_, spell, _) => events.push(BattleEvent::Chanting(who, spell)),
+        Action::UseSkill(_, skill, _) if !chr.can_afford(skill.cost()) => return true,
+        Action::UseSkill(_, skill, _) => events.push(BattleEvent::WindingUp(who, skill)),
+        _ => (),
+    }
+    chr.charging = Some(Charge {
+        action: *action,
+        rounds_left: windup,
+    });
+    false
+}
 
-pub fn run_action(field: &mut Battlefield, action: &Action) {
+/// Carries out an action, then lets anyone it set off react to it.
+pub fn run_action(field: &mut Battlefield, action: &Action, events: &mut Vec<BattleEvent>) {
     // If the source of an action is dead, we skip the action.
+    // Same if they've been put to sleep since they picked it,
+    // but then we say so.
     {
         let source = action.source();
         let sourcechar = field.get(source).unwrap();
         if !sourcechar.is_alive() {
             return;
         }
+        if sourcechar.is_asleep() {
+            events.push(BattleEvent::Asleep(source));
+            return;
+        }
     };
+    if !charge_up(field, action, events) {
+        return;
+    }
+    let start = events.len();
+    perform(field, action, events);
+    let happened = events[start..].to_vec();
+    react(field, &happened, events);
+}
 
+fn perform(field: &mut Battlefield, action: &Action, events: &mut Vec<BattleEvent>) {
     match *action {
-        Action::Attack(from, to) => do_attack(field, from, to),
-        Action::Defend(who) => do_defend(field, who),
+        Action::Attack(from, to) => do_attack(field, from, to, events),
+        Action::Defend(who) => do_defend(field, who, events),
+        Action::ChangeRow(who) => do_change_row(field, who, events),
+        Action::Cast(who, spell, to) => do_cast(field, who, spell, to, events),
+        Action::UseItem(who, item, to) => do_use_item(field, who, item, to, events),
+        Action::Summon(who, n) => do_summon(field, who, n, events),
+        Action::UseSkill(who, skill, to) => do_use_skill(field, who, skill, to, events),
+        Action::PsycheUp(who) => do_psyche_up(field, who, events),
     };
 }
 
@@ -148,32 +655,338 @@ pub fn run_action(field: &mut Battlefield, action: &Action) {
 /// Takes a Vec<Action> and reorders it into the order
 /// in which they should be executed in the fight:
 /// Actions have priority, highest priority ones go first
-/// Then, characters with higher speed go befoer those with
-/// lower speed.
-pub fn order_actions(field: &Battlefield, actions: &mut Vec<Action>) {
+/// Then, whoever rolled better initiative this round
+/// goes before whoever didn't.
+pub fn order_actions(order: &TurnOrder, actions: &mut [Action]) {
     let compare_actions = |action1: &Action, action2: &Action| {
         if action1.priority() > action2.priority() {
             cmp::Ordering::Less
         } else if action1.priority() < action2.priority() {
             cmp::Ordering::Greater
         } else {
-            // Actions have equal priority,
-            // find out who is doing each action and go off
-            // the faster one.
-            let charspec1 = action1.source();
-            let charspec2 = action2.source();
-            let char1 = field.get(charspec1).unwrap();
-            let char2 = field.get(charspec2).unwrap();
-            // BUGGO:
-            // This is actually slightly wrong, because the sort is stable,
-            // so if the speeds are equal, the first one will always go first.
-            // Ideally it should be a coin-flip, or such...
-            // If we want to really do it Dragon Warrior style there should probably
-            // be a bit of unpredictability in the ordering here, too.
-            // Ah well, fine for now.
-            char2.spd.cmp(&char1.spd)
+            // Actions have equal priority, so it's down to
+            // who's first in the turn order.  Ties have already
+            // been settled when it was rolled.
+            order.position(action1.source()).cmp(&order.position(action2.source()))
         }
     };
     actions.sort_by(compare_actions);
 
 }
+
+#[test]
+fn elements_matter() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Blazeghost", Team::Monster));
+    b.get_mut(0).unwrap().set_affinity(Element::Fire, Affinity::Absorb);
+    b.get_mut(0).unwrap().set_affinity(Element::Ice, Affinity::Weak);
+    b.get_mut(0).unwrap().set_affinity(Element::Holy, Affinity::Immune);
+    let mut events = Vec::new();
+
+    deal_damage(&mut b, 0, 4, Element::Ice, &mut events);
+    assert_eq!(events,
+               vec![BattleEvent::SuperEffective(0), BattleEvent::Damaged(0, 6, 0)]);
+    assert_eq!(b.get(0).unwrap().hp.val, 4);
+
+    events.clear();
+    deal_damage(&mut b, 0, 3, Element::Fire, &mut events);
+    assert_eq!(events, vec![BattleEvent::Absorbed(0, 3)]);
+    assert_eq!(b.get(0).unwrap().hp.val, 7);
+
+    // Can't absorb past full.
+    events.clear();
+    deal_damage(&mut b, 0, 5, Element::Fire, &mut events);
+    assert_eq!(events, vec![BattleEvent::Absorbed(0, 3)]);
+
+    events.clear();
+    deal_damage(&mut b, 0, 100, Element::Holy, &mut events);
+    assert_eq!(events, vec![BattleEvent::Immune(0)]);
+
+    events.clear();
+    deal_damage(&mut b, 0, 100, Element::Physical, &mut events);
+    assert_eq!(events, vec![BattleEvent::Damaged(0, 10, 90), BattleEvent::Died(0)]);
+    assert_eq!(overkill(&events), 90);
+}
+
+#[test]
+fn melee_cant_reach_back_row() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Healslime", Team::Monster));
+    b.get_mut(2).unwrap().row = Row::Back;
+    let mut events = Vec::new();
+    do_attack(&mut b, 0, Target::Single(2), &mut events);
+    assert_eq!(events[0], BattleEvent::Attacked(0, 1));
+
+    events.clear();
+    run_action(&mut b, &Action::ChangeRow(2), &mut events);
+    assert_eq!(b.get(2).unwrap().row, Row::Front);
+    assert_eq!(events, vec![BattleEvent::ChangedRow(2, Row::Front)]);
+}
+
+#[test]
+fn group_attacks_hit_everyone() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Drakee", Team::Monster));
+    let mut events = Vec::new();
+    do_attack(&mut b, 0, Target::Group(1), &mut events);
+    let attacked: Vec<BattleEvent> = events.iter()
+        .cloned()
+        .filter(|e| matches!(*e, BattleEvent::Attacked(..)))
+        .collect();
+    assert_eq!(attacked,
+               vec![BattleEvent::Attacked(0, 1), BattleEvent::Attacked(0, 2)]);
+
+    // Three random hits at a single slime all land on it,
+    // until it dies and the rest go elsewhere.
+    b.get_mut(2).unwrap().take_damage(1_000_000);
+    b.get_mut(3).unwrap().take_damage(1_000_000);
+    events.clear();
+    do_attack(&mut b, 0, Target::Random(3), &mut events);
+    assert!(events.iter().all(|e| match *e {
+        BattleEvent::Attacked(0, to) => to == 1,
+        _ => true,
+    }));
+}
+
+#[test]
+fn revival() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new_with_stats("Cristo", Team::Player, 10, 20, 10, 10, 10, 10));
+    b.chars.push(Character::new("Alena", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    b.inventory_mut(Team::Player).add(Item::WorldTreeLeaf, 1);
+    let mut events = Vec::new();
+
+    run_action(&mut b,
+               &Action::UseItem(0, Item::WorldTreeLeaf, Target::Single(1)),
+               &mut events);
+    assert_eq!(events,
+               vec![BattleEvent::UsedItem(0, Item::WorldTreeLeaf), BattleEvent::Revived(1)]);
+    assert_eq!(b.get(1).unwrap().hp.val, 10);
+
+    // No more leaves.
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    events.clear();
+    run_action(&mut b,
+               &Action::UseItem(0, Item::WorldTreeLeaf, Target::Single(1)),
+               &mut events);
+    assert_eq!(events, vec![BattleEvent::OutOfItem(0, Item::WorldTreeLeaf)]);
+
+    // Kazing always works, but costs MP.
+    b.get_mut(0).unwrap().mp -= 10;
+    events.clear();
+    run_action(&mut b, &Action::Cast(0, Spell::Kazing, Target::Single(1)), &mut events);
+    assert_eq!(events, vec![BattleEvent::NotEnoughMp(0)]);
+    b.get_mut(0).unwrap().mp += 20;
+    events.clear();
+    run_action(&mut b, &Action::Cast(0, Spell::Kazing, Target::Single(1)), &mut events);
+    assert_eq!(events,
+               vec![BattleEvent::CastSpell(0, Spell::Kazing), BattleEvent::Revived(1)]);
+    assert_eq!(b.get(0).unwrap().mp.val, 5);
+
+    // And reviving someone who's up does nothing.
+    events.clear();
+    b.get_mut(0).unwrap().mp += 20;
+    run_action(&mut b, &Action::Cast(0, Spell::Kazing, Target::Single(1)), &mut events);
+    assert_eq!(events[1], BattleEvent::NoEffect(1));
+}
+
+#[test]
+fn calling_for_help() {
+    let mut b = Battlefield::new();
+    b.capacity = 2;
+    b.chars.push(Character::new("Rex", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.get_mut(0).unwrap().summons.push(String::from("Great Sabrecat"));
+    b.get_mut(1).unwrap().summons.push(String::from("Slime"));
+    let mut events = Vec::new();
+
+    run_action(&mut b, &Action::Summon(1, 0), &mut events);
+    assert_eq!(events,
+               vec![BattleEvent::CalledForHelp(1), BattleEvent::Appeared(2)]);
+    assert_eq!(b.get(2).unwrap().team, Team::Monster);
+    assert!(!b.get(2).unwrap().is_temporary());
+
+    events.clear();
+    run_action(&mut b, &Action::Summon(1, 0), &mut events);
+    assert_eq!(events,
+               vec![BattleEvent::CalledForHelp(1), BattleEvent::NobodyCame(1)]);
+
+    events.clear();
+    run_action(&mut b, &Action::Summon(0, 0), &mut events);
+    let cat = b.get(3).unwrap();
+    assert_eq!(cat.name, "Great Sabrecat");
+    assert_eq!(cat.team, Team::Player);
+    assert_eq!(cat.lifetime, Some(SUMMON_ROUNDS));
+}
+
+#[test]
+fn defending_still_goes_first() {
+    let order = TurnOrder::from_list(&[2, 0, 1]);
+    let mut actions = [Action::Attack(0, Target::Single(2)),
+                       Action::Attack(2, Target::Single(0)),
+                       Action::Defend(1)];
+    order_actions(&order, &mut actions);
+    assert_eq!(actions,
+               [Action::Defend(1),
+                Action::Attack(2, Target::Single(0)),
+                Action::Attack(0, Target::Single(2))]);
+}
+
+#[test]
+fn actions_come_out_in_order() {
+    dice::seed(3);
+    for _ in 0..200 {
+        let mut people: Vec<CharSpecifier> = (0..8).collect();
+        dice::shuffle(&mut people);
+        let order = TurnOrder::from_list(&people[..6]);
+        let mut actions: Vec<Action> = (0..8)
+            .map(|c| match dice::random::<u32>() % 4 {
+                0 => Action::Attack(c, Target::Single(0)),
+                1 => Action::Defend(c),
+                2 => Action::ChangeRow(c),
+                _ => Action::PsycheUp(c),
+            })
+            .collect();
+        order_actions(&order, &mut actions);
+        for pair in actions.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            assert!(a.priority() >= b.priority());
+            if a.priority() == b.priority() {
+                assert!(order.position(a.source()) < order.position(b.source()) ||
+                        order.position(b.source()) == 6);
+            }
+        }
+    }
+}
+
+#[test]
+fn attack_damage_spread() {
+    dice::seed(11);
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new_with_stats("Metal Slime", Team::Monster, 1_000_000, 0, 0, 1, 10, 0));
+    b.get_mut(0).unwrap().atk = 10;
+    let mut counts = [0u32; 20];
+    let mut events = Vec::new();
+    const HITS: u32 = 10_000;
+    for _ in 0..HITS {
+        attack_one(&mut b, 0, 1, &mut events);
+        match events.last() {
+            Some(&BattleEvent::Damaged(1, n, 0)) => counts[n as usize] += 1,
+            other => panic!("Wrong event: {:?}", other),
+        }
+        events.clear();
+    }
+    // atk/2 up to 3*atk/2, evenly, and nothing else.
+    assert!(counts.iter().enumerate().all(|(n, &c)| (5..15).contains(&n) == (c > 0)));
+    for &c in &counts[5..15] {
+        assert!((800..1200).contains(&c), "{:?}", counts);
+    }
+    let total: u32 = counts.iter().enumerate().map(|(n, &c)| n as u32 * c).sum();
+    let mean = f64::from(total) / f64::from(HITS);
+    assert!((mean - 9.5).abs() < 0.1, "mean {}", mean);
+}
+
+#[test]
+fn haste_spells() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Brey", Team::Player));
+    b.chars.push(Character::new("Alena", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    let mut events = Vec::new();
+    run_action(&mut b, &Action::Cast(0, Spell::Acceleratle, Target::AllAllies), &mut events);
+    assert_eq!(events,
+               vec![BattleEvent::CastSpell(0, Spell::Acceleratle),
+                    BattleEvent::Buffed(0, BuffType::Haste),
+                    BattleEvent::Buffed(1, BuffType::Haste)]);
+    assert_eq!(b.get(1).unwrap().speed(), 20);
+    run_action(&mut b, &Action::Cast(0, Spell::Deceleratle, Target::Single(2)), &mut events);
+    assert_eq!(b.get(2).unwrap().speed(), 5);
+}
+
+#[test]
+fn tension() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new_with_stats("Terry", Team::Player, 10, 0, 2, 1, 10, 10));
+    b.chars.push(Character::new_with_stats("Slime", Team::Monster, 100, 0, 2, 1, 10, 10));
+    b.get_mut(0).unwrap().skills.push(Skill::Gigaslash);
+    let mut events = Vec::new();
+
+    run_action(&mut b, &Action::UseSkill(0, Skill::Gigaslash, Target::AllEnemies), &mut events);
+    assert_eq!(events, vec![BattleEvent::NotPsyched(0)]);
+    for _ in 0..3 {
+        run_action(&mut b, &Action::PsycheUp(0), &mut events);
+    }
+    assert_eq!(*events.last().unwrap(), BattleEvent::PsychedUp(0, TENSION_MAX));
+    assert!(format!("{}", b.get(0).unwrap()).contains("Tension: 100/100"));
+
+    // Full tension triples the hit, and then it's gone.
+    events.clear();
+    run_action(&mut b, &Action::Attack(0, Target::Single(1)), &mut events);
+    assert_eq!(events[0], BattleEvent::Unleashed(0));
+    match events[2] {
+        BattleEvent::Damaged(1, n, _) => assert!(n == 3 || n == 6, "did {}", n),
+        other => panic!("Wrong event: {:?}", other),
+    }
+    assert_eq!(b.get(0).unwrap().tension.val, 0);
+
+    // Getting hurt builds it back up.
+    deal_damage(&mut b, 0, 5, Element::Physical, &mut events);
+    assert_eq!(b.get(0).unwrap().tension.val, 50);
+    // But half a meter doesn't do anything yet, so it's kept.
+    events.clear();
+    run_action(&mut b, &Action::Attack(0, Target::Single(1)), &mut events);
+    assert_eq!(events[0], BattleEvent::Attacked(0, 1));
+    assert_eq!(b.get(0).unwrap().tension.val, 50);
+
+    // A full meter with nobody left to hit stays full.
+    b.get_mut(0).unwrap().tension.val = TENSION_MAX;
+    b.get_mut(1).unwrap().knock_out();
+    events.clear();
+    run_action(&mut b, &Action::Attack(0, Target::Single(1)), &mut events);
+    assert!(!events.contains(&BattleEvent::Unleashed(0)));
+    assert!(b.get(0).unwrap().fully_psyched());
+}
+
+#[test]
+fn skill_costs_and_cooldowns() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new_with_stats("Taloon", Team::Player, 3, 0, 2, 1, 10, 10));
+    b.chars.push(Character::new_with_stats("Slime", Team::Monster, 100, 0, 2, 1, 10, 10));
+    b.get_mut(0).unwrap().skills = vec![Skill::DoubleSlash, Skill::Steal];
+    let mut events = Vec::new();
+
+    // Two swings, for 2 HP.
+    run_action(&mut b, &Action::UseSkill(0, Skill::DoubleSlash, Target::Single(1)), &mut events);
+    let swings = events.iter().filter(|e| matches!(**e, BattleEvent::Attacked(0, 1))).count();
+    assert_eq!(swings, 2);
+    assert_eq!(b.get(0).unwrap().hp.val, 1);
+    // Which they can't pay again, and it's cooling down anyway.
+    assert!(!b.get(0).unwrap().can_use_skill(Skill::DoubleSlash, b.round + 2));
+    events.clear();
+    run_action(&mut b, &Action::UseSkill(0, Skill::DoubleSlash, Target::Single(1)), &mut events);
+    assert_eq!(events, vec![BattleEvent::CoolingDown(0, Skill::DoubleSlash)]);
+    b.round += 2;
+    events.clear();
+    run_action(&mut b, &Action::UseSkill(0, Skill::DoubleSlash, Target::Single(1)), &mut events);
+    assert_eq!(events, vec![BattleEvent::NotEnoughHp(0)]);
+
+    // Nothing to steal yet.
+    events.clear();
+    run_action(&mut b, &Action::UseSkill(0, Skill::Steal, Target::Single(1)), &mut events);
+    assert_eq!(events[1], BattleEvent::StealFailed(0));
+    b.inventory_mut(Team::Monster).add(Item::WorldTreeLeaf, 1);
+    while b.inventory_mut(Team::Player).is_empty() {
+        b.round += 2;
+        run_action(&mut b, &Action::UseSkill(0, Skill::Steal, Target::Single(1)), &mut events);
+    }
+    assert!(events.contains(&BattleEvent::Stole(0, Item::WorldTreeLeaf)));
+    assert!(b.inventory_mut(Team::Monster).is_empty());
+}
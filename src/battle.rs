@@ -0,0 +1,523 @@
+use super::character::*;
+use super::battlefield::*;
+use super::action::*;
+use super::event::*;
+use super::target::*;
+use super::reaction::*;
+use super::skill::*;
+use super::initiative::TurnOrder;
+
+use std::mem;
+
+use super::dice;
+use rand;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BattleStatus {
+    /// The party, and whoever was on their side, won.
+    PlayerVictory,
+    /// The monsters, and whoever was on their side, won.
+    MonsterVictory,
+    /// Somebody else entirely came out on top.
+    OtherVictory(Team),
+    Continuing, /* PlayersFled,
+                 * MonstersFled */
+}
+
+/// Checks whether the battle's over, and who won if so.
+/// It's over when only one alliance is left standing.
+pub fn battle_status(field: &Battlefield) -> BattleStatus {
+    if !field.battle_over() {
+        return BattleStatus::Continuing;
+    }
+    // Neutral bystanders don't get to win just by surviving.
+    let winners: Vec<Team> = field.chars
+        .iter()
+        .filter(|chr| chr.is_alive() && field.is_combatant(chr.team))
+        .map(|chr| chr.team)
+        .collect();
+    let on_side_of = |side: Team| winners.iter().any(|&t| field.relations.allied(t, side));
+    if on_side_of(Team::Player) {
+        BattleStatus::PlayerVictory
+    } else if on_side_of(Team::Monster) {
+        BattleStatus::MonsterVictory
+    } else {
+        match winners.first() {
+            Some(&team) => BattleStatus::OtherVictory(team),
+            // Everybody's dead, which counts as a loss for the party.
+            None => BattleStatus::MonsterVictory,
+        }
+    }
+}
+
+/// Picks actions for everyone the computer controls, which is
+/// everyone not in the party, plus anyone the party summoned.
+pub fn decide_monster_actions(field: &Battlefield, actions: &mut Vec<Action>) {
+    let computer_controlled: Vec<CharSpecifier> = field.chars
+        .iter()
+        .enumerate()
+        .filter(|&(_, chr)| chr.team != Team::Player || chr.is_temporary())
+        .map(|(i, _)| i)
+        .collect();
+    decide_actions(field, &computer_controlled, actions);
+}
+
+/// The other half of `decide_monster_actions`: picks actions for
+/// the party, for when nobody's playing them.
+pub fn decide_party_actions(field: &Battlefield, actions: &mut Vec<Action>) {
+    let party: Vec<CharSpecifier> = field.chars
+        .iter()
+        .enumerate()
+        .filter(|&(_, chr)| chr.team == Team::Player && !chr.is_temporary())
+        .map(|(i, _)| i)
+        .collect();
+    decide_actions(field, &party, actions);
+}
+
+/// Picks actions for the given characters, skipping any who can't act.
+/// For now they just attack someone they don't like at random,
+/// unless they can revive a fallen friend, or feel like calling
+/// for help or pulling out one of their big moves.
+/// Now and then they psyche up instead, and once they're
+/// fully psyched they'll use it on something big if they can.
+pub fn decide_actions(field: &Battlefield, who: &[CharSpecifier], actions: &mut Vec<Action>) {
+    let living = who.iter()
+        .filter_map(|&i| field.get(i).map(|chr| (i, chr)))
+        .filter(|&(_, chr)| chr.can_act());
+
+    for (i, chr) in living {
+        if let Some(action) = choose_revival(field, i) {
+            actions.push(action);
+            continue;
+        }
+        if !chr.summons.is_empty() && field.has_room(chr.team) && dice::random::<u32>().is_multiple_of(8) {
+            let n = dice::random::<usize>() % chr.summons.len();
+            actions.push(Action::Summon(i, n));
+            continue;
+        }
+        let round = field.round;
+        let usable: Vec<Skill> = chr.skills
+            .iter()
+            .cloned()
+            .filter(|&s| chr.can_use_skill(s, round))
+            .collect();
+        // Anything that runs on tension gets used as soon as it can be;
+        // it's what they were psyching up for.
+        let big_move = usable.iter()
+            .cloned()
+            .find(|s| matches!(s.cost(), Cost::Tension(_)));
+        let skill = big_move.or_else(|| {
+            if dice::random::<u32>().is_multiple_of(3) {
+                dice::choose(&usable).cloned()
+            } else {
+                None
+            }
+        });
+        if let Some(action) = skill.and_then(|s| aim_skill(field, i, s)) {
+            actions.push(action);
+            continue;
+        }
+        if !chr.fully_psyched() && dice::random::<u32>().is_multiple_of(8) {
+            actions.push(Action::PsycheUp(i));
+            continue;
+        }
+        let living_enemies = field.get_opponents_enumerate(chr.team)
+            .filter(|&(i, _)| field.in_reach(i));
+        let action = match dice::with(|rng| rand::seq::sample_iter(rng, living_enemies, 1)) {
+            Ok(ref sample) if !sample.is_empty() => Action::Attack(i, Target::Single(sample[0].0)),
+            // Nobody to fight, so just sit tight.
+            _ => Action::Defend(i),
+        };
+        actions.push(action);
+    }
+}
+
+
+/// Picks someone to use the skill on, if it needs picking.
+/// None if there's nobody it could be used on.
+fn aim_skill(field: &Battlefield, who: CharSpecifier, skill: Skill) -> Option<Action> {
+    if let Some(to) = skill.area() {
+        return Some(Action::UseSkill(who, skill, to));
+    }
+    let team = field.get(who)?.team;
+    let reachable: Vec<CharSpecifier> = field.get_opponents_enumerate(team)
+        .map(|(i, _)| i)
+        .filter(|&i| field.in_reach(i))
+        .collect();
+    let to = *dice::choose(&reachable)?;
+    Some(Action::UseSkill(who, skill, Target::Single(to)))
+}
+
+/// If the character knows a revival spell they can afford and
+/// one of their allies is down, picks one to bring back.
+fn choose_revival(field: &Battlefield, who: CharSpecifier) -> Option<Action> {
+    let chr = field.get(who)?;
+    let spell = chr.spells
+        .iter()
+        .cloned()
+        .find(|s| s.revival().is_some() && chr.mp.val >= s.mp_cost())?;
+    let fallen = Target::AllAllies.resolve_fallen(field, who);
+    match dice::with(|rng| rand::seq::sample_iter(rng, fallen, 1)) {
+        Ok(ref sample) if !sample.is_empty() => {
+            Some(Action::Cast(who, spell, Target::Single(sample[0])))
+        }
+        _ => None,
+    }
+}
+
+
+/// Brings in any waves of reinforcements that are due,
+/// either because it's their round or because their side's
+/// been wiped out.  Anyone who doesn't fit stays waiting
+/// for another try later.
+pub fn call_reinforcements(field: &mut Battlefield, events: &mut Vec<BattleEvent>) {
+    let waves = mem::take(&mut field.waves);
+    for mut wave in waves {
+        let due = match wave.chars.first() {
+            Some(chr) => {
+                wave.round <= field.round || !field.get_team(chr.team).any(|c| c.is_alive())
+            }
+            None => false,
+        };
+        if due {
+            let arriving = mem::take(&mut wave.chars);
+            for chr in arriving {
+                if !field.has_room(chr.team) {
+                    wave.chars.push(chr);
+                    continue;
+                }
+                field.spawn(chr, events);
+            }
+        }
+        if !wave.chars.is_empty() {
+            field.waves.push(wave);
+        }
+    }
+}
+
+/// Counts down how long summoned allies have left,
+/// and sends home the ones whose time is up.
+pub fn tick_summons(field: &mut Battlefield, events: &mut Vec<BattleEvent>) {
+    for (i, chr) in field.chars.iter_mut().enumerate() {
+        match chr.lifetime {
+            Some(0) | None => (),
+            Some(n) => {
+                chr.lifetime = Some(n - 1);
+                if n == 1 {
+                    chr.knock_out();
+                    events.push(BattleEvent::Departed(i));
+                }
+            }
+        }
+    }
+}
+
+
+/// Sets off anything that was due to go off by the end of this round.
+pub fn set_off_delayed(field: &mut Battlefield, events: &mut Vec<BattleEvent>) {
+    let round = field.round;
+    let (due, waiting): (Vec<Delayed>, Vec<Delayed>) = mem::take(&mut field.delayed)
+        .into_iter()
+        .partition(|d| d.round <= round);
+    field.delayed = waiting;
+    for d in due {
+        events.push(BattleEvent::WentOff(d.from, d.skill));
+        skill_effect(field, d.from, d.skill, d.target, events);
+    }
+}
+
+/// What the character's going to do on their turn whether
+/// anyone likes it or not, since they're busy winding it up.
+pub fn charged_action(field: &Battlefield, c: CharSpecifier) -> Option<Action> {
+    field.get(c)?.charging.map(|charge| charge.action)
+}
+
+pub fn tick_buffs(field: &mut Battlefield) {
+    // The knocked-out don't have any buffs to tick.
+    for c in field.chars.iter_mut().filter(|c| c.is_alive()) {
+        c.tick_buffs()
+    }
+}
+
+/// Rolls this round's turn order ahead of time, so it can be shown
+/// to the player before they pick commands.  `run_turn` will stick
+/// to it rather than rolling a new one.
+pub fn preview_turn_order(field: &mut Battlefield) -> TurnOrder {
+    let order = TurnOrder::roll(field);
+    field.turn_order.get_or_insert(order).clone()
+}
+
+/// Runs a single turn in the battle.
+/// It takes a battlefield state, and a list of actions
+/// and applies the actions in the proper order.
+/// Anything that happens gets pushed onto `events`.
+/// It returns a battle status.
+pub fn run_turn(field: &mut Battlefield,
+                actions: &mut [Action],
+                events: &mut Vec<BattleEvent>)
+                -> BattleStatus {
+    // We're going to want a sort-actions step, where we order the actions
+    // by priority and character speed and such (defend's always take effect first, etc)
+    // and THEN execute them.
+    let order = match field.turn_order.take() {
+        Some(order) => order,
+        None => TurnOrder::roll(field),
+    };
+    // Anyone in the middle of winding something up carries on
+    // with it, whatever else they got told to do.
+    let mut actions: Vec<Action> = actions.iter()
+        .cloned()
+        .filter(|a| charged_action(field, a.source()).is_none())
+        .collect();
+    actions.extend((0..field.chars.len()).filter_map(|c| charged_action(field, c)));
+    order_actions(&order, &mut actions);
+    // Sleepers don't get to pick anything, but they still lose their
+    // turn, same as when a conditional-turn-based battle skips them.
+    for (i, chr) in field.chars.iter().enumerate() {
+        if chr.is_alive() && chr.is_asleep() && !actions.iter().any(|a| a.source() == i) {
+            events.push(BattleEvent::Asleep(i));
+        }
+    }
+    for action in actions.iter() {
+        // If the battle is over, we stop where we are!
+        // Partially 'cause any remaining actions will be invalid.
+        // Reinforcements get a chance to jump in first, though.
+        call_reinforcements(field, events);
+        let status = battle_status(field);
+        if status != BattleStatus::Continuing {
+            return status;
+        }
+
+        run_action(field, action, events);
+    }
+
+    // Poison and time bombs might finish someone off, too.
+    set_off_delayed(field, events);
+    tick_statuses(field, events);
+
+    // Check again, juuuuust in case that last action finished
+    // something off.
+    call_reinforcements(field, events);
+    let status = battle_status(field);
+    if status != BattleStatus::Continuing {
+        return status;
+    }
+
+    tick_summons(field, events);
+    field.increment_round();
+    call_reinforcements(field, events);
+    BattleStatus::Continuing
+}
+
+#[test]
+fn three_way_victory() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Joe", Team::Player));
+    b.chars.push(Character::new("Guest", Team::Other(1)));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Drakee", Team::Other(2)));
+    b.relations.set(Team::Player, Team::Other(1), Stance::Allied);
+    assert_eq!(battle_status(&b), BattleStatus::Continuing);
+
+    b.get_mut(2).unwrap().take_damage(1_000_000);
+    assert_eq!(battle_status(&b), BattleStatus::Continuing);
+    // Party's gone but their guest is still kicking.
+    b.get_mut(0).unwrap().take_damage(1_000_000);
+    b.get_mut(3).unwrap().take_damage(1_000_000);
+    assert_eq!(battle_status(&b), BattleStatus::PlayerVictory);
+
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    b.get_mut(3).unwrap().revive(10);
+    assert_eq!(battle_status(&b), BattleStatus::OtherVictory(Team::Other(2)));
+}
+
+#[test]
+fn run_turn_finishes_battle() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Joe", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+
+    let mut events = Vec::new();
+    let mut actions = vec![Action::Defend(0)];
+    let status = run_turn(&mut b, &mut actions, &mut events);
+    assert_eq!(status, BattleStatus::PlayerVictory);
+    assert!(events.is_empty());
+    assert_eq!(b.round, 1);
+}
+
+#[test]
+fn healers_revive_their_friends() {
+    use spell::Spell;
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Healer", Team::Monster));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.get_mut(1).unwrap().spells.push(Spell::Zing);
+    b.get_mut(2).unwrap().take_damage(1_000_000);
+
+    let mut actions = Vec::new();
+    decide_monster_actions(&b, &mut actions);
+    assert_eq!(actions, vec![Action::Cast(1, Spell::Zing, Target::Single(2))]);
+
+    // Once the slime's back up it's a fair target again.
+    b.get_mut(2).unwrap().revive(50);
+    assert_eq!(battle_status(&b), BattleStatus::Continuing);
+    assert_eq!(Target::AllEnemies.resolve(&b, 0), vec![1, 2]);
+}
+
+#[test]
+fn reinforcements_arrive() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Rust Dragon", Team::Monster));
+    b.waves.push(Wave {
+        round: 3,
+        chars: vec![Character::new("Wyvern", Team::Monster)],
+    });
+    b.waves.push(Wave {
+        round: 10,
+        chars: vec![Character::new("Slime", Team::Monster)],
+    });
+    let mut events = Vec::new();
+
+    // Round 3 comes around and the wyvern shows up.
+    run_turn(&mut b, &mut [Action::Defend(0)], &mut events);
+    assert_eq!(events, vec![BattleEvent::Defended(0)]);
+    run_turn(&mut b, &mut [Action::Defend(0)], &mut events);
+    assert_eq!(events, vec![BattleEvent::Defended(0), BattleEvent::Defended(0),
+                            BattleEvent::Appeared(2)]);
+
+    // Killing everyone off brings in the next wave early,
+    // instead of ending the fight.
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    b.get_mut(2).unwrap().take_damage(1_000_000);
+    events.clear();
+    let status = run_turn(&mut b, &mut [Action::Defend(0)], &mut events);
+    assert_eq!(status, BattleStatus::Continuing);
+    assert_eq!(events[0], BattleEvent::Appeared(3));
+    assert!(b.waves.is_empty());
+}
+
+#[test]
+fn summons_go_home() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Rex", Team::Player));
+    b.chars.push(Character::new("Great Sabrecat", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.get_mut(1).unwrap().lifetime = Some(2);
+
+    // The cat fights on its own.
+    let mut actions = Vec::new();
+    decide_monster_actions(&b, &mut actions);
+    assert!(actions.iter().any(|a| a.source() == 1));
+
+    let mut events = Vec::new();
+    tick_summons(&mut b, &mut events);
+    assert!(events.is_empty());
+    tick_summons(&mut b, &mut events);
+    assert_eq!(events, vec![BattleEvent::Departed(1)]);
+    assert!(!b.get(1).unwrap().is_alive());
+    assert!(!b.get_mut(1).unwrap().revive(100));
+}
+
+#[test]
+fn previewed_order_sticks() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    let order = preview_turn_order(&mut b);
+    // Both defend, so they go in turn order.
+    let mut events = Vec::new();
+    run_turn(&mut b, &mut [Action::Defend(0), Action::Defend(1)], &mut events);
+    let defended: Vec<BattleEvent> = order.order()
+        .iter()
+        .map(|&c| BattleEvent::Defended(c))
+        .collect();
+    assert_eq!(events, defended);
+    // And it's used up after the round.
+    assert!(b.turn_order.is_none());
+}
+
+#[test]
+fn big_moves_take_a_round() {
+    use skill::Skill;
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new_with_stats("Ragnar", Team::Player, 100, 0, 10, 10, 10, 10));
+    b.chars.push(Character::new("Rust Dragon", Team::Monster));
+    b.get_mut(1).unwrap().skills.push(Skill::FireBreath);
+    let breath = Action::UseSkill(1, Skill::FireBreath, Target::AllEnemies);
+    let mut events = Vec::new();
+
+    run_turn(&mut b, &mut [breath, Action::Defend(0)], &mut events);
+    assert!(events.contains(&BattleEvent::WindingUp(1, Skill::FireBreath)));
+    assert!(!events.contains(&BattleEvent::UsedSkill(1, Skill::FireBreath)));
+    // Busy, so no new orders.
+    assert!(!b.get(1).unwrap().can_act());
+    let mut actions = Vec::new();
+    decide_monster_actions(&b, &mut actions);
+    assert!(actions.is_empty());
+
+    // Next round it goes off without being told again.
+    events.clear();
+    run_turn(&mut b, &mut [Action::Defend(0)], &mut events);
+    assert!(events.contains(&BattleEvent::UsedSkill(1, Skill::FireBreath)));
+    assert!(b.get(0).unwrap().hp.val < 100);
+    assert!(b.get(1).unwrap().can_act());
+}
+
+#[test]
+fn interruptions() {
+    use skill::Skill;
+    use spell::Spell;
+    use element::Element;
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Brey", Team::Player));
+    b.chars.push(Character::new_with_stats("Rust Dragon", Team::Monster, 100, 0, 10, 10, 10, 10));
+    b.get_mut(0).unwrap().spells.push(Spell::Snooze);
+    let breath = Action::UseSkill(1, Skill::FireBreath, Target::AllEnemies);
+    let mut events = Vec::new();
+
+    // A little scratch doesn't bother it, but a big hit does.
+    run_action(&mut b, &breath, &mut events);
+    deal_damage(&mut b, 1, 5, Element::Physical, &mut events);
+    assert!(b.get(1).unwrap().charging.is_some());
+    deal_damage(&mut b, 1, 30, Element::Physical, &mut events);
+    assert!(b.get(1).unwrap().charging.is_none());
+    assert_eq!(*events.last().unwrap(), BattleEvent::Interrupted(1));
+
+    // So does being put to sleep, which also stops it doing anything
+    // until it wears off or someone hits it.
+    run_action(&mut b, &breath, &mut events);
+    events.clear();
+    run_action(&mut b, &Action::Cast(0, Spell::Snooze, Target::Single(1)), &mut events);
+    assert_eq!(*events.last().unwrap(), BattleEvent::Interrupted(1));
+    events.clear();
+    run_action(&mut b, &Action::Attack(1, Target::Single(0)), &mut events);
+    assert_eq!(events, vec![BattleEvent::Asleep(1)]);
+    deal_damage(&mut b, 1, 1, Element::Physical, &mut events);
+    assert_eq!(*events.last().unwrap(), BattleEvent::WokeUp(1));
+    assert!(b.get(1).unwrap().can_act());
+}
+
+#[test]
+fn time_bombs_go_off_later() {
+    use skill::Skill;
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new_with_stats("Ragnar", Team::Player, 100, 0, 10, 10, 10, 10));
+    b.chars.push(Character::new("Bomb Crag", Team::Monster));
+    let mut events = Vec::new();
+    run_turn(&mut b,
+             &mut [Action::UseSkill(1, Skill::TimeBomb, Target::AllEnemies)],
+             &mut events);
+    assert_eq!(events, vec![BattleEvent::UsedSkill(1, Skill::TimeBomb)]);
+    // Even blowing up the bomber doesn't stop it.
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    b.chars.push(Character::new("Bomb Crag", Team::Monster));
+    events.clear();
+    run_turn(&mut b, &mut [Action::Defend(0)], &mut events);
+    assert!(!events.contains(&BattleEvent::WentOff(1, Skill::TimeBomb)));
+    run_turn(&mut b, &mut [Action::Defend(0)], &mut events);
+    assert!(events.contains(&BattleEvent::WentOff(1, Skill::TimeBomb)));
+    assert!(b.delayed.is_empty());
+}
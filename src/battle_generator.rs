@@ -2,10 +2,106 @@
 
 use super::character::*;
 use super::battlefield::*;
+use super::element::*;
+use super::spell::*;
+use super::item::*;
+use super::reaction::*;
+use super::skill::*;
+use super::loot::Drop;
+use super::bounded_number::Keep;
 
+use super::dice;
 use rand;
 
 
+/// Makes a monster whose attacks are of the given element,
+/// with the given weaknesses/resistances/etc.
+fn elemental(name: &str, attack: Element, affinities: &[(Element, Affinity)]) -> Character {
+    let mut c = Character::new(name, Team::Monster);
+    c.attack_element = attack;
+    for &(element, affinity) in affinities {
+        c.set_affinity(element, affinity);
+    }
+    c
+}
+
+/// Gives someone a list of who they can call for help.
+fn calls(mut c: Character, summons: &[&str]) -> Character {
+    c.summons = summons.iter().map(|&s| String::from(s)).collect();
+    c
+}
+
+/// Gives someone reactions and passive traits.
+fn traits(mut c: Character, reactions: &[Reaction], passives: &[Passive]) -> Character {
+    c.reactions.extend_from_slice(reactions);
+    c.passives.extend_from_slice(passives);
+    c
+}
+
+/// Teaches someone some skills.
+fn skilled(mut c: Character, skills: &[Skill]) -> Character {
+    c.skills.extend_from_slice(skills);
+    c
+}
+
+/// Makes someone who knows some spells.
+fn caster(name: &str, team: Team, spells: &[Spell]) -> Character {
+    let mut c = Character::new(name, team);
+    c.spells.extend_from_slice(spells);
+    c
+}
+
+
+/// What beating each monster is worth: experience, gold,
+/// and whatever they might drop.
+fn bounty(name: &str) -> (u32, u32, Vec<Drop>) {
+    let medal = |chance| Drop::rare(Item::SmallMedal, chance);
+    let leaf = |chance| Drop::common(Item::WorldTreeLeaf, chance);
+    match name {
+        "Slime" => (1, 2, vec![]),
+        "Slime Knight" => (5, 8, vec![]),
+        "King Slime" => (40, 50, vec![leaf(10)]),
+        "Magician" => (6, 8, vec![]),
+        "Healer" => (4, 6, vec![leaf(5)]),
+        "Babble" => (5, 5, vec![]),
+        "Army Crab" => (8, 9, vec![]),
+        "Gas Cloud" => (10, 12, vec![]),
+        "Demon Toadstool" => (6, 7, vec![]),
+        "Rogue Knight" => (12, 20, vec![]),
+        "Mimic" => (30, 60, vec![medal(10)]),
+        "Bomb Crag" => (25, 30, vec![]),
+        "Wyvern" => (15, 18, vec![]),
+        "Armor Scorpion" => (12, 14, vec![]),
+        "Blazeghost" => (14, 16, vec![]),
+        "Metal Slime" => (100, 6, vec![medal(5)]),
+        "Baby Panther" => (7, 6, vec![]),
+        "Clay Doll" => (8, 10, vec![]),
+        "Cactus Ball" => (6, 8, vec![]),
+        "Drakee" => (2, 3, vec![]),
+        "Minidemon" => (20, 25, vec![]),
+        "Hork" => (9, 10, vec![]),
+        "Demon Pot" => (18, 40, vec![medal(5)]),
+        "Dancing Jewel" => (20, 100, vec![medal(8)]),
+        "Lipps" => (7, 7, vec![]),
+        "Onion" => (3, 4, vec![]),
+        "Rust Dragon" => (200, 300, vec![medal(50), leaf(50)]),
+        "Bandersnatch" => (30, 35, vec![]),
+        _ => (1, 1, vec![]),
+    }
+}
+
+fn with_bounties(mobs: Vec<Character>) -> Vec<Character> {
+    mobs.into_iter()
+        .map(|mut c| {
+            let (xp, gold, drops) = bounty(&c.name);
+            c.xp = xp;
+            c.gold = gold;
+            c.drops = drops;
+            c
+        })
+        .collect()
+}
+
 // lazy_static is awesome.
 // It basically defines a "static variable" that can contain function calls,
 // which is initialized at runtime upon the first time it is dereferenced.
@@ -13,70 +109,113 @@ use rand;
 // nightly.
 lazy_static! {
 
-    static ref PLAYERS: Vec<Character> = {
-        let mut c = Vec::new();
-        c.push(Character::new("Ragnar", Team::Player));
-        c.push(Character::new("Alena", Team::Player));
-        c.push(Character::new("Cristo", Team::Player));
-        c.push(Character::new("Brey", Team::Player));
-        c.push(Character::new("Taloon", Team::Player));
-        c.push(Character::new("Mara", Team::Player));
-        c.push(Character::new("Nara", Team::Player));
-        c.push(Character::new("Orin", Team::Player));
-        c.push(Character::new("Katta", Team::Player));
-
-        c.push(Character::new("Papas", Team::Player));
-        c.push(Character::new("Bianca", Team::Player));
-        c.push(Character::new("Flora", Team::Player));
-        c.push(Character::new("Tabitha", Team::Player));
-        c.push(Character::new("Rex", Team::Player));
-        c.push(Character::new("Sancho", Team::Player));
-        c.push(Character::new("Rusty", Team::Player));
-        
-        c.push(Character::new("Hassan", Team::Player));
-        c.push(Character::new("Muriel", Team::Player));
-        c.push(Character::new("Barbara", Team::Player));
-        c.push(Character::new("Chamoro", Team::Player));
-        c.push(Character::new("Amos", Team::Player));
-        c.push(Character::new("Terry", Team::Player));
-        c
-    };
+    static ref PLAYERS: Vec<Character> = vec![
+        skilled(traits(Character::new("Ragnar", Team::Player), &[Reaction::Cover], &[]), &[Skill::Sweep]),
+        skilled(traits(Character::new("Alena", Team::Player), &[Reaction::Counter], &[]),
+                &[Skill::DoubleSlash]),
+        caster("Cristo", Team::Player, &[Spell::Zing, Spell::Kazing]),
+        caster("Brey", Team::Player, &[Spell::Acceleratle, Spell::Deceleratle, Spell::Snooze]),
+        skilled(Character::new("Taloon", Team::Player), &[Skill::Steal]),
+        caster("Mara", Team::Player, &[Spell::Deceleratle, Spell::Kaboom]),
+        Character::new("Nara", Team::Player),
+        Character::new("Orin", Team::Player),
+        Character::new("Katta", Team::Player),
 
-    static ref MOBS: Vec<Character> = {
-        let mut c = Vec::new();
-        c.push(Character::new("Slime", Team::Monster));
-        c.push(Character::new("Slime Knight", Team::Monster));
-        c.push(Character::new("King Slime", Team::Monster));
-        c.push(Character::new("Magician", Team::Monster));
-        c.push(Character::new("Healer", Team::Monster));
-        c.push(Character::new("Babble", Team::Monster));
-        c.push(Character::new("Army Crab", Team::Monster));
-        c.push(Character::new("Gas Cloud", Team::Monster));
-        c.push(Character::new("Demon Toadstool", Team::Monster));
-        c.push(Character::new("Rogue Knight", Team::Monster));
-        c.push(Character::new("Mimic", Team::Monster));
-        c.push(Character::new("Bomb Crag", Team::Monster));
-        c.push(Character::new("Wyvern", Team::Monster));
-        c.push(Character::new("Armor Scorpion", Team::Monster));
-        c.push(Character::new("Blazeghost", Team::Monster));
-        c.push(Character::new("Metal Slime", Team::Monster));
-        c.push(Character::new("Baby Panther", Team::Monster));
-        c.push(Character::new("Clay Doll", Team::Monster));
-        c.push(Character::new("Cactus Ball", Team::Monster));
-        c.push(Character::new("Drakee", Team::Monster));
-        c.push(Character::new("Minidemon", Team::Monster));
-        c.push(Character::new("Hork", Team::Monster));
-        c.push(Character::new("Demon Pot", Team::Monster));
-        c.push(Character::new("Dancing Jewel", Team::Monster));
-        c.push(Character::new("Lipps", Team::Monster));
-        c.push(Character::new("Onion", Team::Monster));
-        
-
-        // They should drop small medals after dying :-3
-        c.push(Character::new("Rust Dragon", Team::Monster));
-        c.push(Character::new("Bandersnatch", Team::Monster));
-        c
-    };
+        Character::new("Papas", Team::Player),
+        Character::new("Bianca", Team::Player),
+        caster("Flora", Team::Player, &[Spell::Zing]),
+        calls(Character::new("Tabitha", Team::Player), &["Healie"]),
+        skilled(calls(Character::new("Rex", Team::Player), &["Great Sabrecat"]), &[Skill::Whistle]),
+        Character::new("Sancho", Team::Player),
+        Character::new("Rusty", Team::Player),
+
+        traits(Character::new("Hassan", Team::Player), &[Reaction::Counter], &[]),
+        Character::new("Muriel", Team::Player),
+        Character::new("Barbara", Team::Player),
+        caster("Chamoro", Team::Player, &[Spell::Zing]),
+        Character::new("Amos", Team::Player),
+        skilled(Character::new("Terry", Team::Player), &[Skill::Gigaslash, Skill::DoubleSlash]),
+    ];
+
+    static ref MOBS: Vec<Character> = with_bounties(vec![
+        calls(Character::new("Slime", Team::Monster), &["Slime"]),
+        Character::new("Slime Knight", Team::Monster),
+        calls(Character::new("King Slime", Team::Monster), &["Slime", "Slime Knight"]),
+        caster("Magician", Team::Monster, &[Spell::Deceleratle, Spell::Snooze]),
+        caster("Healer", Team::Monster, &[Spell::Zing]),
+        traits(Character::new("Babble", Team::Monster), &[], &[Passive::PoisonTouch]),
+        Character::new("Army Crab", Team::Monster),
+        elemental("Gas Cloud", Element::Dark, &[
+            (Element::Physical, Affinity::Resist),
+            (Element::Wind, Affinity::Weak),
+        ]),
+        Character::new("Demon Toadstool", Team::Monster),
+        skilled(Character::new("Rogue Knight", Team::Monster), &[Skill::DoubleSlash]),
+        traits(Character::new("Mimic", Team::Monster), &[Reaction::Counter], &[Passive::FirstStrike]),
+        skilled(elemental("Bomb Crag", Element::Fire, &[
+            (Element::Fire, Affinity::Immune),
+            (Element::Ice, Affinity::Weak),
+        ]), &[Skill::TimeBomb]),
+        elemental("Wyvern", Element::Wind, &[
+            (Element::Wind, Affinity::Resist),
+            (Element::Lightning, Affinity::Weak),
+        ]),
+        skilled(Character::new("Armor Scorpion", Team::Monster), &[Skill::Sweep]),
+        elemental("Blazeghost", Element::Fire, &[
+            (Element::Fire, Affinity::Absorb),
+            (Element::Ice, Affinity::Weak),
+            (Element::Holy, Affinity::Weak),
+            (Element::Dark, Affinity::Immune),
+        ]),
+        traits(elemental("Metal Slime", Element::Physical, &[
+            (Element::Fire, Affinity::Immune),
+            (Element::Ice, Affinity::Immune),
+            (Element::Lightning, Affinity::Immune),
+            (Element::Wind, Affinity::Immune),
+            (Element::Holy, Affinity::Immune),
+            (Element::Dark, Affinity::Immune),
+        ]), &[], &[Passive::MagicImmunity]),
+        traits(Character::new("Baby Panther", Team::Monster), &[], &[Passive::FirstStrike]),
+        Character::new("Clay Doll", Team::Monster),
+        Character::new("Cactus Ball", Team::Monster),
+        calls(Character::new("Drakee", Team::Monster), &["Drakee"]),
+        elemental("Minidemon", Element::Dark, &[
+            (Element::Dark, Affinity::Resist),
+            (Element::Holy, Affinity::Weak),
+        ]),
+        traits(Character::new("Hork", Team::Monster), &[], &[Passive::Regen(2)]),
+        Character::new("Demon Pot", Team::Monster),
+        traits(elemental("Dancing Jewel", Element::Physical, &[
+            (Element::Lightning, Affinity::Absorb),
+        ]), &[Reaction::Reflect], &[]),
+        Character::new("Lipps", Team::Monster),
+        Character::new("Onion", Team::Monster),
+
+        skilled(elemental("Rust Dragon", Element::Fire, &[
+            (Element::Fire, Affinity::Absorb),
+            (Element::Ice, Affinity::Resist),
+            (Element::Lightning, Affinity::Weak),
+            (Element::Holy, Affinity::Weak),
+        ]), &[Skill::FireBreath]),
+        skilled(Character::new("Bandersnatch", Team::Monster), &[Skill::FireBreath]),
+    ]);
+
+    // Friends the party can call on, who don't fight in
+    // the party proper.
+    static ref ALLIES: Vec<Character> = vec![
+        Character::new("Great Sabrecat", Team::Player),
+        traits(caster("Healie", Team::Player, &[Spell::Zing]), &[], &[Passive::Regen(1)]),
+    ];
+}
+
+/// A fresh copy of anyone we know about, by name.
+/// This is who gets called in when someone calls for help.
+pub fn template(name: &str) -> Option<Character> {
+    MOBS.iter()
+        .chain(PLAYERS.iter())
+        .chain(ALLIES.iter())
+        .find(|c| c.name == name)
+        .cloned()
 }
     
 //const characters: [Character;1] = [
@@ -84,32 +223,190 @@ lazy_static! {
 //];
 
 fn select_players() -> Vec<&'static Character> {
-    let mut rng = rand::thread_rng();
-    let sample = rand::sample(&mut rng, (*PLAYERS).iter(), 4);
-    sample.clone()
+    dice::with(|rng| rand::seq::sample_iter(rng, (*PLAYERS).iter(), 4)).unwrap()
 }
 
 fn select_monsters() -> Vec<&'static Character> {
-    let mut rng = rand::thread_rng();
-    let sample = rand::sample(&mut rng, (*MOBS).iter(), 3);
-    sample.clone()
+    dice::with(|rng| rand::seq::sample_iter(rng, (*MOBS).iter(), 3)).unwrap()
+
+}
 
+/// A fresh copy of any old monster.
+pub fn random_monster() -> Character {
+    dice::choose(&MOBS).unwrap().clone()
+}
+
+/// Four random heroes, fresh from the tavern.
+pub fn random_party() -> Vec<Character> {
+    select_players().into_iter().cloned().collect()
 }
 
 pub fn generate() -> Battlefield {
+    let mut b = generate_for(random_party());
+    // Something to fall back on.
+    b.inventory_mut(Team::Player).add(Item::WorldTreeLeaf, 1);
+    b
+}
+
+/// A random fight for the given party.
+/// They're put first, so they keep the same `CharSpecifier`s
+/// as their place in the party.
+pub fn generate_for(party: Vec<Character>) -> Battlefield {
     let mut b = Battlefield::new();
     // We need to make copies of the Character
     // objects because they're going to get modified
     // in the course of the battle.
-    let player_refs = select_players();
-    let mut players: Vec<_> = player_refs.iter()
-        .map(|p| (*p).clone())
-        .collect();
     let monster_refs = select_monsters();
     let monsters: Vec<_> = monster_refs.iter()
         .map(|p| (*p).clone())
         .collect();
-    players.extend(monsters);
-    b.chars = players;
+    b.chars = party;
+    b.chars.extend(monsters);
+    // And something for thieves to go after.
+    if dice::random::<u32>().is_multiple_of(3) {
+        b.inventory_mut(Team::Monster).add(Item::WorldTreeLeaf, 1);
+    }
+    b
+}
+
+/// A fight for the given party against the named monsters,
+/// like the ones that jump out at you on the map.
+pub fn fight_with(party: Vec<Character>, monsters: &[String]) -> Battlefield {
+    let mut b = Battlefield::new();
+    b.chars = party;
+    b.chars.extend(monsters.iter().filter_map(|n| template(n)));
     b
 }
+
+/// A quick battle made to order: the named heroes and monsters,
+/// topped up with random ones until there are `party_size` heroes
+/// and `monster_count` monsters.  Nobody's in the party twice, but
+/// there can be as many slimes as you like.
+pub fn custom(heroes: &[String],
+              party_size: usize,
+              monsters: &[String],
+              monster_count: usize)
+              -> Result<Battlefield, String> {
+    let named = |name: &String, team: Team| match template(name) {
+        Some(ref chr) if chr.team == team => Ok(chr.clone()),
+        Some(_) => Err(format!("{} isn't on that side", name)),
+        None => Err(format!("nobody called {}", name)),
+    };
+    let mut b = Battlefield::new();
+    for name in heroes {
+        if b.chars.iter().any(|chr| chr.name == *name) {
+            return Err(format!("{} can't be in the party twice", name));
+        }
+        b.chars.push(named(name, Team::Player)?);
+    }
+    let spare: Vec<&Character> = PLAYERS.iter()
+        .filter(|p| !heroes.contains(&p.name))
+        .collect();
+    let wanted = party_size.saturating_sub(heroes.len());
+    if wanted > spare.len() {
+        return Err(format!("there are only {} heroes", PLAYERS.len()));
+    }
+    let extra = dice::with(|rng| rand::seq::sample_iter(rng, spare, wanted)).unwrap();
+    b.chars.extend(extra.into_iter().cloned());
+    for name in monsters {
+        b.chars.push(named(name, Team::Monster)?);
+    }
+    for _ in monsters.len()..monster_count {
+        b.chars.push(random_monster());
+    }
+    b.inventory_mut(Team::Player).add(Item::WorldTreeLeaf, 1);
+    Ok(b)
+}
+
+/// How tough the monsters are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    /// Percent of their usual HP and attack.
+    pub fn percent(&self) -> u32 {
+        match *self {
+            Difficulty::Easy => 75,
+            Difficulty::Normal => 100,
+            Difficulty::Hard => 150,
+        }
+    }
+
+    /// Toughens up, or softens up, everyone not on the party's team,
+    /// including anyone waiting to jump in later.
+    pub fn apply(&self, field: &mut Battlefield) {
+        let percent = self.percent();
+        let waiting = field.waves.iter_mut().flat_map(|w| w.chars.iter_mut());
+        for chr in field.chars.iter_mut().chain(waiting).filter(|c| c.team != Team::Player) {
+            let max = (chr.hp.max * percent / 100).max(1);
+            chr.hp.set_max(max, Keep::Fraction);
+            chr.atk = (chr.atk * percent / 100).max(1);
+        }
+    }
+}
+
+/// A fight with the Rust Dragon, who has friends waiting to jump
+/// in once things start going badly, and more on the way regardless.
+pub fn boss_fight() -> Battlefield {
+    let mut b = boss_fight_for(random_party());
+    b.inventory_mut(Team::Player).add(Item::WorldTreeLeaf, 1);
+    b
+}
+
+/// The Rust Dragon fight, for the given party.
+pub fn boss_fight_for(party: Vec<Character>) -> Battlefield {
+    let mut b = Battlefield::new();
+    b.chars = party;
+    b.chars.push(template("Rust Dragon").unwrap());
+    let wave = |names: &[&str]| names.iter().map(|n| template(n).unwrap()).collect();
+    b.waves.push(Wave {
+        round: 4,
+        chars: wave(&["Wyvern", "Wyvern"]),
+    });
+    b.waves.push(Wave {
+        round: 8,
+        chars: wave(&["Blazeghost", "Bomb Crag", "Blazeghost"]),
+    });
+    b
+}
+
+#[test]
+fn made_to_order() {
+    let names = |n: &[&str]| n.iter().map(|&s| String::from(s)).collect::<Vec<_>>();
+    let mut b = custom(&names(&["Alena"]), 3, &names(&["Slime", "Slime"]), 4).unwrap();
+    assert_eq!(b.players().count(), 3);
+    assert_eq!(b.monsters().count(), 4);
+    assert_eq!(b.get(0).unwrap().name, "Alena");
+    assert!(b.players().skip(1).all(|chr| chr.name != "Alena"));
+    assert_eq!(b.get(4).unwrap().name, "Slime");
+    b.chars[5].hp.set(4);
+    Difficulty::Hard.apply(&mut b);
+    assert_eq!(b.get(0).unwrap().hp.max, 10);
+    assert_eq!(b.get(4).unwrap().hp.max, 15);
+    assert_eq!(b.get(4).unwrap().hp.val, 15);
+    assert_eq!(b.get(5).unwrap().hp.val, 6);
+    assert!(custom(&names(&["Slime"]), 1, &[], 1).is_err());
+    assert!(custom(&names(&["Alena", "Alena"]), 2, &[], 1).is_err());
+    assert!(custom(&[], 100, &[], 1).is_err());
+}
+
+#[test]
+fn boss_fight_has_waves() {
+    let b = boss_fight();
+    assert_eq!(b.monsters().count(), 1);
+    assert_eq!(b.waves.len(), 2);
+    assert!(b.waves.iter().all(|w| w.chars.iter().all(|c| c.team == Team::Monster)));
+}
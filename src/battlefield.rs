@@ -1,14 +1,107 @@
-use std;
+use std::collections::HashMap;
 use std::fmt;
 
 
 use super::character::*;
+use super::item::*;
+use super::event::*;
+use super::skill::Skill;
+use super::target::Target;
+use super::initiative::TurnOrder;
+
+/// How two teams feel about each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stance {
+    /// Fighting on the same side.
+    Allied,
+    /// Not fighting each other, but not helping either.
+    Neutral,
+    /// Trying to kill each other.
+    Hostile,
+}
+
+/// The alliance/hostility matrix between teams.
+/// A team is always allied with itself, and anything that
+/// hasn't been set otherwise is hostile, so the usual players vs.
+/// monsters fight needs no setup at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Relations {
+    stances: HashMap<(Team, Team), Stance>,
+}
+
+impl Relations {
+    pub fn new() -> Relations {
+        Relations::default()
+    }
+
+    /// Sets how two teams feel about each other.
+    /// It always goes both ways.
+    pub fn set(&mut self, a: Team, b: Team, stance: Stance) {
+        self.stances.insert((a, b), stance);
+        self.stances.insert((b, a), stance);
+    }
+
+    pub fn stance(&self, a: Team, b: Team) -> Stance {
+        if a == b {
+            Stance::Allied
+        } else {
+            self.stances.get(&(a, b)).cloned().unwrap_or(Stance::Hostile)
+        }
+    }
+
+    pub fn allied(&self, a: Team, b: Team) -> bool {
+        self.stance(a, b) == Stance::Allied
+    }
+
+    pub fn hostile(&self, a: Team, b: Team) -> bool {
+        self.stance(a, b) == Stance::Hostile
+    }
+}
+
+/// How many characters a side can have standing at once,
+/// unless the battlefield says otherwise.
+pub const DEFAULT_CAPACITY: usize = 8;
+
+/// A batch of reinforcements waiting in the wings.
+/// They show up at the start of the given round, or as soon as
+/// their side has nobody left standing, whichever comes first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wave {
+    pub round: u32,
+    pub chars: Vec<Character>,
+}
+
+/// Something that was set in motion earlier and goes off
+/// at the end of the given round, like a time bomb.
+/// It goes off whether or not whoever started it is still around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delayed {
+    pub round: u32,
+    pub from: CharSpecifier,
+    pub skill: Skill,
+    pub target: Target,
+}
 
 /// The central structure containing a battle's state.
 #[derive(Debug, Clone)]
 pub struct Battlefield {
     pub chars: Vec<Character>,
     pub round: u32,
+    pub relations: Relations,
+    /// Each side's shared bag of items.
+    pub inventories: HashMap<Team, Inventory>,
+    /// Most characters any one team can have standing at once.
+    pub capacity: usize,
+    /// Reinforcements that haven't shown up yet.
+    pub waves: Vec<Wave>,
+    /// Effects waiting to go off.
+    pub delayed: Vec<Delayed>,
+    /// Whether to roll the turn order before commands get entered,
+    /// so the player can see who's going when.  Otherwise it gets
+    /// rolled once everyone's picked, and it's a surprise.
+    pub preview_turn_order: bool,
+    /// This round's turn order, if it's been rolled already.
+    pub turn_order: Option<TurnOrder>,
 }
 
 /// A structure that specifies a specific character in a Battlefield.
@@ -18,126 +111,195 @@ impl fmt::Display for Battlefield {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 
         // Oh man :#? is great
-        // try!(writeln!(f, "{:#?}", self));
+        // writeln!(f, "{:#?}", self)?;
 
-        try!(writeln!(f, "Round {}", self.round));
-        try!(writeln!(f, "Characters:"));
-        for chr in self.players() {
-            try!(writeln!(f, "  {}", chr));
-        }
-        try!(writeln!(f, "Monsters:"));
-        for mob in self.monsters() {
-            try!(writeln!(f, "  {}", mob));
+        writeln!(f, "Round {}", self.round)?;
+        writeln!(f, "Characters:")?;
+        self.fmt_formation(f, Team::Player)?;
+        writeln!(f, "Monsters:")?;
+        self.fmt_formation(f, Team::Monster)?;
+        for team in self.teams() {
+            if let Team::Other(n) = team {
+                writeln!(f, "Team {}:", n)?;
+                self.fmt_formation(f, team)?;
+            }
         }
-        write!(f, "")
+        Ok(())
     }
 }
 
 
+impl Default for Battlefield {
+    fn default() -> Battlefield {
+        Battlefield::new()
+    }
+}
+
 impl Battlefield {
     pub fn new() -> Battlefield {
         Battlefield {
             chars: vec![],
             round: 1,
+            relations: Relations::new(),
+            inventories: HashMap::new(),
+            capacity: DEFAULT_CAPACITY,
+            waves: Vec::new(),
+            delayed: Vec::new(),
+            preview_turn_order: false,
+            turn_order: None,
+        }
+    }
+
+    /// Writes out one side's front and back rows.
+    /// The back row heading only shows up if anyone's in it.
+    fn fmt_formation(&self, f: &mut fmt::Formatter, team: Team) -> fmt::Result {
+        let has_back_row = self.get_team(team).any(|chr| chr.row == Row::Back);
+        if !has_back_row {
+            for chr in self.get_team(team) {
+                writeln!(f, "  {}", chr)?;
+            }
+            return Ok(());
         }
+        for &(row, heading) in &[(Row::Front, "Front row:"), (Row::Back, "Back row:")] {
+            writeln!(f, "  {}", heading)?;
+            for chr in self.get_team(team).filter(|chr| chr.row == row) {
+                writeln!(f, "    {}", chr)?;
+            }
+        }
+        Ok(())
     }
+
     pub fn increment_round(&mut self) {
         self.round += 1
     }
 
-    pub fn get<'a>(&'a self, c: CharSpecifier) -> Option<&'a Character> {
-        self.chars.get(c as usize)
+    pub fn get(&self, c: CharSpecifier) -> Option<&Character> {
+        self.chars.get(c)
+    }
+
+    pub fn get_mut(&mut self, c: CharSpecifier) -> Option<&mut Character> {
+        self.chars.get_mut(c)
     }
 
-    pub fn get_mut<'a>(&'a mut self, c: CharSpecifier) -> Option<&'a mut Character> {
-        self.chars.get_mut(c as usize)
+    /// Whether the team has room for anyone else to join the fight.
+    pub fn has_room(&self, team: Team) -> bool {
+        self.get_team(team).filter(|chr| chr.is_alive()).count() < self.capacity
     }
 
-    // This is insane.
-    // Never touch it.  The type system will eat you alive.
-    // You can't define types from closures.  That's the first hiccup.
-    // The type of Filter is crazy.  That's the second hiccup.
-    // I still have no idea why this needs a &&Character instead of just a &Character.
-    // Third, the Filter borrows the thing it's filtering, which makes the lifetimes squirrelly.
-    // impl Trait is currently in nightly, and once that's useable we'll be able to make this:
-    // fn players<'a>(&'a self) ->
-    //    impl Iterator<Item=&'a Character> {
-    //        self.chars.iter().filter(|chr| chr.team == Team::Player)
-    //  }
-    pub fn players<'a>
-        (&'a self)
-         -> std::iter::Filter<std::slice::Iter<'a, Character>, fn(&&Character) -> bool> {
+    /// Brings a new character into the fight partway through.
+    /// They always go on the end of `chars`, so everyone else's
+    /// `CharSpecifier` stays the same.
+    /// Returns None, and doesn't add them, if their side is full up.
+    pub fn spawn(&mut self,
+                 chr: Character,
+                 events: &mut Vec<BattleEvent>)
+                 -> Option<CharSpecifier> {
+        if !self.has_room(chr.team) {
+            return None;
+        }
+        self.chars.push(chr);
+        let c = self.chars.len() - 1;
+        events.push(BattleEvent::Appeared(c));
+        Some(c)
+    }
+
+    /// The team's items, if they've ever had any.
+    pub fn inventory(&self, team: Team) -> Option<&Inventory> {
+        self.inventories.get(&team)
+    }
+
+    /// The team's items, handing them an empty bag if they
+    /// didn't have one.
+    pub fn inventory_mut(&mut self, team: Team) -> &mut Inventory {
+        self.inventories.entry(team).or_default()
+    }
+
+    // This used to be insane, back before impl Trait existed and
+    // we had to spell out the whole Filter<...> type by hand.
+    pub fn players(&self) -> impl Iterator<Item = &Character> + '_ {
         self.get_team(Team::Player)
     }
 
-    pub fn monsters<'a>
-        (&'a self)
-         -> std::iter::Filter<std::slice::Iter<'a, Character>, fn(&&Character) -> bool> {
+    pub fn monsters(&self) -> impl Iterator<Item = &Character> + '_ {
         self.get_team(Team::Monster)
     }
 
-    pub fn get_team<'a>
-        (&'a self,
-         team: Team)
-         -> std::iter::Filter<std::slice::Iter<'a, Character>, fn(&&Character) -> bool> {
-        // Booooo returning the results of filter() is dumb
-        // 'cause you can't specify types of closures.
-        // Though apparently there's a feature in nightly
-        // as of August 2016 that allows you to specify a
-        // trait return value rather than a specific type
-        // And you can't use instance methods as if they
-        // were class methods, either.
-
-        fn is_player(p: &&Character) -> bool {
-            p.team == Team::Player
-        }
+    pub fn get_team(&self, team: Team) -> impl Iterator<Item = &Character> + '_ {
+        self.chars.iter().filter(move |chr| chr.team == team)
+    }
 
-        fn is_monster(p: &&Character) -> bool {
-            p.team == Team::Monster
-        }
-        match team {
-            Team::Player => self.chars.iter().filter(is_player),
-            Team::Monster => self.chars.iter().filter(is_monster),
+    pub fn get_team_enumerate(&self,
+                              team: Team)
+                              -> impl Iterator<Item = (CharSpecifier, &Character)> + '_ {
+        self.chars.iter().enumerate().filter(move |&(_, chr)| chr.team == team)
+    }
+
+    /// Every team that has anyone on the field, in the order
+    /// they first show up.
+    pub fn teams(&self) -> Vec<Team> {
+        let mut teams = Vec::new();
+        for chr in &self.chars {
+            if !teams.contains(&chr.team) {
+                teams.push(chr.team);
+            }
         }
+        teams
+    }
+
+    /// Everyone hostile to the given team, alive or not.
+    pub fn get_opponents(&self, team: Team) -> impl Iterator<Item = &Character> + '_ {
+        self.chars.iter().filter(move |chr| self.relations.hostile(team, chr.team))
     }
 
-    pub fn get_team_enumerate<'a>(&'a self, team: Team) ->
-        std::iter::Filter<std::iter::Enumerate<std::slice::Iter<'a, Character>>,
-                          fn(&(usize, &Character)) -> bool> {
-        // Booooo returning the results of filter() is dumb
-        // 'cause you can't specify types of closures.
-        // Though apparently there's a feature in nightly
-        // as of August 2016 that allows you to specify a
-        // trait return value rather than a specific type
-        // And you can't use instance methods as if they
-        // were class methods, either.
+    pub fn get_opponents_enumerate(&self,
+                                   team: Team)
+                                   -> impl Iterator<Item = (CharSpecifier, &Character)> + '_ {
+        self.chars
+            .iter()
+            .enumerate()
+            .filter(move |&(_, chr)| self.relations.hostile(team, chr.team))
+    }
 
-        fn is_player(p: &(usize, &Character)) -> bool {
-            p.1.team == Team::Player
+    /// Whether two characters are out for each other's blood.
+    pub fn hostile(&self, a: CharSpecifier, b: CharSpecifier) -> bool {
+        match (self.get(a), self.get(b)) {
+            (Some(x), Some(y)) => self.relations.hostile(x.team, y.team),
+            _ => false,
         }
+    }
 
-        fn is_monster(p: &(usize, &Character)) -> bool {
-            p.1.team == Team::Monster
-        }
-        match team {
-            Team::Player => self.chars.iter().enumerate().filter(is_player),
-            Team::Monster => self.chars.iter().enumerate().filter(is_monster),
+    /// Whether a character can be reached with a melee attack.
+    /// The back row is out of reach as long as anyone on their
+    /// team is still standing in the front row.
+    pub fn in_reach(&self, c: CharSpecifier) -> bool {
+        match self.get(c) {
+            Some(chr) if chr.is_alive() => {
+                chr.row == Row::Front ||
+                !self.get_team(chr.team).any(|o| o.is_alive() && o.row == Row::Front)
+            }
+            _ => false,
         }
     }
 
+    /// Whether the team is fighting anyone at all, as opposed to
+    /// just being neutral bystanders.
+    pub fn is_combatant(&self, team: Team) -> bool {
+        self.teams().iter().any(|&t| self.relations.hostile(team, t))
+    }
 
-    pub fn get_opponents<'a>
-        (&'a self,
-         team: Team)
-         -> std::iter::Filter<std::slice::Iter<'a, Character>, fn(&&Character) -> bool> {
-        match team {
-            Team::Player => self.get_team(Team::Monster),
-            Team::Monster => self.get_team(Team::Player),
-        }
+    /// True when nobody left standing is hostile to anybody else
+    /// left standing, ie. only one alliance remains.
+    pub fn battle_over(&self) -> bool {
+        let living: Vec<Team> = self.chars
+            .iter()
+            .filter(|chr| chr.is_alive())
+            .map(|chr| chr.team)
+            .collect();
+        !living.iter().any(|&a| living.iter().any(|&b| self.relations.hostile(a, b)))
     }
 
     pub fn team_victorious(&self, team: Team) -> bool {
-        self.get_opponents(team).filter(|x| x.is_alive()).count() == 0
+        self.get_opponents(team).all(|x| !x.is_alive())
     }
 }
 
@@ -150,12 +312,73 @@ fn random_battlefield_methods() {
 
     {
         let c1 = b.get(1);
-        assert!(c1 == None);
+        assert!(c1.is_none());
     }
 
     b.chars.push(Character::new("Joe", Team::Player));
     {
         let c2 = b.get(0);
-        assert!(c2 != None);
+        assert!(c2.is_some());
     }
 }
+
+#[test]
+fn back_row_is_out_of_reach() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Healslime", Team::Monster));
+    b.get_mut(2).unwrap().row = Row::Back;
+    assert!(b.in_reach(1));
+    assert!(!b.in_reach(2));
+    assert!(format!("{}", b).contains("Back row:\n    Name: Healslime"));
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    assert!(!b.in_reach(1));
+    assert!(b.in_reach(2));
+}
+
+#[test]
+fn three_way_battles() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Healie", Team::Other(1)));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Drakee", Team::Other(2)));
+    b.chars.push(Character::new("Merchant", Team::Other(3)));
+    b.relations.set(Team::Player, Team::Other(1), Stance::Allied);
+    for &t in &[Team::Player, Team::Other(1), Team::Monster, Team::Other(2)] {
+        b.relations.set(t, Team::Other(3), Stance::Neutral);
+    }
+
+    assert!(!b.hostile(0, 1));
+    assert!(b.hostile(2, 3));
+    assert!(!b.hostile(0, 4));
+    assert!(!b.is_combatant(Team::Other(3)));
+    assert_eq!(b.get_opponents(Team::Player).count(), 2);
+    assert!(!b.battle_over());
+
+    // The slime and drakee can still go at each other
+    // even with the party gone.
+    b.get_mut(0).unwrap().take_damage(1_000_000);
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    assert!(!b.battle_over());
+    b.get_mut(3).unwrap().take_damage(1_000_000);
+    assert!(b.battle_over());
+    assert!(b.team_victorious(Team::Monster));
+}
+
+#[test]
+fn spawning_has_limits() {
+    let mut b = Battlefield::new();
+    b.capacity = 2;
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    let mut events = Vec::new();
+    assert_eq!(b.spawn(Character::new("Slime", Team::Monster), &mut events), Some(2));
+    assert_eq!(b.spawn(Character::new("Slime", Team::Monster), &mut events), None);
+    assert_eq!(events, vec![BattleEvent::Appeared(2)]);
+    // The dead don't take up room.
+    b.get_mut(1).unwrap().take_damage(1_000_000);
+    assert_eq!(b.spawn(Character::new("Drakee", Team::Monster), &mut events), Some(3));
+    assert_eq!(b.get(1).unwrap().name, "Slime");
+}
@@ -1,58 +1,230 @@
-use std::cmp;
 use std::fmt;
-use std::ops::{Add, Sub, AddAssign, SubAssign};
+use std::ops::{Add, Sub, Mul, Div, AddAssign, SubAssign, MulAssign, DivAssign};
 
 
+/// The kinds of number a `Bounded` can hold.
+pub trait Number: Copy + PartialOrd + fmt::Debug + fmt::Display {
+    fn zero() -> Self;
+    fn saturating_add(self, rhs: Self) -> Self;
+    fn saturating_sub(self, rhs: Self) -> Self;
+    fn to_f64(self) -> f64;
+    /// Rounds toward zero, and saturates at the type's limits.
+    fn from_f64(f: f64) -> Self;
+}
+
+macro_rules! number {
+    ($($t:ty)*) => {$(
+        impl Number for $t {
+            fn zero() -> $t {
+                0
+            }
+            fn saturating_add(self, rhs: $t) -> $t {
+                <$t>::saturating_add(self, rhs)
+            }
+            fn saturating_sub(self, rhs: $t) -> $t {
+                <$t>::saturating_sub(self, rhs)
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn from_f64(f: f64) -> $t {
+                f as $t
+            }
+        }
+    )*}
+}
+
+number!(u8 u16 u32 u64 i8 i16 i32 i64);
+
+/// A number that's fixed to be between some min and max value.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Bounded<T> {
+    pub val: T,
+    pub min: T,
+    pub max: T,
+}
+
+/// What `set_max` does with the current value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Keep {
+    /// Stays the same, unless it's out of the new range.
+    Value,
+    /// Stays the same fraction of the way between min and max.
+    Fraction,
+}
+
 /// Represents a u32 that is fixed to be between 0 and some max value.
+pub type BoundedNumber = Bounded<u32>;
+
+/// What actually happened when something got added or taken away.
 #[derive(Copy, Clone, Debug, PartialEq)]
-pub struct BoundedNumber {
-    pub val: u32,
-    pub max: u32,
+pub struct Outcome<T> {
+    /// How much the value really moved by.
+    pub applied: T,
+    /// How much got cut off by the min or max, like overkill
+    /// damage or overhealing.
+    pub overflow: T,
+    /// Went from above zero to zero or below, or back again.
+    pub crossed_zero: bool,
+    /// Wasn't full before, is now.
+    pub became_full: bool,
 }
 
-impl BoundedNumber {
-    pub fn new(max: u32) -> BoundedNumber {
-        BoundedNumber {
+impl<T: Number> Bounded<T> {
+    /// Between 0 and `max`, and full.
+    pub fn new(max: T) -> Bounded<T> {
+        Bounded::between(T::zero(), max)
+    }
+
+    /// Between `min` and `max`, and full.
+    pub fn between(min: T, max: T) -> Bounded<T> {
+        assert!(min <= max, "Bounded min {} is over max {}", min, max);
+        Bounded {
             val: max,
-            max: max,
+            min,
+            max,
         }
     }
-}
 
-impl Add<u32> for BoundedNumber {
-    type Output = BoundedNumber;
-    fn add(self, _rhs: u32) -> BoundedNumber {
-        BoundedNumber {
-            max: self.max,
-            val: cmp::min(self.max, self.val.saturating_add(_rhs)),
+    fn clamp(&self, val: T) -> T {
+        if val < self.min {
+            self.min
+        } else if val > self.max {
+            self.max
+        } else {
+            val
+        }
+    }
+
+    /// Sets the value, keeping it in bounds.
+    pub fn set(&mut self, val: T) {
+        self.val = self.clamp(val);
+    }
+
+    /// Changes the max.  The min comes down with it if it has to.
+    pub fn set_max(&mut self, max: T, keep: Keep) {
+        let fraction = self.fraction();
+        self.max = max;
+        if self.min > max {
+            self.min = max;
+        }
+        match keep {
+            Keep::Value => self.val = self.clamp(self.val),
+            Keep::Fraction => {
+                let span = self.max.to_f64() - self.min.to_f64();
+                self.val = self.clamp(T::from_f64(self.min.to_f64() + span * fraction));
+            }
+        }
+    }
+
+    /// How far between min and max the value is, from 0.0 to 1.0.
+    /// Something that can only be one value is always full.
+    pub fn fraction(&self) -> f64 {
+        let span = self.max.to_f64() - self.min.to_f64();
+        if span == 0.0 {
+            1.0
+        } else {
+            (self.val.to_f64() - self.min.to_f64()) / span
+        }
+    }
+
+    /// `fraction`, as a whole percent (rounded down).
+    pub fn percent(&self) -> u32 {
+        (self.fraction() * 100.0) as u32
+    }
+
+    /// Adds `amount` (which shouldn't be negative), and says how it went.
+    pub fn gain(&mut self, amount: T) -> Outcome<T> {
+        let old = *self;
+        self.val = self.clamp(self.val.saturating_add(amount));
+        let applied = self.val.saturating_sub(old.val);
+        self.outcome(old, applied, amount)
+    }
+
+    /// Takes away `amount` (which shouldn't be negative), and says how it went.
+    pub fn lose(&mut self, amount: T) -> Outcome<T> {
+        let old = *self;
+        self.val = self.clamp(self.val.saturating_sub(amount));
+        let applied = old.val.saturating_sub(self.val);
+        self.outcome(old, applied, amount)
+    }
+
+    fn outcome(&self, old: Bounded<T>, applied: T, amount: T) -> Outcome<T> {
+        Outcome {
+            applied,
+            overflow: amount.saturating_sub(applied),
+            crossed_zero: (old.val > T::zero()) != (self.val > T::zero()),
+            became_full: !old.is_full() && self.is_full(),
         }
     }
+
+    pub fn is_full(&self) -> bool {
+        self.val == self.max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.val == self.min
+    }
 }
 
-impl AddAssign<u32> for BoundedNumber {
-    fn add_assign(&mut self, _rhs: u32) {
+impl<T: Number> Add<T> for Bounded<T> {
+    type Output = Bounded<T>;
+    fn add(mut self, _rhs: T) -> Bounded<T> {
+        self.gain(_rhs);
+        self
+    }
+}
+
+impl<T: Number> AddAssign<T> for Bounded<T> {
+    fn add_assign(&mut self, _rhs: T) {
         self.val = (*self + _rhs).val;
     }
 }
 
 
-impl Sub<u32> for BoundedNumber {
-    type Output = BoundedNumber;
-    fn sub(self, _rhs: u32) -> BoundedNumber {
-        BoundedNumber {
-            max: self.max,
-            val: cmp::max(0, self.val.saturating_sub(_rhs)),
-        }
+impl<T: Number> Sub<T> for Bounded<T> {
+    type Output = Bounded<T>;
+    fn sub(mut self, _rhs: T) -> Bounded<T> {
+        self.lose(_rhs);
+        self
     }
 }
 
-impl SubAssign<u32> for BoundedNumber {
-    fn sub_assign(&mut self, _rhs: u32) {
+impl<T: Number> SubAssign<T> for Bounded<T> {
+    fn sub_assign(&mut self, _rhs: T) {
         self.val = (*self - _rhs).val;
     }
 }
 
-impl fmt::Display for BoundedNumber {
+/// Scales the value by a ratio, so `hp * 0.5` is half of what's left.
+impl<T: Number> Mul<f64> for Bounded<T> {
+    type Output = Bounded<T>;
+    fn mul(mut self, _rhs: f64) -> Bounded<T> {
+        self.val = self.clamp(T::from_f64(self.val.to_f64() * _rhs));
+        self
+    }
+}
+
+impl<T: Number> MulAssign<f64> for Bounded<T> {
+    fn mul_assign(&mut self, _rhs: f64) {
+        self.val = (*self * _rhs).val;
+    }
+}
+
+impl<T: Number> Div<f64> for Bounded<T> {
+    type Output = Bounded<T>;
+    fn div(self, _rhs: f64) -> Bounded<T> {
+        self * (1.0 / _rhs)
+    }
+}
+
+impl<T: Number> DivAssign<f64> for Bounded<T> {
+    fn div_assign(&mut self, _rhs: f64) {
+        self.val = (*self / _rhs).val;
+    }
+}
+
+impl<T: Number> fmt::Display for Bounded<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}/{}", self.val, self.max)
     }
@@ -61,7 +233,8 @@ impl fmt::Display for BoundedNumber {
 
 #[cfg(test)]
 mod tests {
-    use super::BoundedNumber;
+    use super::*;
+    use dice;
     #[test]
     fn bounded_number_is_bounded() {
         let max = 100;
@@ -73,4 +246,85 @@ mod tests {
         assert!(z.val == 0);
     }
 
+    #[test]
+    fn signed_and_scaled() {
+        let mut morale = Bounded::between(-50i32, 50);
+        morale -= 80;
+        assert_eq!(morale.val, -30);
+        assert_eq!(morale.percent(), 20);
+        morale -= 1000;
+        assert!(morale.is_empty() && morale.val == -50);
+        morale.set(20);
+        morale *= 1.5;
+        assert_eq!(morale.val, 30);
+        morale /= 4.0;
+        assert_eq!(morale.val, 7);
+        morale *= 100.0;
+        assert!(morale.is_full());
+
+        let mut hp = BoundedNumber::new(20);
+        hp -= 15;
+        hp.set_max(10, Keep::Value);
+        assert_eq!(hp.to_string(), "5/10");
+        hp.set_max(40, Keep::Fraction);
+        assert_eq!(hp.to_string(), "20/40");
+        hp.set_max(0, Keep::Fraction);
+        assert!(hp.is_full() && hp.is_empty());
+        assert_eq!(hp.fraction(), 1.0);
+    }
+
+    #[test]
+    fn outcomes() {
+        let mut hp = BoundedNumber::new(10);
+        let hit = hp.lose(4);
+        assert_eq!((hit.applied, hit.overflow, hit.crossed_zero, hit.became_full),
+                   (4, 0, false, false));
+        let heal = hp.gain(7);
+        assert_eq!((heal.applied, heal.overflow, heal.became_full), (4, 3, true));
+        let overkill = hp.lose(25);
+        assert_eq!((overkill.applied, overkill.overflow, overkill.crossed_zero), (10, 15, true));
+        assert!(!hp.lose(1).crossed_zero);
+        assert!(hp.gain(1).crossed_zero);
+
+        let mut morale = Bounded::between(-5i32, 5);
+        morale.set(2);
+        let drop = morale.lose(4);
+        assert_eq!((drop.applied, drop.crossed_zero), (4, true));
+        assert_eq!(morale.lose(4).overflow, 1);
+    }
+
+    #[test]
+    fn random_operations_stay_in_bounds() {
+        dice::seed(45);
+        for _ in 0..500 {
+            let min = i32::from(dice::random::<i16>());
+            let mut n = Bounded::between(min, min + i32::from(dice::random::<u16>()));
+            for _ in 0..20 {
+                let amount = i32::from(dice::random::<u16>());
+                let before = n.val;
+                match dice::random::<u32>() % 5 {
+                    0 => {
+                        let outcome = n.gain(amount);
+                        assert_eq!(outcome.applied + outcome.overflow, amount);
+                        assert_eq!(n.val, before + outcome.applied);
+                    }
+                    1 => {
+                        let outcome = n.lose(amount);
+                        assert_eq!(outcome.applied + outcome.overflow, amount);
+                        assert_eq!(n.val, before - outcome.applied);
+                    }
+                    2 => n *= dice::random::<f64>() * 3.0,
+                    3 => n.set(i32::from(dice::random::<i16>())),
+                    _ => {
+                        let keep = if dice::random() { Keep::Value } else { Keep::Fraction };
+                        n.set_max(i32::from(dice::random::<i16>()), keep);
+                    }
+                }
+                assert!(n.min <= n.val && n.val <= n.max, "{:?}", n);
+                assert!(n.percent() <= 100);
+                assert_eq!(n.is_full(), n.val == n.max);
+                assert_eq!(n.is_empty(), n.val == n.min);
+            }
+        }
+    }
 }
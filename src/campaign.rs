@@ -0,0 +1,380 @@
+//! Stringing battles together into an adventure.
+//!
+//! A `Campaign` is a party that sticks around from one fight to the
+//! next, along with their bag and how far along the road they've got.
+//! The road's a fixed list of stops: fights, inns to rest up at, a
+//! shop, and the Rust Dragon at the end.  Like `command`, none of this
+//! does any input or output itself; a frontend asks what the next stop
+//! is and calls the right methods.
+//!
+//! Campaigns get saved as plain text, one thing per line:
+//!
+//! ```text
+//! RUSTDRAGON SAVE <version>
+//! STAGE <stops passed>
+//! GOLD <gold>
+//! ITEM <item> <count>
+//! CHAR <level> <xp> <hp> <max hp> <mp> <max mp> <atk> <def> <spd> <lck> <KO|OK> <name>
+//! EQUIP <item> <atk> <def> <spd>
+//! ```
+//!
+//! with an `ITEM` line per item in the bag and a `CHAR` line per party
+//! member, each followed by an `EQUIP` line per piece of gear they've
+//! got on.  Their stats already count the gear, and the numbers on an
+//! `EQUIP` line are how much it actually changed them by, so it can
+//! come off again cleanly.  Anything not saved
+//! (spells, skills, and so on) comes from whoever in `battle_generator`
+//! has that name.
+//!
+//! Version 2 added `EQUIP`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use super::character::*;
+use super::battlefield::*;
+use super::item::*;
+use super::shop::*;
+use super::battle_generator;
+
+pub const SAVE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// A random fight.
+    Battle,
+    /// Somewhere to rest up.
+    Inn,
+    /// Somewhere to spend all that gold.
+    Shop,
+    /// The Rust Dragon.
+    Boss,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stop::Battle => write!(f, "Monsters approach!"),
+            Stop::Inn => write!(f, "You come to an inn."),
+            Stop::Shop => write!(f, "You come to a shop."),
+            Stop::Boss => write!(f, "The Rust Dragon blocks the way!"),
+        }
+    }
+}
+
+/// The road from start to finish.
+pub const ROUTE: [Stop; 9] = [Stop::Battle,
+                              Stop::Battle,
+                              Stop::Inn,
+                              Stop::Battle,
+                              Stop::Shop,
+                              Stop::Battle,
+                              Stop::Battle,
+                              Stop::Inn,
+                              Stop::Boss];
+
+/// What a night at the inn costs, per party member.
+pub const INN_PRICE: u32 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Campaign {
+    pub party: Vec<Character>,
+    /// Gold and items.
+    pub bag: Inventory,
+    /// How many stops along `ROUTE` they've got past.
+    pub stage: usize,
+}
+
+impl Default for Campaign {
+    fn default() -> Campaign {
+        Campaign::new()
+    }
+}
+
+impl Campaign {
+    /// A new game, with a random party.
+    pub fn new() -> Campaign {
+        let mut bag = Inventory::new();
+        bag.add(Item::WorldTreeLeaf, 1);
+        Campaign {
+            party: battle_generator::random_party(),
+            bag,
+            stage: 0,
+        }
+    }
+
+    /// Where they are now.  None once they've made it to the end.
+    pub fn stop(&self) -> Option<Stop> {
+        ROUTE.get(self.stage).cloned()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stop().is_none()
+    }
+
+    /// Moves on to the next stop.
+    pub fn leave(&mut self) {
+        self.stage += 1;
+    }
+
+    /// Sets up the fight at this stop, if there is one.
+    /// The party goes first in it, in party order.
+    pub fn battlefield(&self) -> Option<Battlefield> {
+        let mut field = match self.stop()? {
+            Stop::Battle => battle_generator::generate_for(self.party.clone()),
+            Stop::Boss => battle_generator::boss_fight_for(self.party.clone()),
+            Stop::Inn | Stop::Shop => return None,
+        };
+        *field.inventory_mut(Team::Player) = self.bag.clone();
+        Some(field)
+    }
+
+    /// A fight against the named monsters, wherever they are.
+    /// Set up just like the ones along the route.
+    pub fn encounter(&self, monsters: &[String]) -> Battlefield {
+        let mut field = battle_generator::fight_with(self.party.clone(), monsters);
+        *field.inventory_mut(Team::Player) = self.bag.clone();
+        field
+    }
+
+    /// Takes the party and their bag back after winning the fight at
+    /// this stop, and moves on.  Anyone with enough experience goes up
+    /// a level; returns what to tell the player about it.
+    pub fn finish_battle(&mut self, field: &Battlefield) -> Vec<String> {
+        let messages = self.take_back(field);
+        self.leave();
+        messages
+    }
+
+    /// Like `finish_battle`, but stays put.
+    pub fn take_back(&mut self, field: &Battlefield) -> Vec<String> {
+        let mut messages = Vec::new();
+        for (i, member) in self.party.iter_mut().enumerate() {
+            if let Some(chr) = field.get(i) {
+                *member = chr.clone();
+            }
+            member.after_battle();
+            if member.level_up() > 0 {
+                messages.push(format!("{} is now level {}!", member.name, member.level));
+            }
+        }
+        if let Some(bag) = field.inventory(Team::Player) {
+            self.bag = bag.clone();
+        }
+        messages
+    }
+
+    /// The shop along the way.
+    pub fn shop(&self) -> Shop {
+        Shop::new(&[Item::WorldTreeLeaf,
+                    Item::CopperSword,
+                    Item::IronSword,
+                    Item::LeatherArmor,
+                    Item::ChainMail])
+    }
+
+    pub fn inn(&self) -> Inn {
+        Inn { price_per_head: INN_PRICE }
+    }
+
+    /// Spends a night at the inn, if they can pay for it.
+    pub fn rest(&mut self) -> Result<(), ShopError> {
+        self.inn().stay(&mut self.party, &mut self.bag)
+    }
+
+    pub fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "RUSTDRAGON SAVE {}", SAVE_VERSION)?;
+        writeln!(w, "STAGE {}", self.stage)?;
+        writeln!(w, "GOLD {}", self.bag.gold)?;
+        for &(item, count) in self.bag.items() {
+            writeln!(w, "ITEM {:?} {}", item, count)?;
+        }
+        for chr in &self.party {
+            writeln!(w,
+                     "CHAR {} {} {} {} {} {} {} {} {} {} {} {}",
+                     chr.level,
+                     chr.xp,
+                     chr.hp.val,
+                     chr.hp.max,
+                     chr.mp.val,
+                     chr.mp.max,
+                     chr.atk,
+                     chr.def,
+                     chr.spd,
+                     chr.lck,
+                     if chr.is_alive() { "OK" } else { "KO" },
+                     chr.name)?;
+            // Always in the same order, so saves don't shuffle about.
+            for slot in &[Slot::Weapon, Slot::Armor] {
+                if let Some(item) = chr.equipped.get(slot) {
+                    let applied = chr.gear_bonus[slot];
+                    writeln!(w,
+                             "EQUIP {:?} {} {} {}",
+                             item,
+                             applied.atk,
+                             applied.def,
+                             applied.spd)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<R: BufRead>(r: R) -> Result<Campaign, String> {
+        let mut lines = r.lines();
+        let mut next = || -> Result<Option<String>, String> {
+            lines.next().transpose().map_err(|e| e.to_string())
+        };
+        // Version 1 saves are just version 2 ones without any gear.
+        match next()? {
+            Some(ref l) if l == "RUSTDRAGON SAVE 1" || l == "RUSTDRAGON SAVE 2" => (),
+            Some(l) => return Err(format!("not a save file, or the wrong version: {}", l)),
+            None => return Err(String::from("empty save file")),
+        }
+        let mut campaign = Campaign {
+            party: Vec::new(),
+            bag: Inventory::new(),
+            stage: 0,
+        };
+        while let Some(line) = next()? {
+            let words: Vec<&str> = line.split(' ').collect();
+            match words[0] {
+                "STAGE" => campaign.stage = parse_num(words.get(1))? as usize,
+                "GOLD" => campaign.bag.gold = parse_num(words.get(1))?,
+                "ITEM" => campaign.bag.add(parse_item(&line)?, parse_num(words.get(2))?),
+                "EQUIP" => {
+                    let item = parse_item(&line)?;
+                    let slot = item.slot().ok_or_else(|| format!("can't wear {}", item))?;
+                    let chr = campaign.party
+                        .last_mut()
+                        .ok_or_else(|| String::from("EQUIP before any CHAR"))?;
+                    let applied = StatChange {
+                        atk: parse_delta(words.get(2))?,
+                        def: parse_delta(words.get(3))?,
+                        spd: parse_delta(words.get(4))?,
+                    };
+                    chr.equipped.insert(slot, item);
+                    chr.gear_bonus.insert(slot, applied);
+                }
+                "CHAR" if words.len() > 12 => campaign.party.push(parse_char(&words)?),
+                "" => (),
+                _ => return Err(format!("don't know what to do with {}", line)),
+            }
+        }
+        if campaign.party.is_empty() {
+            return Err(String::from("nobody in the party"));
+        }
+        Ok(campaign)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        self.save(&mut f)
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Campaign, String> {
+        let f = File::open(path).map_err(|e| e.to_string())?;
+        Campaign::load(BufReader::new(f))
+    }
+}
+
+fn parse_num(word: Option<&&str>) -> Result<u32, String> {
+    let word = word.ok_or_else(|| String::from("missing number"))?;
+    word.parse().map_err(|_| format!("bad number {}", word))
+}
+
+fn parse_delta(word: Option<&&str>) -> Result<i32, String> {
+    let word = word.ok_or_else(|| String::from("missing number"))?;
+    word.parse().map_err(|_| format!("bad number {}", word))
+}
+
+fn parse_item(line: &str) -> Result<Item, String> {
+    line.split(' ')
+        .nth(1)
+        .and_then(|w| Item::all().into_iter().find(|i| format!("{:?}", i) == w))
+        .ok_or_else(|| format!("unknown item in {}", line))
+}
+
+fn parse_char(words: &[&str]) -> Result<Character, String> {
+    let name = words[12..].join(" ");
+    let mut chr = battle_generator::template(&name)
+        .ok_or_else(|| format!("nobody called {}", name))?;
+    let num = |i: usize| parse_num(words.get(i));
+    chr.level = num(1)?;
+    chr.xp = num(2)?;
+    chr.hp.max = num(4)?;
+    chr.hp.val = num(3)?.min(chr.hp.max);
+    chr.mp.max = num(6)?;
+    chr.mp.val = num(5)?.min(chr.mp.max);
+    chr.atk = num(7)?;
+    chr.def = num(8)?;
+    chr.spd = num(9)?;
+    chr.lck = num(10)?;
+    chr.knocked_out = words[11] == "KO";
+    Ok(chr)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_route() {
+        let mut c = Campaign::new();
+        assert_eq!(c.party.len(), 4);
+        assert_eq!(c.stop(), Some(Stop::Battle));
+        let mut field = c.battlefield().unwrap();
+        assert_eq!(field.get(0).unwrap().name, c.party[0].name);
+        field.get_mut(0).unwrap().xp = xp_for_level(2);
+        field.get_mut(1).unwrap().knock_out();
+        field.inventory_mut(Team::Player).gold = 100;
+        let messages = c.finish_battle(&field);
+        assert_eq!(messages, vec![format!("{} is now level 2!", c.party[0].name)]);
+        assert!(!c.party[1].is_alive());
+        assert_eq!(c.bag.gold, 100);
+
+        c.leave();
+        assert_eq!(c.stop(), Some(Stop::Inn));
+        assert!(c.battlefield().is_none());
+        assert_eq!(c.rest(), Ok(()));
+        assert_eq!(c.bag.gold, 100 - 4 * INN_PRICE);
+        assert!(c.party.iter().all(|chr| chr.is_alive() && chr.hp.val == chr.hp.max));
+
+        c.stage = ROUTE.len() - 1;
+        let field = c.battlefield().unwrap();
+        assert!(field.monsters().any(|chr| chr.name == "Rust Dragon"));
+        c.leave();
+        assert!(c.is_finished());
+    }
+
+    #[test]
+    fn saving_and_loading() {
+        let mut c = Campaign::new();
+        c.stage = 3;
+        c.bag.gold = 77;
+        c.bag.add(Item::SmallMedal, 2);
+        c.party[0].xp = 45;
+        c.party[0].level_up();
+        c.party[1].hp -= 4;
+        c.party[2].knock_out();
+        c.party[3].equip(Item::ChainMail);
+        c.party[3].equip(Item::CopperSword);
+        let mut saved = Vec::new();
+        c.save(&mut saved).unwrap();
+        let loaded = Campaign::load(&saved[..]).unwrap();
+        assert_eq!(loaded, c);
+
+        assert!(Campaign::load(&b"HELLO"[..]).is_err());
+        let old = b"RUSTDRAGON SAVE 1\nGOLD 5\nCHAR 1 0 10 10 10 10 10 10 10 10 OK Ragnar\n";
+        assert_eq!(Campaign::load(&old[..]).unwrap().bag.gold, 5);
+        assert!(Campaign::load(&b"RUSTDRAGON SAVE 2\nEQUIP IronSword\n"[..]).is_err());
+        let bare = b"RUSTDRAGON SAVE 2\nCHAR 1 0 10 10 10 10 10 10 10 10 OK Ragnar\n\
+                     EQUIP CopperSword\n";
+        assert!(Campaign::load(&bare[..]).is_err());
+        assert!(Campaign::load(&b"RUSTDRAGON SAVE 1\nCHAR 1 0 1 1 1 1 1 1 1 1 OK Nobody\n"[..])
+            .is_err());
+    }
+}
@@ -3,20 +3,69 @@ use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::cmp;
 
-use super::bounded_number::BoundedNumber;
+use super::bounded_number::{BoundedNumber, Outcome};
+use super::element::*;
+use super::spell::*;
+use super::reaction::*;
+use super::skill::*;
+use super::loot::Drop;
+use super::item::*;
+use super::action::Charge;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum Team {
     Player,
     Monster,
+    /// Anybody else: guests tagging along with the party,
+    /// a rival gang of monsters, innocent bystanders...
+    /// How they get along with everyone is up to the
+    /// battlefield's `Relations`.
+    Other(u32),
+}
+
+/// Where a character stands in their side's formation.
+/// The back row is harder to hit with melee attacks
+/// and takes less physical damage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Row {
+    Front,
+    Back,
+}
+
+impl Row {
+    /// The other row.
+    pub fn swap(&self) -> Row {
+        match *self {
+            Row::Front => Row::Back,
+            Row::Back => Row::Front,
+        }
+    }
 }
 
 /// A structure that contains every possible buff
 /// because there's no damn reason to manaeg them
 /// individually...?
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum BuffType {
     Defend,
+    /// Double speed.
+    Haste,
+    /// Half speed.
+    Slow,
+    /// Hurts a bit at the end of every round.
+    Poison,
+    /// Can't do anything until it wears off or something hits them.
+    Sleep,
+}
+
+/// How much tension anyone can build up.
+pub const TENSION_MAX: u32 = 100;
+
+/// A tension meter with nothing in it yet.
+fn empty_tension() -> BoundedNumber {
+    let mut tension = BoundedNumber::new(TENSION_MAX);
+    tension.val = 0;
+    tension
 }
 
 // #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -24,13 +73,17 @@ pub enum BuffType {
 //     pub turns_left: u32
 // }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Character {
     pub name: String,
     pub hp: BoundedNumber,
     pub mp: BoundedNumber,
+    // Builds up from getting hurt or psyching up, and gets
+    // spent all at once on a big hit.  Starts empty.
+    pub tension: BoundedNumber,
 
     pub team: Team,
+    pub row: Row,
 
     // Stats.
     // Attack, how much damage you do
@@ -42,8 +95,53 @@ pub struct Character {
     // Luck, determines critical hit chance
     pub lck: u32,
 
+    // What kind of damage their plain attacks do
+    pub attack_element: Element,
+    // Anything they're weak to, resist, etc.
+    // Elements that aren't in here are Normal.
+    pub affinities: HashMap<Element, Affinity>,
+
+    // Spells they know how to cast
+    pub spells: Vec<Spell>,
+    // Non-magical tricks they know
+    pub skills: Vec<Skill>,
+    // Skill, round it can next be used in
+    pub cooldowns: HashMap<Skill, u32>,
+    // Names of whoever they can call in to help
+    pub summons: Vec<String>,
+
+    // What they do on their own when things happen
+    pub reactions: Vec<Reaction>,
+    // Traits that are just always on
+    pub passives: Vec<Passive>,
+
     // Buff type, duration
     pub buffs: HashMap<BuffType, u32>,
+
+    // Down for the count.  Stays set until someone revives them,
+    // even if something manages to heal them in the meantime.
+    pub knocked_out: bool,
+    // How many more rounds a summoned ally sticks around for.
+    // None for everyone who's here for the long haul.
+    pub lifetime: Option<u32>,
+    // Whatever they're in the middle of winding up, if anything.
+    pub charging: Option<Charge>,
+
+    // Experience: how much the party gets for beating a monster,
+    // or how much a party member has earned so far.
+    pub xp: u32,
+    pub level: u32,
+    // What they've got on.  Its bonuses are already counted
+    // in their stats.
+    pub equipped: HashMap<Slot, Item>,
+    // How much each piece of gear actually changed their stats by,
+    // which is less than its bonus if a stat would've gone below 1.
+    // Taking it off undoes exactly this.
+    pub gear_bonus: HashMap<Slot, StatChange>,
+    // How much gold they're carrying, for whoever beats them.
+    pub gold: u32,
+    // What else they might leave behind.
+    pub drops: Vec<Drop>,
 }
 
 
@@ -53,18 +151,42 @@ impl Character {
             name: String::from(name),
             hp: BoundedNumber::new(10),
             mp: BoundedNumber::new(10),
+            tension: empty_tension(),
 
-            team: team,
+            team,
+            row: Row::Front,
 
             atk: 10,
             def: 10,
             spd: 10,
             lck: 10,
 
+            attack_element: Element::Physical,
+            affinities: HashMap::new(),
+
+            spells: Vec::new(),
+            skills: Vec::new(),
+            cooldowns: HashMap::new(),
+            summons: Vec::new(),
+
+            reactions: Vec::new(),
+            passives: Vec::new(),
+
             buffs: HashMap::new(),
+            knocked_out: false,
+            lifetime: None,
+            charging: None,
+
+            xp: 0,
+            level: 1,
+            equipped: HashMap::new(),
+            gear_bonus: HashMap::new(),
+            gold: 0,
+            drops: Vec::new(),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new_with_stats(name: &str, team: Team,
                           maxhp: u32, maxmp: u32,
                           atk: u32, def: u32, spd: u32, lck: u32)
@@ -73,25 +195,319 @@ impl Character {
             name: String::from(name),
             hp: BoundedNumber::new(maxhp),
             mp: BoundedNumber::new(maxmp),
+            tension: empty_tension(),
+
+            team,
+            row: Row::Front,
+
+            atk,
+            def,
+            spd,
+            lck,
 
-            team: team,
+            attack_element: Element::Physical,
+            affinities: HashMap::new(),
 
-            atk: atk,
-            def: def,
-            spd: spd,
-            lck: lck,
+            spells: Vec::new(),
+            skills: Vec::new(),
+            cooldowns: HashMap::new(),
+            summons: Vec::new(),
+
+            reactions: Vec::new(),
+            passives: Vec::new(),
 
             buffs: HashMap::new(),
+            knocked_out: false,
+            lifetime: None,
+            charging: None,
+
+            xp: 0,
+            level: 1,
+            equipped: HashMap::new(),
+            gear_bonus: HashMap::new(),
+            gold: 0,
+            drops: Vec::new(),
         }
     }
 
 
     pub fn is_alive(&self) -> bool {
-        self.hp.val > 0
+        !self.knocked_out
+    }
+
+    /// Whether the character is in any shape to be given orders.
+    /// They have to be alive, awake, and not busy winding up
+    /// something big.
+    pub fn can_act(&self) -> bool {
+        self.is_alive() && !self.is_asleep() && self.charging.is_none()
+    }
+
+    /// Whether their tension's as high as it goes.
+    pub fn fully_psyched(&self) -> bool {
+        self.tension.val == self.tension.max
+    }
+
+    /// How much harder they hit thanks to their tension, in percent.
+    /// Only a full meter counts, and it triples it.
+    pub fn tension_boost(&self) -> u32 {
+        if self.fully_psyched() {
+            300
+        } else {
+            100
+        }
+    }
+
+    /// Uses up all their tension, returning how much there was.
+    pub fn spend_tension(&mut self) -> u32 {
+        let spent = self.tension.val;
+        self.tension.val = 0;
+        spent
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.has_buff(BuffType::Sleep)
+    }
+
+    /// Stops them winding up whatever they were winding up.
+    /// Returns whether there was anything to stop.
+    pub fn interrupt(&mut self) -> bool {
+        self.charging.take().is_some()
+    }
+
+    /// How fast they are right now, after haste and slow.
+    /// Never less than 1, so everyone gets a turn eventually.
+    pub fn speed(&self) -> u32 {
+        let mut spd = cmp::max(1, self.spd);
+        if self.has_buff(BuffType::Haste) {
+            spd *= 2;
+        }
+        if self.has_buff(BuffType::Slow) {
+            spd = cmp::max(1, spd / 2);
+        }
+        spd
+    }
+
+    /// Getting hit wakes people up, if it doesn't knock them out.
+    /// Says how much of the damage actually landed.
+    pub fn take_damage(&mut self, damage: u32) -> Outcome<u32> {
+        let outcome = self.hp.lose(damage);
+        self.buffs.remove(&BuffType::Sleep);
+        if self.hp.val == 0 {
+            self.knock_out();
+        }
+        outcome
+    }
+
+    /// Puts the character down.
+    /// Whatever buffs they had go away with them.
+    pub fn knock_out(&mut self) {
+        self.hp.val = 0;
+        self.knocked_out = true;
+        self.buffs.clear();
+        self.charging = None;
+    }
+
+    /// Gets a knocked-out character back up with the given
+    /// percentage of their max HP, always at least 1.
+    /// Does nothing and returns false if they weren't down,
+    /// or were a summon whose time ran out.
+    pub fn revive(&mut self, hp_percent: u32) -> bool {
+        if !self.knocked_out || self.has_left() {
+            return false;
+        }
+        self.knocked_out = false;
+        self.hp.val = 0;
+        self.hp += cmp::max(1, self.hp.max * hp_percent / 100);
+        true
+    }
+
+    /// Gets them back on their feet with everything topped up,
+    /// like after a good night's sleep.
+    pub fn rest(&mut self) {
+        self.knocked_out = false;
+        self.hp.val = self.hp.max;
+        self.mp.val = self.mp.max;
+    }
+
+    /// Clears out everything that only lasts for one fight:
+    /// buffs, tension, cooldowns, and anything half wound up.
+    /// HP, MP and being knocked out all stick.
+    pub fn after_battle(&mut self) {
+        self.buffs.clear();
+        self.tension.val = 0;
+        self.cooldowns.clear();
+        self.charging = None;
+    }
+
+    /// Goes up as many levels as their experience allows,
+    /// getting a bit tougher each time.
+    /// Returns how many levels they went up.
+    pub fn level_up(&mut self) -> u32 {
+        let mut gained = 0;
+        while self.xp >= xp_for_level(self.level + 1) {
+            self.level += 1;
+            gained += 1;
+            self.hp.max += 3;
+            self.mp.max += 2;
+            self.atk += 1;
+            self.def += 1;
+            self.spd += 1;
+            self.lck += 1;
+            if self.is_alive() {
+                self.hp += 3;
+                self.mp += 2;
+            }
+        }
+        gained
+    }
+
+    /// How their stats would change if they put this on instead of
+    /// whatever they've got in that slot now.
+    pub fn equip_change(&self, item: Item) -> StatChange {
+        let new = item.bonus();
+        let old = item.slot()
+            .and_then(|slot| self.equipped.get(&slot))
+            .map(|i| i.bonus())
+            .unwrap_or_default();
+        StatChange {
+            atk: new.atk - old.atk,
+            def: new.def - old.def,
+            spd: new.spd - old.spd,
+        }
+    }
+
+    /// Puts on a piece of gear, taking off whatever was in that slot.
+    /// Returns what came off, or the item itself if it isn't gear.
+    pub fn equip(&mut self, item: Item) -> Option<Item> {
+        let slot = match item.slot() {
+            Some(slot) => slot,
+            None => return Some(item),
+        };
+        let old = self.unequip(slot);
+        let applied = self.apply(item.bonus());
+        self.gear_bonus.insert(slot, applied);
+        self.equipped.insert(slot, item);
+        old
+    }
+
+    /// Takes off whatever's in the slot, and returns it.
+    pub fn unequip(&mut self, slot: Slot) -> Option<Item> {
+        let item = self.equipped.remove(&slot)?;
+        // Gear from a save file went on before we were keeping track.
+        let applied = self.gear_bonus.remove(&slot).unwrap_or_else(|| item.bonus());
+        self.apply(StatChange {
+            atk: -applied.atk,
+            def: -applied.def,
+            spd: -applied.spd,
+        });
+        Some(item)
+    }
+
+    /// Changes their stats, and returns how much they really changed.
+    fn apply(&mut self, change: StatChange) -> StatChange {
+        // Nothing ever goes below 1; defense especially, since
+        // it gets used for a random range.
+        fn add(stat: &mut u32, n: i32) -> i32 {
+            let old = *stat as i32;
+            *stat = cmp::max(1, old + n) as u32;
+            *stat as i32 - old
+        }
+        StatChange {
+            atk: add(&mut self.atk, change.atk),
+            def: add(&mut self.def, change.def),
+            spd: add(&mut self.spd, change.spd),
+        }
+    }
+
+    /// Whether they're a summoned ally who'll leave after a while.
+    /// These fight on their own rather than taking orders.
+    pub fn is_temporary(&self) -> bool {
+        self.lifetime.is_some()
+    }
+
+    /// Whether they were summoned and have gone home again.
+    pub fn has_left(&self) -> bool {
+        self.lifetime == Some(0)
+    }
+
+    pub fn knows_spell(&self, spell: Spell) -> bool {
+        self.spells.contains(&spell)
+    }
+
+    pub fn knows_skill(&self, skill: Skill) -> bool {
+        self.skills.contains(&skill)
+    }
+
+    /// Whether the skill's still cooling down from the last
+    /// time they used it, as of the given round.
+    pub fn cooling_down(&self, skill: Skill, round: u32) -> bool {
+        self.cooldowns.get(&skill).is_some_and(|&ready| round < ready)
+    }
+
+    /// Keeps them from using the skill again until its cooldown's
+    /// up.  A cooldown of 1 means they have to skip a round.
+    pub fn start_cooldown(&mut self, skill: Skill, round: u32) {
+        if skill.cooldown() > 0 {
+            self.cooldowns.insert(skill, round + skill.cooldown() + 1);
+        }
+    }
+
+    /// Whether they've got enough of whatever it takes.
+    pub fn can_afford(&self, cost: Cost) -> bool {
+        match cost {
+            Cost::Free => true,
+            Cost::Mp(n) => self.mp.val >= n,
+            Cost::Hp(n) => self.hp.val > n,
+            Cost::Tension(n) => self.tension.val >= n,
+        }
+    }
+
+    /// Takes the cost out of them.  Check `can_afford` first.
+    pub fn pay(&mut self, cost: Cost) {
+        match cost {
+            Cost::Free => (),
+            Cost::Mp(n) => self.mp -= n,
+            Cost::Hp(n) => self.hp -= n,
+            Cost::Tension(n) => self.tension -= n,
+        }
+    }
+
+    /// Whether they could use the skill in the given round:
+    /// they know it, can pay for it, and it's cooled down.
+    pub fn can_use_skill(&self, skill: Skill, round: u32) -> bool {
+        self.knows_skill(skill) && self.can_afford(skill.cost()) &&
+        !self.cooling_down(skill, round)
+    }
+
+    pub fn has_reaction(&self, reaction: Reaction) -> bool {
+        self.reactions.contains(&reaction)
+    }
+
+    pub fn has_passive(&self, passive: Passive) -> bool {
+        self.passives.contains(&passive)
+    }
+
+    /// How much HP they get back every round, all told.
+    pub fn regen(&self) -> u32 {
+        self.passives
+            .iter()
+            .map(|p| match *p {
+                Passive::Regen(n) => n,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn affinity(&self, element: Element) -> Affinity {
+        self.affinities.get(&element).cloned().unwrap_or(Affinity::Normal)
     }
 
-    pub fn take_damage(&mut self, damage: u32) {
-        self.hp -= damage;
+    pub fn set_affinity(&mut self, element: Element, affinity: Affinity) {
+        if affinity == Affinity::Normal {
+            self.affinities.remove(&element);
+        } else {
+            self.affinities.insert(element, affinity);
+        }
     }
 
     /// Buffs have a timer,
@@ -141,10 +557,27 @@ impl Character {
     }
 }
 
+/// How much experience it takes, all told, to get to a level.
+pub fn xp_for_level(level: u32) -> u32 {
+    let n = level.saturating_sub(1);
+    10 * n * n
+}
+
 impl fmt::Display for Character {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Name: {}, HP: {}, MP: {}", self.name, self.hp, self.mp);
-        if self.buffs.len() > 0 {
+        write!(f,
+               "Name: {}, HP: {}, MP: {}, Tension: {}",
+               self.name,
+               self.hp,
+               self.mp,
+               self.tension)?;
+        if self.knocked_out {
+            write!(f, " [KO]")?;
+        }
+        if self.charging.is_some() {
+            write!(f, " [charging]")?;
+        }
+        if !self.buffs.is_empty() {
             write!(f, " {:?}", self.buffs)
         } else {
             write!(f, "")
@@ -152,6 +585,35 @@ impl fmt::Display for Character {
     }
 }
 
+#[test]
+fn levelling_up() {
+    let mut c = Character::new("Ragnar", Team::Player);
+    assert_eq!(c.level_up(), 0);
+    c.xp = xp_for_level(3);
+    c.hp -= 5;
+    assert_eq!(c.level_up(), 2);
+    assert_eq!(c.level, 3);
+    assert_eq!(c.hp.max, 16);
+    assert_eq!(c.hp.val, 11);
+    assert_eq!(c.atk, 12);
+    assert_eq!(c.level_up(), 0);
+}
+
+#[test]
+fn gear() {
+    let mut c = Character::new("Ragnar", Team::Player);
+    assert_eq!(c.equip(Item::WorldTreeLeaf), Some(Item::WorldTreeLeaf));
+    assert_eq!(c.equip(Item::LeatherArmor), None);
+    assert_eq!(c.def, 12);
+    let change = c.equip_change(Item::ChainMail);
+    assert_eq!(change.to_string(), "+3 DEF, -1 SPD");
+    assert_eq!(c.equip(Item::ChainMail), Some(Item::LeatherArmor));
+    assert_eq!((c.def, c.spd), (15, 9));
+    assert_eq!(c.unequip(Slot::Armor), Some(Item::ChainMail));
+    assert_eq!((c.def, c.spd), (10, 10));
+    assert_eq!(c.unequip(Slot::Weapon), None);
+}
+
 #[test]
 fn random_char_methods() {
     let mut c = Character::new("Bob", Team::Monster);
@@ -159,3 +621,74 @@ fn random_char_methods() {
     c.take_damage(1_000_000);
     assert!(!c.is_alive());
 }
+
+#[test]
+fn gear_comes_off_the_way_it_went_on() {
+    let mut c = Character::new_with_stats("Taloon", Team::Player, 10, 0, 1, 1, 1, 1);
+    for _ in 0..5 {
+        c.equip(Item::ChainMail);
+        assert_eq!((c.def, c.spd), (6, 1));
+        c.equip(Item::LeatherArmor);
+        assert_eq!((c.def, c.spd), (3, 1));
+        c.unequip(Slot::Armor);
+        assert_eq!((c.atk, c.def, c.spd), (1, 1, 1));
+    }
+}
+
+#[test]
+fn buffs_run_out_on_time() {
+    ::dice::seed(7);
+    let all = [BuffType::Defend, BuffType::Haste, BuffType::Slow, BuffType::Poison, BuffType::Sleep];
+    for _ in 0..200 {
+        let mut c = Character::new("Alena", Team::Player);
+        let mut longest = HashMap::new();
+        for _ in 0..6 {
+            let buff = *::dice::choose(&all).unwrap();
+            let duration = ::dice::random::<u32>() % 6;
+            c.add_buff(buff, duration);
+            let d = longest.entry(buff).or_insert(duration);
+            *d = cmp::max(*d, duration);
+        }
+        // A buff put on for n rounds is still there after n ticks,
+        // with nothing left, and gone after the next one.
+        for ticks in 0..8 {
+            for buff in &all {
+                match longest.get(buff) {
+                    Some(&d) if ticks <= d => assert_eq!(c.buffs.get(buff), Some(&(d - ticks))),
+                    _ => assert!(!c.has_buff(*buff)),
+                }
+            }
+            c.tick_buffs();
+        }
+        assert!(c.buffs.is_empty());
+    }
+}
+
+#[test]
+fn knocked_out_and_back() {
+    let mut c = Character::new("Alena", Team::Player);
+    c.add_buff(BuffType::Defend, 3);
+    assert!(!c.revive(100));
+    c.take_damage(1_000_000);
+    assert!(c.knocked_out);
+    assert!(c.buffs.is_empty());
+    assert!(format!("{}", c).ends_with("[KO]"));
+    assert!(c.revive(50));
+    assert!(c.is_alive());
+    assert_eq!(c.hp.val, 5);
+    c.take_damage(1_000_000);
+    c.revive(0);
+    assert_eq!(c.hp.val, 1);
+}
+
+#[test]
+fn haste_and_slow() {
+    let mut c = Character::new("Brey", Team::Player);
+    assert_eq!(c.speed(), 10);
+    c.add_buff(BuffType::Haste, 3);
+    assert_eq!(c.speed(), 20);
+    c.add_buff(BuffType::Slow, 3);
+    assert_eq!(c.speed(), 10);
+    c.buffs.remove(&BuffType::Haste);
+    assert_eq!(c.speed(), 5);
+}
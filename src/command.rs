@@ -0,0 +1,654 @@
+//! The command-entry menus, as a state machine.
+//!
+//! Picking actions for the party goes
+//! actor -> command -> sub-menu -> target -> (next actor...) -> confirm,
+//! and you can back out of any step, including back to the previous
+//! party member's choice.  None of this knows anything about stdin
+//! or terminals; a frontend asks for the current `Prompt`, shows it
+//! however it likes, and feeds the player's answer back in as an `Input`.
+
+use std::fmt;
+
+use super::character::*;
+use super::battlefield::*;
+use super::action::*;
+use super::target::*;
+use super::spell::*;
+use super::skill::*;
+use super::item::*;
+
+/// The top-level things a character can be told to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Attack,
+    Defend,
+    ChangeRow,
+    Spell,
+    Skill,
+    Item,
+    Summon,
+    PsycheUp,
+}
+
+/// The spells the character knows, in menu order.
+fn spells(field: &Battlefield, actor: CharSpecifier) -> Vec<Spell> {
+    field.get(actor).map(|chr| chr.spells.clone()).unwrap_or_default()
+}
+
+/// The skills the character could use right now, in menu order.
+/// Anything they can't pay for or that's still cooling down
+/// is left off.
+fn skills(field: &Battlefield, actor: CharSpecifier) -> Vec<Skill> {
+    field.get(actor)
+        .map(|chr| {
+            chr.skills
+                .iter()
+                .cloned()
+                .filter(|&s| chr.can_use_skill(s, field.round))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// What's in the character's team's bag that's any use
+/// in a fight, in menu order.
+fn items(field: &Battlefield, actor: CharSpecifier) -> Vec<(Item, u32)> {
+    field.get(actor)
+        .and_then(|chr| field.inventory(chr.team))
+        .map(|inv| inv.items().iter().cloned().filter(|e| e.0.usable_in_battle()).collect())
+        .unwrap_or_default()
+}
+
+impl Command {
+    /// Everything that goes in the command menu.
+    pub fn all() -> Vec<Command> {
+        vec![Command::Attack,
+             Command::Defend,
+             Command::ChangeRow,
+             Command::Spell,
+             Command::Skill,
+             Command::Item,
+             Command::Summon,
+             Command::PsycheUp]
+    }
+
+    /// Whether the command always needs something picked from
+    /// a sub-menu, even if there's nothing in it.
+    pub fn has_submenu(&self) -> bool {
+        match *self {
+            Command::Spell | Command::Skill | Command::Item | Command::Summon => true,
+            Command::Attack | Command::Defend | Command::ChangeRow | Command::PsycheUp => false,
+        }
+    }
+
+    /// The list of options under this command, if it has one
+    /// (which spell, which item, etc).
+    /// An empty list means there's no sub-menu to show.
+    pub fn submenu(&self, field: &Battlefield, actor: CharSpecifier) -> Vec<String> {
+        match *self {
+            Command::Attack | Command::Defend | Command::ChangeRow | Command::PsycheUp => vec![],
+            Command::Spell => {
+                spells(field, actor)
+                    .iter()
+                    .map(|s| format!("{} ({} MP)", s, s.mp_cost()))
+                    .collect()
+            }
+            Command::Skill => {
+                skills(field, actor)
+                    .iter()
+                    .map(|s| match s.cost() {
+                        Cost::Free => s.to_string(),
+                        cost => format!("{} ({})", s, cost),
+                    })
+                    .collect()
+            }
+            Command::Item => {
+                items(field, actor)
+                    .iter()
+                    .map(|&(item, count)| format!("{} x{}", item, count))
+                    .collect()
+            }
+            Command::Summon => {
+                field.get(actor).map(|chr| chr.summons.clone()).unwrap_or_default()
+            }
+        }
+    }
+
+    /// Whether the command needs the player to pick a target.
+    pub fn needs_target(&self) -> bool {
+        match *self {
+            Command::Attack | Command::Spell | Command::Skill | Command::Item => true,
+            Command::Defend | Command::ChangeRow | Command::Summon | Command::PsycheUp => false,
+        }
+    }
+
+    /// The targets this command could be aimed at,
+    /// given what was picked from the sub-menu.
+    /// A plain attack only ever hits the one monster in front of you,
+    /// and revivals go to whichever allies are down.
+    pub fn targets(&self,
+                   field: &Battlefield,
+                   actor: CharSpecifier,
+                   choice: Option<usize>)
+                   -> Vec<Target> {
+        let team = field.get(actor).unwrap().team;
+        let revives = match *self {
+            Command::Spell => {
+                choice.and_then(|n| spells(field, actor).get(n).and_then(|s| s.revival()))
+                    .is_some()
+            }
+            Command::Item => {
+                choice.and_then(|n| items(field, actor).get(n).and_then(|i| i.0.revival()))
+                    .is_some()
+            }
+            _ => false,
+        };
+        if revives {
+            return Target::AllAllies.resolve_fallen(field, actor)
+                .into_iter()
+                .map(Target::Single)
+                .collect();
+        }
+        let spell = match *self {
+            Command::Spell => choice.and_then(|n| spells(field, actor).get(n).cloned()),
+            _ => None,
+        };
+        match spell {
+            Some(s) if s.hits_all() => return vec![Target::AllEnemies],
+            // Magic doesn't care about rows.
+            Some(s) if s.hostile() => {
+                return field.get_opponents_enumerate(team)
+                    .filter(|&(_, chr)| chr.is_alive())
+                    .map(|(i, _)| Target::Single(i))
+                    .collect();
+            }
+            Some(_) => return vec![Target::AllAllies],
+            None => (),
+        }
+        let skill = match *self {
+            Command::Skill => choice.and_then(|n| skills(field, actor).get(n).cloned()),
+            _ => None,
+        };
+        if let Some(to) = skill.and_then(|s| s.area()) {
+            return vec![to];
+        }
+        match *self {
+            // Skills aimed at one enemy work like attacks.
+            Command::Attack | Command::Skill => {
+                field.get_opponents_enumerate(team)
+                    .map(|(i, _)| i)
+                    .filter(|&i| field.in_reach(i))
+                    .map(Target::Single)
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Turns a fully-specified command into an action.
+    pub fn to_action(&self,
+                     field: &Battlefield,
+                     actor: CharSpecifier,
+                     choice: Option<usize>,
+                     target: Option<Target>)
+                     -> Action {
+        match *self {
+            Command::Attack => Action::Attack(actor, target.unwrap()),
+            Command::Defend => Action::Defend(actor),
+            Command::ChangeRow => Action::ChangeRow(actor),
+            Command::Spell => {
+                Action::Cast(actor,
+                             spells(field, actor)[choice.unwrap()],
+                             target.unwrap())
+            }
+            Command::Skill => {
+                Action::UseSkill(actor,
+                                 skills(field, actor)[choice.unwrap()],
+                                 target.unwrap())
+            }
+            Command::Item => {
+                Action::UseItem(actor,
+                                items(field, actor)[choice.unwrap()].0,
+                                target.unwrap())
+            }
+            Command::Summon => Action::Summon(actor, choice.unwrap()),
+            Command::PsycheUp => Action::PsycheUp(actor),
+        }
+    }
+}
+
+/// Everything the menus would let `actor` pick right now, in menu
+/// order, for anything that'd rather choose from a list than walk
+/// through the menus.
+pub fn valid_actions(field: &Battlefield, actor: CharSpecifier) -> Vec<Action> {
+    let mut actions = Vec::new();
+    if !field.get(actor).is_some_and(|chr| chr.can_act()) {
+        return actions;
+    }
+    for cmd in Command::all() {
+        let choices: Vec<Option<usize>> = if cmd.has_submenu() {
+            (0..cmd.submenu(field, actor).len()).map(Some).collect()
+        } else {
+            vec![None]
+        };
+        for choice in choices {
+            if cmd.needs_target() {
+                for target in cmd.targets(field, actor, choice) {
+                    actions.push(cmd.to_action(field, actor, choice, Some(target)));
+                }
+            } else {
+                actions.push(cmd.to_action(field, actor, choice, None));
+            }
+        }
+    }
+    actions
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Attack => write!(f, "Attack"),
+            Command::Defend => write!(f, "Defend"),
+            Command::ChangeRow => write!(f, "Change Row"),
+            Command::Spell => write!(f, "Spell"),
+            Command::Skill => write!(f, "Skill"),
+            Command::Item => write!(f, "Item"),
+            Command::Summon => write!(f, "Summon"),
+            Command::PsycheUp => write!(f, "Psyche Up"),
+        }
+    }
+}
+
+/// What the player is being asked right now.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prompt {
+    /// Pick a command for this character.
+    Command(CharSpecifier, Vec<Command>),
+    /// Pick which flavor of the command, from the given labels.
+    SubMenu(CharSpecifier, Command, Vec<String>),
+    /// Pick who to aim the command at.
+    Target(CharSpecifier, Command, Vec<Target>),
+    /// Everyone's got orders, go ahead with them?
+    Confirm,
+    /// All finished.
+    Done,
+}
+
+/// An answer to a `Prompt`.
+/// `Choose` is an index into whatever list the prompt had;
+/// for `Confirm` the only choice is 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Choose(usize),
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Command,
+    SubMenu(Command),
+    Target(Command, Option<usize>),
+    Confirm,
+    Done,
+}
+
+/// Walks the player through giving orders to their whole team.
+#[derive(Debug, Clone)]
+pub struct CommandEntry {
+    /// Everyone who needs orders this round, in menu order.
+    actors: Vec<CharSpecifier>,
+    /// The actions chosen so far; `actions[i]` belongs to `actors[i]`.
+    actions: Vec<Action>,
+    state: State,
+}
+
+impl CommandEntry {
+    /// Starts entering commands for everyone on `team`
+    /// who is able to act.  Summoned allies don't take orders.
+    pub fn new(field: &Battlefield, team: Team) -> CommandEntry {
+        let actors: Vec<CharSpecifier> = field.chars
+            .iter()
+            .enumerate()
+            .filter(|&(_, chr)| chr.team == team && !chr.is_temporary())
+            .map(|(i, _)| i)
+            .collect();
+        CommandEntry::for_actors(field, &actors)
+    }
+
+    /// Starts entering commands for just the given characters,
+    /// skipping any who aren't able to act.
+    pub fn for_actors(field: &Battlefield, actors: &[CharSpecifier]) -> CommandEntry {
+        let actors: Vec<CharSpecifier> = actors.iter()
+            .cloned()
+            .filter(|&i| field.get(i).is_some_and(|chr| chr.can_act()))
+            .collect();
+        let state = if actors.is_empty() {
+            State::Confirm
+        } else {
+            State::Command
+        };
+        CommandEntry {
+            actors,
+            actions: Vec::new(),
+            state,
+        }
+    }
+
+    /// The character currently being given orders, if any.
+    pub fn actor(&self) -> Option<CharSpecifier> {
+        self.actors.get(self.actions.len()).cloned()
+    }
+
+    /// The actions chosen so far.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn prompt(&self, field: &Battlefield) -> Prompt {
+        match (self.state, self.actor()) {
+            (State::Command, Some(actor)) => Prompt::Command(actor, Command::all()),
+            (State::SubMenu(cmd), Some(actor)) => {
+                Prompt::SubMenu(actor, cmd, cmd.submenu(field, actor))
+            }
+            (State::Target(cmd, choice), Some(actor)) => {
+                Prompt::Target(actor, cmd, cmd.targets(field, actor, choice))
+            }
+            (State::Done, _) => Prompt::Done,
+            _ => Prompt::Confirm,
+        }
+    }
+
+    /// Feeds in the player's answer to the current prompt.
+    /// Returns false if it didn't make sense (say, choosing
+    /// an option that doesn't exist), in which case nothing changes.
+    pub fn input(&mut self, field: &Battlefield, input: Input) -> bool {
+        match input {
+            Input::Cancel => self.cancel(),
+            Input::Choose(n) => self.choose(field, n),
+        }
+    }
+
+    fn choose(&mut self, field: &Battlefield, n: usize) -> bool {
+        match self.prompt(field) {
+            Prompt::Command(actor, commands) => {
+                match commands.get(n) {
+                    // Nothing to cast or use, so don't bother.
+                    Some(&cmd) if cmd.has_submenu() && cmd.submenu(field, actor).is_empty() => {
+                        false
+                    }
+                    Some(&cmd) => {
+                        self.advance(field, actor, cmd, None);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Prompt::SubMenu(actor, cmd, ref options) if n < options.len() => {
+                self.advance(field, actor, cmd, Some(n));
+                true
+            }
+            Prompt::Target(actor, cmd, targets) => {
+                let choice = match self.state {
+                    State::Target(_, choice) => choice,
+                    _ => None,
+                };
+                match targets.get(n) {
+                    Some(&target) => {
+                        self.finish_actor(cmd.to_action(field, actor, choice, Some(target)));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Prompt::Confirm if n == 0 => {
+                self.state = State::Done;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves on from a chosen command (and maybe a sub-menu choice)
+    /// to whatever it needs next.
+    fn advance(&mut self,
+               field: &Battlefield,
+               actor: CharSpecifier,
+               cmd: Command,
+               choice: Option<usize>) {
+        if choice.is_none() && !cmd.submenu(field, actor).is_empty() {
+            self.state = State::SubMenu(cmd);
+        } else if cmd.needs_target() {
+            self.state = State::Target(cmd, choice);
+        } else {
+            self.finish_actor(cmd.to_action(field, actor, choice, None));
+        }
+    }
+
+    fn finish_actor(&mut self, action: Action) {
+        self.actions.push(action);
+        self.state = if self.actions.len() < self.actors.len() {
+            State::Command
+        } else {
+            State::Confirm
+        };
+    }
+
+    /// Backs up one step.
+    /// Backing out of the command menu goes to the previous
+    /// character and throws away what they'd picked.
+    fn cancel(&mut self) -> bool {
+        self.state = match self.state {
+            State::Target(cmd, Some(_)) => State::SubMenu(cmd),
+            State::Target(..) | State::SubMenu(_) => State::Command,
+            State::Command | State::Confirm => {
+                if self.actions.pop().is_none() {
+                    return false;
+                }
+                State::Command
+            }
+            State::Done => return false,
+        };
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_field() -> Battlefield {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.chars.push(Character::new("Alena", Team::Player));
+        b.chars.push(Character::new("Cristo", Team::Player));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        b.chars.push(Character::new("Drakee", Team::Monster));
+        b
+    }
+
+    fn singles(cs: &[CharSpecifier]) -> Vec<Target> {
+        cs.iter().cloned().map(Target::Single).collect()
+    }
+
+    #[test]
+    fn enters_commands_for_everyone() {
+        let b = test_field();
+        let mut c = CommandEntry::new(&b, Team::Player);
+        assert_eq!(c.prompt(&b), Prompt::Command(0, Command::all()));
+        assert!(c.input(&b, Input::Choose(0)));
+        assert_eq!(c.prompt(&b), Prompt::Target(0, Command::Attack, singles(&[3, 4])));
+        assert!(c.input(&b, Input::Choose(1)));
+        assert!(c.input(&b, Input::Choose(1)));
+        assert!(c.input(&b, Input::Choose(1)));
+        assert_eq!(c.prompt(&b), Prompt::Confirm);
+        assert!(!c.is_done());
+        assert!(c.input(&b, Input::Choose(0)));
+        assert!(c.is_done());
+        assert_eq!(c.prompt(&b), Prompt::Done);
+        assert_eq!(c.actions().len(), 3);
+        match c.actions()[0] {
+            Action::Attack(0, Target::Single(4)) => (),
+            other => panic!("Wrong action: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cancel_backs_up() {
+        let b = test_field();
+        let mut c = CommandEntry::new(&b, Team::Player);
+        // Can't back up past the first character.
+        assert!(!c.input(&b, Input::Cancel));
+        c.input(&b, Input::Choose(1));
+        assert_eq!(c.actor(), Some(1));
+        // Into the target menu and back out.
+        c.input(&b, Input::Choose(0));
+        assert!(c.input(&b, Input::Cancel));
+        assert_eq!(c.prompt(&b), Prompt::Command(1, Command::all()));
+        // Back to Ragnar, which forgets his Defend.
+        assert!(c.input(&b, Input::Cancel));
+        assert_eq!(c.actor(), Some(0));
+        assert!(c.actions().is_empty());
+    }
+
+    #[test]
+    fn cancel_from_confirm() {
+        let b = test_field();
+        let mut c = CommandEntry::new(&b, Team::Player);
+        for _ in 0..3 {
+            c.input(&b, Input::Choose(1));
+        }
+        assert_eq!(c.prompt(&b), Prompt::Confirm);
+        assert!(c.input(&b, Input::Cancel));
+        assert_eq!(c.prompt(&b), Prompt::Command(2, Command::all()));
+        assert_eq!(c.actions().len(), 2);
+    }
+
+    #[test]
+    fn bad_input_changes_nothing() {
+        let b = test_field();
+        let mut c = CommandEntry::new(&b, Team::Player);
+        assert!(!c.input(&b, Input::Choose(99)));
+        c.input(&b, Input::Choose(0));
+        assert!(!c.input(&b, Input::Choose(2)));
+        assert_eq!(c.prompt(&b), Prompt::Target(0, Command::Attack, singles(&[3, 4])));
+    }
+
+    #[test]
+    fn back_row_needs_front_row_gone() {
+        let mut b = test_field();
+        b.get_mut(4).unwrap().row = Row::Back;
+        let c = CommandEntry::new(&b, Team::Player);
+        assert_eq!(Command::Attack.targets(&b, 0, None), vec![Target::Single(3)]);
+        b.get_mut(3).unwrap().take_damage(1_000_000);
+        assert_eq!(Command::Attack.targets(&b, 0, None), vec![Target::Single(4)]);
+        assert_eq!(c.actor(), Some(0));
+    }
+
+    #[test]
+    fn dead_characters_are_skipped() {
+        let mut b = test_field();
+        b.get_mut(1).unwrap().take_damage(1_000_000);
+        b.get_mut(3).unwrap().take_damage(1_000_000);
+        let mut c = CommandEntry::new(&b, Team::Player);
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.prompt(&b),
+                   Prompt::Target(0, Command::Attack, vec![Target::Single(4)]));
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.actor(), Some(2));
+    }
+
+    #[test]
+    fn reviving_the_fallen() {
+        let mut b = test_field();
+        b.get_mut(2).unwrap().spells.push(Spell::Zing);
+        b.get_mut(1).unwrap().take_damage(1_000_000);
+        let mut c = CommandEntry::new(&b, Team::Player);
+        // Ragnar doesn't know any spells, and there's nothing in the bag.
+        assert!(!c.input(&b, Input::Choose(3)));
+        assert!(!c.input(&b, Input::Choose(5)));
+        b.inventory_mut(Team::Player).add(Item::WorldTreeLeaf, 1);
+        assert!(c.input(&b, Input::Choose(5)));
+        assert_eq!(c.prompt(&b),
+                   Prompt::SubMenu(0, Command::Item, vec![String::from("Leaf of the World Tree x1")]));
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.prompt(&b), Prompt::Target(0, Command::Item, singles(&[1])));
+        c.input(&b, Input::Choose(0));
+        // Cristo's up next, since Alena's down.
+        c.input(&b, Input::Choose(3));
+        c.input(&b, Input::Choose(0));
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.actions(),
+                   &[Action::UseItem(0, Item::WorldTreeLeaf, Target::Single(1)),
+                     Action::Cast(2, Spell::Zing, Target::Single(1))]);
+    }
+
+    #[test]
+    fn summoning() {
+        let mut b = test_field();
+        b.get_mut(0).unwrap().summons.push(String::from("Great Sabrecat"));
+        b.chars.push(Character::new("Healie", Team::Player));
+        b.get_mut(5).unwrap().lifetime = Some(2);
+        let mut c = CommandEntry::new(&b, Team::Player);
+        assert!(c.input(&b, Input::Choose(6)));
+        assert_eq!(c.prompt(&b),
+                   Prompt::SubMenu(0, Command::Summon, vec![String::from("Great Sabrecat")]));
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.actions(), &[Action::Summon(0, 0)]);
+        // Alena can't summon anybody.
+        assert!(!c.input(&b, Input::Choose(6)));
+        // And Healie isn't taking orders.
+        c.input(&b, Input::Choose(1));
+        c.input(&b, Input::Choose(1));
+        assert_eq!(c.prompt(&b), Prompt::Confirm);
+    }
+
+    #[test]
+    fn skill_menu_only_shows_what_they_can_use() {
+        let mut b = test_field();
+        b.get_mut(0).unwrap().skills = vec![Skill::Gigaslash, Skill::DoubleSlash, Skill::Whistle];
+        let round = b.round;
+        b.get_mut(0).unwrap().start_cooldown(Skill::Whistle, round);
+        let mut c = CommandEntry::new(&b, Team::Player);
+        // Not psyched up, and Whistle's cooling down.
+        assert!(c.input(&b, Input::Choose(4)));
+        assert_eq!(c.prompt(&b),
+                   Prompt::SubMenu(0, Command::Skill, vec![String::from("Double Slash (2 HP)")]));
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.prompt(&b), Prompt::Target(0, Command::Skill, singles(&[3, 4])));
+        c.input(&b, Input::Choose(1));
+        assert_eq!(c.actions(), &[Action::UseSkill(0, Skill::DoubleSlash, Target::Single(4))]);
+        // Alena hasn't got any skills at all.
+        assert!(!c.input(&b, Input::Choose(4)));
+
+        // Once it's cooled down it's back, aimed at themselves.
+        b.round += Skill::Whistle.cooldown() + 1;
+        b.get_mut(0).unwrap().hp.val = 2;
+        let mut c = CommandEntry::new(&b, Team::Player);
+        c.input(&b, Input::Choose(4));
+        assert_eq!(c.prompt(&b),
+                   Prompt::SubMenu(0, Command::Skill, vec![String::from("Whistle")]));
+        c.input(&b, Input::Choose(0));
+        assert_eq!(c.prompt(&b), Prompt::Target(0, Command::Skill, vec![Target::Myself]));
+    }
+
+    #[test]
+    fn valid_actions_match_the_menus() {
+        let mut b = test_field();
+        assert_eq!(valid_actions(&b, 0),
+                   vec![Action::Attack(0, Target::Single(3)),
+                        Action::Attack(0, Target::Single(4)),
+                        Action::Defend(0),
+                        Action::ChangeRow(0),
+                        Action::PsycheUp(0)]);
+        b.get_mut(1).unwrap().summons = vec![String::from("Slime")];
+        assert!(valid_actions(&b, 1).contains(&Action::Summon(1, 0)));
+        b.get_mut(2).unwrap().hp.val = 0;
+        b.get_mut(2).unwrap().knocked_out = true;
+        assert!(valid_actions(&b, 2).is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+//! Where all the luck comes from.
+//!
+//! Everything random in the game rolls through here instead of
+//! calling `rand::thread_rng()` itself, so seeding it makes the same
+//! battle come out the same way every time: handy for tests, replays,
+//! and anyone who wants to show off a particular fight.  Each thread
+//! has its own dice, so tests running side by side don't get in each
+//! other's way.
+
+use std::cell::RefCell;
+
+use rand::{self, Rand, Rng, SeedableRng, XorShiftRng};
+
+thread_local! {
+    static RNG: RefCell<XorShiftRng> = RefCell::new(rand::weak_rng());
+}
+
+/// Makes everything from now on (on this thread) come out
+/// the same as any other time it was seeded with `seed`.
+pub fn seed(seed: u64) {
+    // XorShift gets stuck if its seed is all zeroes, hence the constants.
+    let seed = [seed as u32, (seed >> 32) as u32, 0x9e37_79b9, 0x7f4a_7c15];
+    RNG.with(|rng| *rng.borrow_mut() = XorShiftRng::from_seed(seed));
+}
+
+/// Does something with the dice.
+pub fn with<T, F: FnOnce(&mut XorShiftRng) -> T>(f: F) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+pub fn random<T: Rand>() -> T {
+    with(|rng| rng.gen())
+}
+
+/// One of the items, picked at random.
+pub fn choose<T>(items: &[T]) -> Option<&T> {
+    with(|rng| rng.choose(items))
+}
+
+pub fn shuffle<T>(items: &mut [T]) {
+    with(|rng| rng.shuffle(items))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_rolls() {
+        seed(42);
+        let first: Vec<u32> = (0..10).map(|_| random()).collect();
+        seed(42);
+        let second: Vec<u32> = (0..10).map(|_| random()).collect();
+        assert_eq!(first, second);
+        seed(43);
+        let third: Vec<u32> = (0..10).map(|_| random()).collect();
+        assert!(first != third);
+    }
+}
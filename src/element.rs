@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// What sort of damage something does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Element {
+    Physical,
+    Fire,
+    Ice,
+    Lightning,
+    Wind,
+    Holy,
+    Dark,
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Element::Physical => "physical",
+            Element::Fire => "fire",
+            Element::Ice => "ice",
+            Element::Lightning => "lightning",
+            Element::Wind => "wind",
+            Element::Holy => "holy",
+            Element::Dark => "dark",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How a character reacts to getting hit with an element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Affinity {
+    Normal,
+    /// Takes extra damage.
+    Weak,
+    /// Takes half damage.
+    Resist,
+    /// Takes no damage at all.
+    Immune,
+    /// Gets healed by it instead.
+    Absorb,
+}
+
+impl Affinity {
+    /// Scales a hit by this affinity.
+    /// For `Absorb` this is how much gets healed, rather than
+    /// how much damage gets done.
+    pub fn apply(&self, damage: u32) -> u32 {
+        match *self {
+            Affinity::Normal => damage,
+            Affinity::Weak => damage + damage / 2,
+            Affinity::Resist => damage / 2,
+            Affinity::Immune => 0,
+            Affinity::Absorb => damage,
+        }
+    }
+}
+
+#[test]
+fn affinities_scale_damage() {
+    assert_eq!(Affinity::Normal.apply(10), 10);
+    assert_eq!(Affinity::Weak.apply(10), 15);
+    assert_eq!(Affinity::Resist.apply(10), 5);
+    assert_eq!(Affinity::Immune.apply(10), 0);
+    assert_eq!(Affinity::Absorb.apply(10), 10);
+}
@@ -0,0 +1,157 @@
+use super::character::{BuffType, Row};
+use super::battlefield::*;
+use super::spell::Spell;
+use super::item::Item;
+use super::skill::Skill;
+
+/// Something that happened during a battle.
+///
+/// Actions don't print anything themselves, they just
+/// push these onto a list and whatever frontend is running
+/// decides how to show them to the player.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum BattleEvent {
+    Attacked(CharSpecifier, CharSpecifier),
+    /// How much damage landed, and how much more there was
+    /// than they had HP left for.
+    Damaged(CharSpecifier, u32, u32),
+    NoDamage(CharSpecifier),
+    WardedOff(CharSpecifier),
+    Died(CharSpecifier),
+    SuperEffective(CharSpecifier),
+    Resisted(CharSpecifier),
+    Immune(CharSpecifier),
+    Absorbed(CharSpecifier, u32),
+    Defended(CharSpecifier),
+    ChangedRow(CharSpecifier, Row),
+    CastSpell(CharSpecifier, Spell),
+    NotEnoughMp(CharSpecifier),
+    UsedItem(CharSpecifier, Item),
+    OutOfItem(CharSpecifier, Item),
+    Revived(CharSpecifier),
+    /// Whatever it was didn't do anything to them.
+    NoEffect(CharSpecifier),
+    CalledForHelp(CharSpecifier),
+    NobodyCame(CharSpecifier),
+    Appeared(CharSpecifier),
+    /// A summoned ally's time is up.
+    Departed(CharSpecifier),
+    Buffed(CharSpecifier, BuffType),
+    /// About to hit back.
+    Countered(CharSpecifier),
+    /// The first one takes the hit meant for the second.
+    Covered(CharSpecifier, CharSpecifier),
+    Reflected(CharSpecifier),
+    Cured(CharSpecifier),
+    PoisonDamage(CharSpecifier, u32),
+    Regenerated(CharSpecifier, u32),
+    Chanting(CharSpecifier, Spell),
+    WindingUp(CharSpecifier, Skill),
+    StillCharging(CharSpecifier),
+    /// Lost whatever they were winding up.
+    Interrupted(CharSpecifier),
+    UsedSkill(CharSpecifier, Skill),
+    /// Something they set up earlier just went off.
+    WentOff(CharSpecifier, Skill),
+    /// Slept through their turn.
+    Asleep(CharSpecifier),
+    WokeUp(CharSpecifier),
+    /// Tension's gone up, to this much.
+    PsychedUp(CharSpecifier, u32),
+    /// About to put all their tension into an attack.
+    Unleashed(CharSpecifier),
+    /// Tried something that needs full tension without it.
+    NotPsyched(CharSpecifier),
+    NotEnoughHp(CharSpecifier),
+    /// Used it too recently to use it again yet.
+    CoolingDown(CharSpecifier, Skill),
+    Stole(CharSpecifier, Item),
+    StealFailed(CharSpecifier),
+}
+
+impl BattleEvent {
+    /// Turns the event into a message for the player.
+    /// Needs the battlefield to look up names.
+    pub fn describe(&self, field: &Battlefield) -> String {
+        let name = |c: CharSpecifier| {
+            field.get(c)
+                .map(|chr| chr.name.clone())
+                .unwrap_or_else(|| String::from("???"))
+        };
+        match *self {
+            BattleEvent::Attacked(from, to) => format!("{} attacked {}!", name(from), name(to)),
+            BattleEvent::Damaged(_, amount, _) => format!("Hit!  Did {} damage!", amount),
+            BattleEvent::NoDamage(_) => String::from("Did no damage!"),
+            BattleEvent::WardedOff(who) => format!("{} warded them off!", name(who)),
+            BattleEvent::Died(who) => format!("{} perished!", name(who)),
+            BattleEvent::SuperEffective(_) => String::from("It's super effective!"),
+            BattleEvent::Resisted(who) => format!("{} resisted!", name(who)),
+            BattleEvent::Immune(who) => format!("{} is unaffected!", name(who)),
+            BattleEvent::Absorbed(who, amount) => {
+                format!("{} absorbed the attack and recovered {} HP!", name(who), amount)
+            }
+            BattleEvent::Defended(who) => format!("{} defended themselves!", name(who)),
+            BattleEvent::ChangedRow(who, Row::Front) => format!("{} moved up to the front row.", name(who)),
+            BattleEvent::ChangedRow(who, Row::Back) => format!("{} fell back to the back row.", name(who)),
+            BattleEvent::CastSpell(who, spell) => format!("{} cast {}!", name(who), spell),
+            BattleEvent::NotEnoughMp(who) => format!("{} doesn't have enough MP!", name(who)),
+            BattleEvent::UsedItem(who, item) => format!("{} used the {}!", name(who), item),
+            BattleEvent::OutOfItem(who, item) => {
+                format!("{} reached for a {}, but there were none left!", name(who), item)
+            }
+            BattleEvent::Revived(who) => format!("{} came back to life!", name(who)),
+            BattleEvent::NoEffect(who) => format!("But nothing happened to {}!", name(who)),
+            BattleEvent::CalledForHelp(who) => format!("{} called for help!", name(who)),
+            BattleEvent::NobodyCame(_) => String::from("But nobody came!"),
+            BattleEvent::Appeared(who) => format!("{} appeared!", name(who)),
+            BattleEvent::Departed(who) => format!("{} went back home.", name(who)),
+            BattleEvent::Buffed(who, BuffType::Defend) => format!("{} is on guard.", name(who)),
+            BattleEvent::Buffed(who, BuffType::Haste) => format!("{} speeds up!", name(who)),
+            BattleEvent::Buffed(who, BuffType::Slow) => format!("{} slows down!", name(who)),
+            BattleEvent::Buffed(who, BuffType::Poison) => format!("{} was poisoned!", name(who)),
+            BattleEvent::Countered(who) => format!("{} strikes back!", name(who)),
+            BattleEvent::Covered(who, ally) => {
+                format!("{} jumped in front of {}!", name(who), name(ally))
+            }
+            BattleEvent::Reflected(who) => format!("{} bounced the spell back!", name(who)),
+            BattleEvent::Cured(who) => format!("{} shook off the poison.", name(who)),
+            BattleEvent::PoisonDamage(who, amount) => {
+                format!("{} took {} damage from the poison!", name(who), amount)
+            }
+            BattleEvent::Regenerated(who, amount) => {
+                format!("{} regenerated {} HP.", name(who), amount)
+            }
+            BattleEvent::Buffed(who, BuffType::Sleep) => format!("{} fell asleep!", name(who)),
+            BattleEvent::Chanting(who, spell) => format!("{} begins chanting {}...", name(who), spell),
+            BattleEvent::WindingUp(who, skill) => format!("{} {}", name(who), skill.telegraph()),
+            BattleEvent::StillCharging(who) => format!("{} is still gathering power...", name(who)),
+            BattleEvent::Interrupted(who) => format!("{} lost their concentration!", name(who)),
+            BattleEvent::UsedSkill(who, skill) => format!("{} used {}!", name(who), skill),
+            BattleEvent::WentOff(who, skill) => format!("{}'s {} went off!", name(who), skill),
+            BattleEvent::Asleep(who) => format!("{} is fast asleep.", name(who)),
+            BattleEvent::WokeUp(who) => format!("{} woke up!", name(who)),
+            BattleEvent::PsychedUp(who, tension) => {
+                format!("{} psyches up!  Tension is now {}.", name(who), tension)
+            }
+            BattleEvent::Unleashed(who) => format!("{} unleashes their pent-up tension!", name(who)),
+            BattleEvent::NotPsyched(who) => format!("{} isn't fired up enough yet!", name(who)),
+            BattleEvent::NotEnoughHp(who) => format!("{} doesn't have enough HP!", name(who)),
+            BattleEvent::CoolingDown(who, skill) => {
+                format!("{} can't use {} again just yet!", name(who), skill)
+            }
+            BattleEvent::Stole(who, item) => format!("{} stole a {}!", name(who), item),
+            BattleEvent::StealFailed(who) => format!("{} couldn't steal anything.", name(who)),
+        }
+    }
+}
+
+/// How much damage went to waste on people who didn't have
+/// that much HP left, over a list of events.
+pub fn overkill(events: &[BattleEvent]) -> u32 {
+    events.iter()
+        .map(|e| match *e {
+            BattleEvent::Damaged(_, _, extra) => extra,
+            _ => 0,
+        })
+        .sum()
+}
@@ -0,0 +1,117 @@
+//! Who goes first.
+//!
+//! Every round each character rolls for initiative: their speed
+//! (counting haste and slow) plus
+//! up to half their speed again, at random.  So faster characters
+//! usually go first, but not always, and anyone who ties gets sorted
+//! out with a coin flip instead of whoever happens to be first in the
+//! list.
+
+use std::cmp;
+
+use super::dice;
+
+use super::battlefield::*;
+use super::character::Character;
+use super::reaction::Passive;
+
+/// Rolls a character's initiative for the round.
+pub fn roll_initiative(chr: &Character) -> u32 {
+    let spd = chr.speed();
+    spd + dice::random::<u32>() % (spd / 2 + 1)
+}
+
+/// The order characters act in this round, fastest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TurnOrder {
+    order: Vec<CharSpecifier>,
+}
+
+impl TurnOrder {
+    /// Rolls initiative for everyone still standing.
+    pub fn roll(field: &Battlefield) -> TurnOrder {
+        let mut rolls: Vec<(CharSpecifier, (bool, u32))> = field.chars
+            .iter()
+            .enumerate()
+            .filter(|&(_, chr)| chr.is_alive())
+            .map(|(i, chr)| (i, (chr.has_passive(Passive::FirstStrike), roll_initiative(chr))))
+            .collect();
+        // Shuffling first and then doing a stable sort is
+        // what makes ties a coin flip.
+        // Anyone with first strike beats everyone without,
+        // whatever they rolled.
+        dice::shuffle(&mut rolls);
+        rolls.sort_by_key(|&(_, roll)| cmp::Reverse(roll));
+        TurnOrder { order: rolls.into_iter().map(|(i, _)| i).collect() }
+    }
+
+    /// A turn order that's just the given characters, in order.
+    pub fn from_list(order: &[CharSpecifier]) -> TurnOrder {
+        TurnOrder { order: order.to_vec() }
+    }
+
+    /// Where the character comes in the order.
+    /// Anyone who wasn't around when it was rolled goes last.
+    pub fn position(&self, c: CharSpecifier) -> usize {
+        self.order.iter().position(|&o| o == c).unwrap_or(self.order.len())
+    }
+
+    pub fn order(&self) -> &[CharSpecifier] {
+        &self.order
+    }
+
+    /// Something like "Turn order: Alena, Slime, Ragnar".
+    pub fn describe(&self, field: &Battlefield) -> String {
+        let names: Vec<&str> = self.order
+            .iter()
+            .filter_map(|&c| field.get(c))
+            .map(|chr| chr.name.as_str())
+            .collect();
+        format!("Turn order: {}", names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use character::*;
+
+    #[test]
+    fn faster_usually_goes_first() {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new_with_stats("Slowpoke", Team::Player, 10, 10, 10, 10, 10, 10));
+        b.chars.push(Character::new_with_stats("Speedy", Team::Monster, 10, 10, 10, 10, 30, 10));
+        b.chars.push(Character::new("Corpse", Team::Monster));
+        b.get_mut(2).unwrap().take_damage(1_000_000);
+        // 30 + something always beats 10 + at most 5.
+        for _ in 0..20 {
+            let order = TurnOrder::roll(&b);
+            assert_eq!(order.order(), &[1, 0]);
+            assert_eq!(order.position(2), 2);
+        }
+        assert_eq!(TurnOrder::from_list(&[1, 0]).describe(&b),
+                   "Turn order: Speedy, Slowpoke");
+    }
+
+    #[test]
+    fn first_strike_beats_speed() {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new_with_stats("Speedy", Team::Player, 10, 10, 10, 10, 30, 10));
+        b.chars.push(Character::new_with_stats("Ambusher", Team::Monster, 10, 10, 10, 10, 1, 10));
+        b.get_mut(1).unwrap().passives.push(Passive::FirstStrike);
+        for _ in 0..20 {
+            assert_eq!(TurnOrder::roll(&b).order(), &[1, 0]);
+        }
+    }
+
+    #[test]
+    fn ties_are_a_coin_flip() {
+        let mut b = Battlefield::new();
+        // Speed 1 means no variance at all, so it's all down to the flip.
+        b.chars.push(Character::new_with_stats("Heads", Team::Player, 10, 10, 10, 10, 1, 10));
+        b.chars.push(Character::new_with_stats("Tails", Team::Monster, 10, 10, 10, 10, 1, 10));
+        let firsts = (0..200).filter(|_| TurnOrder::roll(&b).order()[0] == 0).count();
+        // Should be about 100; this'll fail about never.
+        assert!(firsts > 50 && firsts < 150, "{} heads out of 200", firsts);
+    }
+}
@@ -0,0 +1,211 @@
+//! Items, the gear you can wear, and the bags they get carried around in.
+
+use std::fmt;
+
+use super::spell::Revival;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Item {
+    /// Brings someone back at full health, no questions asked.
+    WorldTreeLeaf,
+    /// No use in a fight, but collectors love them.
+    SmallMedal,
+    CopperSword,
+    IronSword,
+    LeatherArmor,
+    /// Tougher than leather, but it slows you down.
+    ChainMail,
+}
+
+/// Where a piece of gear goes.  Everyone's got one of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Slot {
+    Weapon,
+    Armor,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Slot::Weapon => write!(f, "Weapon"),
+            Slot::Armor => write!(f, "Armor"),
+        }
+    }
+}
+
+/// How much something changes someone's stats by.
+/// Shows up as something like "+3 ATK, -1 SPD".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatChange {
+    pub atk: i32,
+    pub def: i32,
+    pub spd: i32,
+}
+
+impl StatChange {
+    pub fn is_empty(&self) -> bool {
+        *self == StatChange::default()
+    }
+}
+
+impl fmt::Display for StatChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no change");
+        }
+        let parts: Vec<String> = [(self.atk, "ATK"), (self.def, "DEF"), (self.spd, "SPD")]
+            .iter()
+            .filter(|&&(n, _)| n != 0)
+            .map(|&(n, stat)| format!("{:+} {}", n, stat))
+            .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl Item {
+    pub fn all() -> Vec<Item> {
+        vec![Item::WorldTreeLeaf,
+             Item::SmallMedal,
+             Item::CopperSword,
+             Item::IronSword,
+             Item::LeatherArmor,
+             Item::ChainMail]
+    }
+
+    /// What it's worth, in gold.  Shops charge more than this and
+    /// pay less.  Zero means nobody'll buy it.
+    pub fn price(&self) -> u32 {
+        match *self {
+            Item::WorldTreeLeaf => 60,
+            Item::SmallMedal => 0,
+            Item::CopperSword => 50,
+            Item::IronSword => 150,
+            Item::LeatherArmor => 40,
+            Item::ChainMail => 120,
+        }
+    }
+
+    /// Where it's worn, if it's gear.
+    pub fn slot(&self) -> Option<Slot> {
+        match *self {
+            Item::CopperSword | Item::IronSword => Some(Slot::Weapon),
+            Item::LeatherArmor | Item::ChainMail => Some(Slot::Armor),
+            Item::WorldTreeLeaf | Item::SmallMedal => None,
+        }
+    }
+
+    /// What wearing it does for you.
+    pub fn bonus(&self) -> StatChange {
+        match *self {
+            Item::CopperSword => StatChange { atk: 3, ..StatChange::default() },
+            Item::IronSword => StatChange { atk: 7, ..StatChange::default() },
+            Item::LeatherArmor => StatChange { def: 2, ..StatChange::default() },
+            Item::ChainMail => {
+                StatChange {
+                    def: 5,
+                    spd: -1,
+                    ..StatChange::default()
+                }
+            }
+            Item::WorldTreeLeaf | Item::SmallMedal => StatChange::default(),
+        }
+    }
+
+    /// What it does for the fallen, if it's a revival item.
+    pub fn revival(&self) -> Option<Revival> {
+        match *self {
+            Item::WorldTreeLeaf => {
+                Some(Revival {
+                    chance: 100,
+                    hp_percent: 100,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether there's any point using it in a fight.
+    pub fn usable_in_battle(&self) -> bool {
+        self.revival().is_some()
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Item::WorldTreeLeaf => write!(f, "Leaf of the World Tree"),
+            Item::SmallMedal => write!(f, "Small Medal"),
+            Item::CopperSword => write!(f, "Copper Sword"),
+            Item::IronSword => write!(f, "Iron Sword"),
+            Item::LeatherArmor => write!(f, "Leather Armor"),
+            Item::ChainMail => write!(f, "Chain Mail"),
+        }
+    }
+}
+
+/// A bag of items, with how many of each, and the team's money.
+/// Items stay in the order they were first picked up,
+/// so menus don't jump around.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inventory {
+    items: Vec<(Item, u32)>,
+    pub gold: u32,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory::default()
+    }
+
+    pub fn add(&mut self, item: Item, count: u32) {
+        if let Some(entry) = self.items.iter_mut().find(|e| e.0 == item) {
+            entry.1 += count;
+            return;
+        }
+        if count > 0 {
+            self.items.push((item, count));
+        }
+    }
+
+    /// Takes one of the item out of the bag.
+    /// Returns false if there weren't any.
+    pub fn take(&mut self, item: Item) -> bool {
+        match self.items.iter().position(|e| e.0 == item) {
+            Some(i) => {
+                self.items[i].1 -= 1;
+                if self.items[i].1 == 0 {
+                    self.items.remove(i);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn count(&self, item: Item) -> u32 {
+        self.items.iter().find(|e| e.0 == item).map(|e| e.1).unwrap_or(0)
+    }
+
+    /// Everything in the bag, with counts.
+    pub fn items(&self) -> &[(Item, u32)] {
+        &self.items
+    }
+
+    /// Whether there are no items in it.
+    /// Doesn't care about gold.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[test]
+fn inventory_counts() {
+    let mut inv = Inventory::new();
+    assert!(!inv.take(Item::WorldTreeLeaf));
+    inv.add(Item::WorldTreeLeaf, 2);
+    assert_eq!(inv.count(Item::WorldTreeLeaf), 2);
+    assert!(inv.take(Item::WorldTreeLeaf));
+    assert!(inv.take(Item::WorldTreeLeaf));
+    assert!(!inv.take(Item::WorldTreeLeaf));
+    assert!(inv.is_empty());
+}
@@ -1,17 +1,164 @@
 pub mod bounded_number;
+pub mod dice;
 pub mod character;
 pub mod battlefield;
 pub mod action;
 pub mod battle_generator;
+pub mod event;
+pub mod element;
+pub mod target;
+pub mod spell;
+pub mod skill;
+pub mod item;
+pub mod loot;
+pub mod reaction;
+pub mod initiative;
+pub mod timeline;
+pub mod battle;
+pub mod campaign;
+pub mod shop;
+pub mod overworld;
+pub mod command;
+pub mod net;
+pub mod scenario;
+pub mod machine;
+pub mod tui;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate rand;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "tui")]
+extern crate termion;
 
 #[cfg(test)]
 mod tests {
-    #[test]
-    fn it_works() {}
+    //! Whole battles, start to finish.  Each one is seeded, and what
+    //! happened gets checked against a log in `tests/golden`, so any
+    //! change to the formulas or the AI shows up here.  If it was on
+    //! purpose, rerun with `UPDATE_GOLDEN=1` set and check the diff.
+
+    use std::env;
+    use std::fmt::Write;
+    use std::fs;
+
+    use action::*;
+    use battle::*;
+    use battlefield::*;
+    use battle_generator;
+    use character::*;
+    use dice;
+    use spell::*;
+    use target::*;
+
+    /// Seeds for random fights, one for the boss, and one for
+    /// `status_fight`.
+    const SEEDS: [u64; 3] = [1, 2, 3];
+    const BOSS_SEED: u64 = 4;
+    const STATUS_SEED: u64 = 3;
+    const MAX_ROUNDS: u32 = 100;
 
+    /// Everybody on autopilot until somebody wins.
+    fn play_out(seed: u64, boss: bool) -> String {
+        dice::seed(seed);
+        let field = if boss {
+            battle_generator::boss_fight()
+        } else {
+            battle_generator::generate()
+        };
+        play_scripted(field, &[])
+    }
 
+    /// Brey and Ragnar against a Babble and a Magician, with the party's
+    /// first few rounds picked so that every sort of buff turns up:
+    /// haste, guarding, sleep and slow, plus the Babble's poison.
+    fn status_fight() -> String {
+        dice::seed(STATUS_SEED);
+        let party = vec![battle_generator::template("Brey").unwrap(),
+                         battle_generator::template("Ragnar").unwrap()];
+        let field = battle_generator::fight_with(party,
+                                                 &["Babble".to_string(),
+                                                   "Magician".to_string()]);
+        play_scripted(field,
+                      &[vec![Action::Cast(0, Spell::Acceleratle, Target::AllAllies),
+                             Action::Defend(1)],
+                        vec![Action::Cast(0, Spell::Snooze, Target::Single(3)),
+                             Action::Defend(1)],
+                        vec![Action::Cast(0, Spell::Deceleratle, Target::Single(2))]])
+    }
+
+    /// Everybody on autopilot until somebody wins, except that anyone
+    /// with an action in `script` for that round (counting from the
+    /// first) does that instead.
+    fn play_scripted(mut field: Battlefield, script: &[Vec<Action>]) -> String {
+        let mut log = String::new();
+        let mut status = BattleStatus::Continuing;
+        while status == BattleStatus::Continuing && field.round < MAX_ROUNDS {
+            writeln!(log, "Round {}", field.round).unwrap();
+            tick_buffs(&mut field);
+            let mut actions = script.get(field.round as usize - 1).cloned().unwrap_or_default();
+            decide_monster_actions(&field, &mut actions);
+            let party: Vec<CharSpecifier> = (0..field.chars.len())
+                .filter(|&c| field.get(c).unwrap().team == Team::Player)
+                .filter(|&c| actions.iter().all(|a| a.source() != c))
+                .collect();
+            decide_actions(&field, &party, &mut actions);
+            let mut events = Vec::new();
+            status = run_turn(&mut field, &mut actions, &mut events);
+            for e in &events {
+                writeln!(log, "  {}", e.describe(&field)).unwrap();
+            }
+        }
+        writeln!(log, "{:?}", status).unwrap();
+        for chr in &field.chars {
+            // Buffs are in a HashMap, so sort them to keep the log steady.
+            let mut buffs: Vec<String> = chr.buffs
+                .iter()
+                .map(|(buff, rounds)| format!("{:?} {}", buff, rounds))
+                .collect();
+            buffs.sort();
+            writeln!(log,
+                     "{} ({:?}) HP {} MP {} Tension {}{} {:?}",
+                     chr.name,
+                     chr.team,
+                     chr.hp,
+                     chr.mp,
+                     chr.tension,
+                     if chr.is_alive() { "" } else { " KO" },
+                     buffs)
+                .unwrap();
+        }
+        log
+    }
+
+    fn check_golden(name: &str, log: &str) {
+        let path = format!("{}/tests/golden/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        if env::var("UPDATE_GOLDEN").is_ok() {
+            fs::write(&path, log).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Couldn't read {}: {}", path, e));
+        assert!(log == expected,
+                "{} doesn't match any more; rerun with UPDATE_GOLDEN=1 if that's on purpose.\n{}",
+                name,
+                log);
+    }
+
+    #[test]
+    fn seeded_battles_come_out_the_same() {
+        assert_eq!(play_out(5, false), play_out(5, false));
+    }
+
+    #[test]
+    fn golden_battles() {
+        for &seed in &SEEDS {
+            check_golden(&format!("battle_{}", seed), &play_out(seed, false));
+        }
+        check_golden("boss", &play_out(BOSS_SEED, true));
+        check_golden("status", &status_fight());
+    }
 }
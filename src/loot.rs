@@ -0,0 +1,175 @@
+//! What the party gets for winning.
+//!
+//! Every monster is worth some experience and gold, and might drop
+//! something.  Once the fight's won, `claim_rewards` adds it all up
+//! for whoever the party beat and hands it over.
+
+use super::character::*;
+use super::battlefield::*;
+use super::item::*;
+
+use super::dice;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Rarity {
+    Common,
+    /// Lucky parties find these more often.
+    Rare,
+}
+
+/// Something a monster might leave behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Drop {
+    pub item: Item,
+    /// Percent chance, before luck.
+    pub chance: u32,
+    pub rarity: Rarity,
+}
+
+/// How much each point of the party's luck adds to the chance
+/// of rare drops, in percent of the base chance.
+/// So someone with 10 luck finds them half again as often.
+pub const LUCK_BONUS: u32 = 5;
+
+impl Drop {
+    pub fn common(item: Item, chance: u32) -> Drop {
+        Drop {
+            item,
+            chance,
+            rarity: Rarity::Common,
+        }
+    }
+
+    pub fn rare(item: Item, chance: u32) -> Drop {
+        Drop {
+            item,
+            chance,
+            rarity: Rarity::Rare,
+        }
+    }
+
+    /// The actual percent chance, given the party's luck.
+    pub fn chance_with_luck(&self, luck: u32) -> u32 {
+        match self.rarity {
+            Rarity::Common => self.chance,
+            Rarity::Rare => self.chance * (100 + luck * LUCK_BONUS) / 100,
+        }
+    }
+
+    /// Rolls to see whether it drops this time.
+    pub fn roll(&self, luck: u32) -> bool {
+        dice::random::<u32>() % 100 < self.chance_with_luck(luck)
+    }
+}
+
+/// Everything the party got out of a fight.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BattleRewards {
+    /// The total; each survivor gets an even share of it.
+    pub xp: u32,
+    pub gold: u32,
+    pub items: Vec<Item>,
+}
+
+impl BattleRewards {
+    /// One message per line, for the player.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![format!("Gained {} experience and {} gold.", self.xp, self.gold)];
+        for item in &self.items {
+            lines.push(format!("Found a {}!", item));
+        }
+        lines
+    }
+}
+
+/// Adds up what everyone the party beat was worth, rolls for drops,
+/// and hands it all over: gold and items go in the party's bag, and
+/// experience gets split between whoever's still standing.
+/// Summoned friends don't get a share.
+pub fn claim_rewards(field: &mut Battlefield) -> BattleRewards {
+    let luck = field.players()
+        .filter(|chr| chr.is_alive())
+        .map(|chr| chr.lck)
+        .max()
+        .unwrap_or(0);
+    let mut rewards = BattleRewards::default();
+    for chr in field.get_opponents(Team::Player).filter(|chr| !chr.is_alive()) {
+        rewards.xp += chr.xp;
+        rewards.gold += chr.gold;
+        rewards.items.extend(chr.drops.iter().filter(|d| d.roll(luck)).map(|d| d.item));
+    }
+    {
+        let bag = field.inventory_mut(Team::Player);
+        bag.gold += rewards.gold;
+        for &item in &rewards.items {
+            bag.add(item, 1);
+        }
+    }
+    let survivors: Vec<&mut Character> = field.chars
+        .iter_mut()
+        .filter(|chr| chr.team == Team::Player && chr.is_alive() && !chr.is_temporary())
+        .collect();
+    if !survivors.is_empty() {
+        // Split evenly, and whoever's first in line gets
+        // whatever won't divide.
+        let share = rewards.xp / survivors.len() as u32;
+        let leftover = rewards.xp % survivors.len() as u32;
+        for (i, chr) in survivors.into_iter().enumerate() {
+            chr.xp += share;
+            if i == 0 {
+                chr.xp += leftover;
+            }
+        }
+    }
+    rewards
+}
+
+#[test]
+fn luck_helps_with_rare_drops() {
+    let medal = Drop::rare(Item::SmallMedal, 10);
+    assert_eq!(medal.chance_with_luck(0), 10);
+    assert_eq!(medal.chance_with_luck(10), 15);
+    let leaf = Drop::common(Item::WorldTreeLeaf, 10);
+    assert_eq!(leaf.chance_with_luck(10), 10);
+    assert!((0..100).all(|_| Drop::common(Item::SmallMedal, 100).roll(0)));
+    assert!((0..100).all(|_| !Drop::rare(Item::SmallMedal, 0).roll(50)));
+}
+
+#[test]
+fn rewards_go_to_the_survivors() {
+    let mut b = Battlefield::new();
+    b.chars.push(Character::new("Ragnar", Team::Player));
+    b.chars.push(Character::new("Alena", Team::Player));
+    b.chars.push(Character::new("Cristo", Team::Player));
+    b.chars.push(Character::new("Slime", Team::Monster));
+    b.chars.push(Character::new("Metal Slime", Team::Monster));
+    b.get_mut(2).unwrap().knock_out();
+    {
+        let slime = b.get_mut(3).unwrap();
+        slime.xp = 3;
+        slime.gold = 2;
+        slime.drops.push(Drop::common(Item::WorldTreeLeaf, 100));
+        slime.knock_out();
+    }
+    {
+        let metal = b.get_mut(4).unwrap();
+        metal.xp = 100;
+        metal.gold = 5;
+        metal.drops.push(Drop::rare(Item::SmallMedal, 0));
+        metal.knock_out();
+    }
+    let rewards = claim_rewards(&mut b);
+    assert_eq!(rewards,
+               BattleRewards {
+                   xp: 103,
+                   gold: 7,
+                   items: vec![Item::WorldTreeLeaf],
+               });
+    assert_eq!(b.get(0).unwrap().xp, 52);
+    assert_eq!(b.get(1).unwrap().xp, 51);
+    assert_eq!(b.get(2).unwrap().xp, 0);
+    let bag = b.inventory(Team::Player).unwrap();
+    assert_eq!(bag.gold, 7);
+    assert_eq!(bag.count(Item::WorldTreeLeaf), 1);
+    assert_eq!(rewards.describe()[1], "Found a Leaf of the World Tree!");
+}
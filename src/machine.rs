@@ -0,0 +1,263 @@
+//! Lets another program play the party, over JSON lines.
+//!
+//! Every line, either way, is one JSON object.  What we send has a
+//! `type`:
+//!
+//! ```text
+//! {"type": "state", "round": 1, "chars": [...], "bags": {"PLAYER": {"gold": 0, "items": {"LEAF": 1}}}}
+//!     How everything stands, sent at the start of every round.
+//!     `chars` has every field of every `Character`, and the index
+//!     into it is the number actions use to mean that character.
+//! {"type": "orders", "actor": 0, "actions": ["ATTACK 0 4", "DEFEND 0", ...]}
+//!     Character 0 needs orders, and these are all the things
+//!     they can do, in the same syntax as `net.rs`.
+//! {"type": "event", "text": "Ragnar attacked Slime!", "event": {"Attacked": [0, 4]}}
+//! {"type": "result", "winner": "PLAYER", "rewards": [...]}
+//!     `winner` is null if nobody won, which is also what gets sent
+//!     if you quit partway.
+//! {"type": "error", "message": "..."}
+//!     The last thing we got didn't make sense; send something else.
+//! ```
+//!
+//! After each `orders` we want one of:
+//!
+//! ```text
+//! {"action": "ATTACK 0 4"}    one of the actions on offer
+//! {"choose": 1}               the action at that index in the list
+//! {"command": "auto"}         let the computer decide this one
+//! {"command": "state"}        send the state again
+//! {"command": "quit"}
+//! ```
+
+use std::io::{self, BufRead, Write};
+
+use serde_json::{self, Value};
+
+use super::action::Action;
+use super::battle::{decide_actions, BattleStatus};
+use super::battlefield::*;
+use super::character::Team;
+use super::command::valid_actions;
+use super::event::BattleEvent;
+use super::net::{action_words, item_name, parse_action, team_name};
+
+pub fn state(field: &Battlefield) -> Value {
+    let mut bags = serde_json::Map::new();
+    for (&team, bag) in &field.inventories {
+        let items: serde_json::Map<String, Value> = bag.items()
+            .iter()
+            .map(|&(item, count)| (String::from(item_name(item)), json!(count)))
+            .collect();
+        bags.insert(team_name(team), json!({"gold": bag.gold, "items": items}));
+    }
+    json!({
+        "type": "state",
+        "round": field.round,
+        "chars": field.chars,
+        "bags": bags,
+    })
+}
+
+pub fn event(field: &Battlefield, e: &BattleEvent) -> Value {
+    json!({"type": "event", "text": e.describe(field), "event": e})
+}
+
+pub fn result(status: BattleStatus, rewards: &[String]) -> Value {
+    let winner = match status {
+        BattleStatus::PlayerVictory => Some(team_name(Team::Player)),
+        BattleStatus::MonsterVictory => Some(team_name(Team::Monster)),
+        BattleStatus::OtherVictory(team) => Some(team_name(team)),
+        BattleStatus::Continuing => None,
+    };
+    json!({"type": "result", "winner": winner, "rewards": rewards})
+}
+
+/// What came back in answer to some orders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reply {
+    Act(Action),
+    Auto,
+    State,
+    Quit,
+}
+
+fn parse_reply(line: &str, on_offer: &[Action]) -> Result<Reply, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("bad JSON: {}", e))?;
+    if let Some(n) = value.get("choose") {
+        let n = n.as_u64().ok_or_else(|| String::from("choose needs a number"))?;
+        return on_offer.get(n as usize)
+            .map(|&a| Reply::Act(a))
+            .ok_or_else(|| format!("there are only {} actions", on_offer.len()));
+    }
+    if let Some(words) = value.get("action") {
+        let words = words.as_str().ok_or_else(|| String::from("action needs a string"))?;
+        let words: Vec<&str> = words.split_whitespace().collect();
+        let action = parse_action(&words)?;
+        if !on_offer.contains(&action) {
+            return Err(format!("{} isn't one of the actions on offer", action_words(action)));
+        }
+        return Ok(Reply::Act(action));
+    }
+    match value.get("command").and_then(Value::as_str) {
+        Some("auto") => Ok(Reply::Auto),
+        Some("state") => Ok(Reply::State),
+        Some("quit") => Ok(Reply::Quit),
+        Some(other) => Err(format!("unknown command {}", other)),
+        None => Err(String::from("expected an action, choose or command")),
+    }
+}
+
+/// The other end of the pipe.
+pub struct Machine<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Machine<R, W> {
+    pub fn new(input: R, output: W) -> Machine<R, W> {
+        Machine { input, output }
+    }
+
+    pub fn send(&mut self, value: &Value) -> io::Result<()> {
+        writeln!(self.output, "{}", value)?;
+        self.output.flush()
+    }
+
+    /// Asks for orders for each of `actors` in turn, skipping anyone
+    /// who can't do anything.  Returns false if the other end hung up
+    /// or said to quit.
+    pub fn read_actions(&mut self,
+                        field: &Battlefield,
+                        actors: &[CharSpecifier],
+                        actions: &mut Vec<Action>)
+                        -> io::Result<bool> {
+        for &actor in actors {
+            let on_offer = valid_actions(field, actor);
+            if on_offer.is_empty() {
+                continue;
+            }
+            let orders = json!({
+                "type": "orders",
+                "actor": actor,
+                "actions": on_offer.iter().map(|&a| action_words(a)).collect::<Vec<_>>(),
+            });
+            self.send(&orders)?;
+            loop {
+                let mut line = String::new();
+                if self.input.read_line(&mut line)? == 0 {
+                    return Ok(false);
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse_reply(&line, &on_offer) {
+                    Ok(Reply::Act(action)) => actions.push(action),
+                    Ok(Reply::Auto) => decide_actions(field, &[actor], actions),
+                    Ok(Reply::State) => {
+                        self.send(&state(field))?;
+                        self.send(&orders)?;
+                        continue;
+                    }
+                    Ok(Reply::Quit) => return Ok(false),
+                    Err(e) => {
+                        self.send(&json!({"type": "error", "message": e}))?;
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// `read_actions` for everyone in the party.
+    pub fn read_party_actions(&mut self,
+                              field: &Battlefield,
+                              actions: &mut Vec<Action>)
+                              -> io::Result<bool> {
+        let party: Vec<CharSpecifier> = field.chars
+            .iter()
+            .enumerate()
+            .filter(|&(_, chr)| chr.team == Team::Player && !chr.is_temporary())
+            .map(|(i, _)| i)
+            .collect();
+        self.read_actions(field, &party, actions)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use character::Character;
+    use target::Target;
+
+    fn test_field() -> Battlefield {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.chars.push(Character::new("Alena", Team::Player));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        b
+    }
+
+    fn talk(field: &Battlefield, input: &str) -> (bool, Vec<Action>, Vec<Value>) {
+        let mut output = Vec::new();
+        let mut actions = Vec::new();
+        let going = {
+            let mut machine = Machine::new(Cursor::new(input), &mut output);
+            machine.read_party_actions(field, &mut actions).unwrap()
+        };
+        let sent = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        (going, actions, sent)
+    }
+
+    #[test]
+    fn orders_go_both_ways() {
+        let b = test_field();
+        let (going, actions, sent) = talk(&b,
+                                          "{\"action\": \"ATTACK 0 2\"}\n\
+                                           {\"choose\": 1}\n");
+        assert!(going);
+        assert_eq!(actions, vec![Action::Attack(0, Target::Single(2)), Action::Defend(1)]);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0]["type"], "orders");
+        assert_eq!(sent[0]["actor"], 0);
+        assert_eq!(sent[0]["actions"][0], "ATTACK 0 2");
+        assert_eq!(sent[1]["actor"], 1);
+    }
+
+    #[test]
+    fn nonsense_gets_another_chance() {
+        let b = test_field();
+        let (going, actions, sent) = talk(&b,
+                                          "hello\n\
+                                           {\"action\": \"ATTACK 1 2\"}\n\
+                                           {\"choose\": 99}\n\
+                                           {\"command\": \"state\"}\n\
+                                           {\"command\": \"auto\"}\n\
+                                           {\"command\": \"quit\"}\n");
+        assert!(!going);
+        assert_eq!(actions.len(), 1);
+        let types: Vec<&str> = sent.iter().map(|v| v["type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["orders", "error", "error", "error", "state", "orders", "orders"]);
+        assert_eq!(sent[4]["chars"][2]["name"], "Slime");
+        assert_eq!(sent[4]["chars"][2]["hp"]["max"], 10);
+        // Running out of input is as good as quitting.
+        assert!(!talk(&b, "").0);
+    }
+
+    #[test]
+    fn events_and_results() {
+        let b = test_field();
+        let e = event(&b, &BattleEvent::Attacked(0, 2));
+        assert_eq!(e["text"], "Ragnar attacked Slime!");
+        assert_eq!(e["event"]["Attacked"][1], 2);
+        assert_eq!(result(BattleStatus::PlayerVictory, &[])["winner"], "PLAYER");
+        assert!(result(BattleStatus::Continuing, &[])["winner"].is_null());
+    }
+}
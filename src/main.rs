@@ -1,171 +1,986 @@
+use std::env;
 use std::io;
+use std::process;
+use std::time::Duration;
 
 extern crate rand;
 extern crate rustdragon;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "tui")]
+extern crate termion;
 
 use rustdragon::character::*;
 use rustdragon::battlefield::*;
 use rustdragon::action::*;
-use rustdragon::battle_generator;
+use rustdragon::battle::*;
+use rustdragon::campaign::*;
+use rustdragon::shop::*;
+use rustdragon::item::Item;
+use rustdragon::command::*;
+use rustdragon::overworld::*;
+use rustdragon::loot::*;
+use rustdragon::event::BattleEvent;
+use rustdragon::battle_generator::{self, Difficulty};
+use rustdragon::net::*;
+use rustdragon::scenario::{Round, Scenario};
+use rustdragon::machine::{self, Machine};
+use rustdragon::dice;
+use rustdragon::timeline::*;
+#[cfg(feature = "tui")]
+use rustdragon::tui::{Tui, TermionBackend};
 
+const DEFAULT_ADDR: &str = "127.0.0.1:4242";
+/// How many on each side of a quick battle, if you only pick some of it.
+const DEFAULT_PARTY_SIZE: usize = 4;
+const DEFAULT_MONSTERS: usize = 3;
 
-enum BattleStatus {
-    PlayerVictory,
-    MonsterVictory,
-    Continuing, /* PlayersFled,
-                 * MonstersFled */
+fn print_menu(items: &[String]) {
+    for (i, item) in items.iter().enumerate() {
+        println!(" {}) {}", i + 1, item);
+    }
+    println!(" 0) Back");
 }
 
-fn print_possible_actions() {
-    println!(" 1) Attack");
-    println!(" 2) Defend");
+/// Reads a menu choice.
+/// The menus are numbered from 1, and 0 means go back.
+fn read_input() -> Option<Input> {
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input) {
+        // Nobody's ever going to answer, so just give up.
+        Ok(0) | Err(_) => process::exit(0),
+        Ok(_) => (),
+    }
+    match input.trim().parse::<usize>() {
+        Ok(0) => Some(Input::Cancel),
+        Ok(n) => Some(Input::Choose(n - 1)),
+        Err(_) => None,
+    }
 }
 
-fn read_attack(field: &Battlefield, i: CharSpecifier) -> Action {
-    // Print out the targets to attack
-    println!("Attack what?");
-    let living_monsters = field.get_team_enumerate(Team::Monster)
-        .filter(|&(_, chr)| chr.is_alive())
-        .collect::<Vec<(CharSpecifier, &Character)>>();
-    let mut j = 0;
+/// Walks the player through the menus until they've
+/// given orders to everyone in `entry`.
+fn read_actions(field: &Battlefield, mut entry: CommandEntry, actions: &mut Vec<Action>) {
+    loop {
+        let items: Vec<String> = match entry.prompt(field) {
+            Prompt::Command(actor, commands) => {
+                println!("Input action for {}", field.get(actor).unwrap().name);
+                commands.iter().map(|c| c.to_string()).collect()
+            }
+            Prompt::SubMenu(_, cmd, options) => {
+                println!("{} which?", cmd);
+                options
+            }
+            Prompt::Target(_, cmd, targets) => {
+                println!("{} what?", cmd);
+                targets.iter().map(|t| t.describe(field)).collect()
+            }
+            Prompt::Confirm => {
+                println!("Ready?");
+                vec![String::from("Fight!")]
+            }
+            Prompt::Done => break,
+        };
+        print_menu(&items);
+        let accepted = match read_input() {
+            Some(input) => entry.input(field, input),
+            None => false,
+        };
+        if !accepted {
+            println!("Please enter a valid option.");
+        }
+    }
+    actions.extend_from_slice(entry.actions());
+}
+
+fn read_player_actions(field: &Battlefield, actions: &mut Vec<Action>) {
+    read_actions(field, CommandEntry::new(field, Team::Player), actions);
+}
+
+/// Gets orders from another program, as JSON lines on stdin.
+/// Everyone in the party gets asked if `actors` is None.
+/// False if they've quit or hung up, so there's nobody left to play.
+fn read_machine_actions(field: &Battlefield,
+                        actors: Option<&[CharSpecifier]>,
+                        actions: &mut Vec<Action>)
+                        -> bool {
+    let stdin = io::stdin();
+    let mut machine = Machine::new(stdin.lock(), io::stdout());
+    let going = match actors {
+        Some(actors) => machine.read_actions(field, actors, actions),
+        None => machine.read_party_actions(field, actions),
+    };
+    going.unwrap_or(false)
+}
+
+/// Tells the other program the battle's over without anyone winning,
+/// for when it's quit partway.
+fn show_quit() {
+    println!("{}", machine::result(BattleStatus::Continuing, &[]));
+}
 
-    for &(_, m) in living_monsters.iter() {
-        j += 1;
-        println!(" {}) {}", j, m.name);
+fn save_recording(scenario: &Scenario, options: &Options) {
+    let path = options.record.as_ref().unwrap();
+    if let Err(e) = scenario.save_to_file(path) {
+        eprintln!("Couldn't save the recording to {}: {}", path, e);
     }
+}
 
-    // Read a numerical string and match it to a target
-    let mut input = String::new();
-    let _ = io::stdin().read_line(&mut input);
-    match input.trim().parse::<usize>() {
-        Ok(target) if target > 0 && target <= living_monsters.len() => {
-            let (idx, _) = living_monsters[target - 1];
-            Action::Attack(i, idx)
+/// Shows the field at the start of a round.
+fn show_field(field: &mut Battlefield, options: &Options) {
+    if options.output == Some(Output::Json) {
+        println!("{}", machine::state(field));
+        return;
+    }
+    println!();
+    println!("{}", field);
+    if field.preview_turn_order {
+        println!("{}", preview_turn_order(field).describe(field));
+    }
+}
+
+fn show_events(field: &Battlefield, events: &[BattleEvent], options: &Options) {
+    for e in events {
+        if options.output == Some(Output::Json) {
+            println!("{}", machine::event(field, e));
+        } else {
+            println!("{}", e.describe(field));
         }
-        _ => {
-            println!("Please enter a valid option!");
-            read_attack(field, i)
+    }
+}
+
+/// Says who won, and hands out the loot if it was the party.
+fn show_result(field: &mut Battlefield, status: BattleStatus, options: &Options) {
+    let rewards = if status == BattleStatus::PlayerVictory {
+        claim_rewards(field).describe()
+    } else {
+        Vec::new()
+    };
+    if options.output == Some(Output::Json) {
+        println!("{}", machine::result(status, &rewards));
+    } else if status == BattleStatus::PlayerVictory {
+        println!("Victory!");
+        for line in rewards {
+            println!("{}", line);
         }
+        println!();
+    } else {
+        println!("Horrible, crushing defeat!\n");
     }
 }
 
-fn read_player_action(field: &Battlefield, i: CharSpecifier) -> Action {
-    print_possible_actions();
-    // This UI really needs a state machine.
-    // We'll just implement it the simple and dumb way.
-    let mut input = String::new();
-    let _ = io::stdin().read_line(&mut input);
+/// Returns None if whoever's playing quit partway.
+fn mainloop(field: &mut Battlefield, options: &Options) -> Option<BattleStatus> {
+    let mut recording = options.record
+        .as_ref()
+        .map(|_| Scenario::starting_from(options.seed.unwrap_or(0), field));
+    let mut actions = Vec::new();
+    let mut events = Vec::new();
+    loop {
+        // This has to happen before printing out the field,
+        // since it happens at the beginning of the turn and we
+        // don't want to print out-of-date info.
+        tick_buffs(field);
+        show_field(field, options);
 
-    match input.trim().parse::<usize>() {
-        Ok(1) => read_attack(field, i),
-        Ok(2) => Action::Defend(i),
-        _res => {
-            println!("Please enter a valid option.");
-            read_player_action(field, i)
+        actions.clear();
+        events.clear();
+        if options.auto {
+            decide_party_actions(field, &mut actions);
+        } else if options.output == Some(Output::Json) {
+            if !read_machine_actions(field, None, &mut actions) {
+                // Whatever got played so far still gets kept.
+                if let Some(ref scenario) = recording {
+                    save_recording(scenario, options);
+                }
+                show_quit();
+                return None;
+            }
+        } else {
+            read_player_actions(field, &mut actions);
+        }
+        decide_monster_actions(field, &mut actions);
+        // Each recorded round gets its own seed, so it plays back the
+        // same no matter what else used the dice in between.
+        let round = recording.as_ref().map(|_| {
+            let seed = dice::random();
+            dice::seed(seed);
+            Round {
+                seed: Some(seed),
+                actions: actions.clone(),
+                events: None,
+            }
+        });
+        let status = run_turn(field, &mut actions, &mut events);
+        show_events(field, &events, options);
+        if let (Some(scenario), Some(mut round)) = (recording.as_mut(), round) {
+            round.events = Some(events.iter().map(|e| e.describe(field)).collect());
+            scenario.rounds.push(round);
+            if status != BattleStatus::Continuing {
+                scenario.result = Some(status);
+            }
+            // Saved every round, in case they quit partway.
+            save_recording(scenario, options);
+        }
+        if status != BattleStatus::Continuing {
+            show_result(field, status, options);
+            return Some(status);
         }
     }
 }
 
-fn read_player_actions(field: &Battlefield, actions: &mut Vec<Action>) {
-    let living_players = field.get_team_enumerate(Team::Player)
-        .filter(|&(_, chr)| chr.is_alive());
 
-    for (i, c) in living_players {
-        println!("Input action for {}", c.name);
-        let action = read_player_action(field, i);
-        actions.push(action);
+/// Like `mainloop`, but a character at a time instead of in rounds.
+fn ctb_mainloop(field: &mut Battlefield, options: &Options) -> Option<BattleStatus> {
+    let mut timeline = Timeline::new(field);
+    let mut actions = Vec::new();
+    let mut events = Vec::new();
+    loop {
+        actions.clear();
+        events.clear();
+        let actor = timeline.next_actor(field, &mut events);
+        show_events(field, &events, options);
+        events.clear();
+        let actor = match actor {
+            Some(actor) => actor,
+            // Either the end of the round settled it, or nobody can do
+            // anything, which is as over as it's going to get.
+            None => {
+                let status = battle_status(field);
+                if status != BattleStatus::Continuing {
+                    show_result(field, status, options);
+                }
+                return Some(status);
+            }
+        };
+        let chr = field.get(actor).unwrap();
+        if let Some(action) = charged_action(field, actor) {
+            actions.push(action);
+        } else if chr.team == Team::Player && !chr.is_temporary() && !options.auto {
+            if options.output == Some(Output::Json) {
+                println!("{}", machine::state(field));
+                if !read_machine_actions(field, Some(&[actor]), &mut actions) {
+                    show_quit();
+                    return None;
+                }
+            } else {
+                println!();
+                println!("{}", field);
+                let upcoming: Vec<&str> = timeline.upcoming(field, 5)
+                    .iter()
+                    .map(|&c| field.get(c).unwrap().name.as_str())
+                    .collect();
+                println!("Next up: {}", upcoming.join(", "));
+                read_actions(field, CommandEntry::for_actors(field, &[actor]), &mut actions);
+            }
+        } else {
+            decide_actions(field, &[actor], &mut actions);
+        }
+        let mut status = BattleStatus::Continuing;
+        for action in &actions {
+            status = take_turn(field, action, &mut events);
+        }
+        show_events(field, &events, options);
+        if status != BattleStatus::Continuing {
+            show_result(field, status, options);
+            return Some(status);
+        }
     }
 }
 
-fn decide_monster_actions(field: &Battlefield, actions: &mut Vec<Action>) {
-    let living_monsters = field.get_team_enumerate(Team::Monster)
-        .filter(|&(_, chr)| chr.is_alive());
 
-    for (i, _) in living_monsters {
-        let living_players = field.get_team_enumerate(Team::Player)
-            .filter(|&(_, chr)| chr.is_alive());
-        let mut rng = rand::thread_rng();
-        let sample = rand::sample(&mut rng, living_players, 1);
-        // We always check for victory before each action, so,
-        // there should always be at least opponent available to
-        // choose from.
-        let (targetidx, _) = sample[0];
-        let action = Action::Attack(i, targetidx);
-        actions.push(action);
+#[cfg(feature = "tui")]
+fn tui_mainloop(field: &mut Battlefield, ctb: bool) -> Option<BattleStatus> {
+    let result = TermionBackend::new().and_then(|backend| {
+        let mut tui = Tui::new(backend);
+        let status = if ctb {
+            tui.run_battle_ctb(field)?
+        } else {
+            tui.run_battle(field)?
+        };
+        // Leave the final screen up until they've seen it.
+        tui.wait_for_key(field, None)?;
+        Ok(status)
+    });
+    match result {
+        Ok(status) => Some(status),
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => None,
+        Err(e) => {
+            println!("Terminal error: {}", e);
+            None
+        }
     }
 }
 
+/// Fights a battle out with whichever frontend makes sense.
+/// Returns None if the player quit partway.
+fn fight(field: &mut Battlefield, options: &Options) -> Option<BattleStatus> {
+    options.difficulty.apply(field);
+    field.preview_turn_order = options.preview_order;
+    #[cfg(feature = "tui")]
+    {
+        let tui = match options.output {
+            Some(output) => output == Output::Tui,
+            // Only go full-screen if there's actually a terminal to do it on;
+            // if output is being piped somewhere, plain text is more useful.
+            None => !options.auto && options.record.is_none() && termion::is_tty(&io::stdout()),
+        };
+        if tui {
+            return tui_mainloop(field, options.ctb);
+        }
+    }
+    if options.ctb {
+        ctb_mainloop(field, options)
+    } else {
+        mainloop(field, options)
+    }
+}
 
-fn tick_buffs(field: &mut Battlefield) {
-    for c in field.chars.iter_mut() {
-        c.tick_buffs()
+/// Asks the player to pick from a menu until they give a
+/// sensible answer.  None means they backed out.
+fn choose(items: &[String]) -> Option<usize> {
+    loop {
+        print_menu(items);
+        match read_input() {
+            Some(Input::Choose(n)) if n < items.len() => return Some(n),
+            Some(Input::Cancel) => return None,
+            _ => println!("Please enter a valid option."),
+        }
     }
 }
 
-/// Runs a single turn in the battle.
-/// It takes a battlefield state, and a list of actions
-/// and applies the actions in the proper order.
-/// It returns a battle status.
-fn run_turn(field: &mut Battlefield, actions: &mut Vec<Action>) -> BattleStatus {
-    // We're going to want a sort-actions step, where we order the actions
-    // by priority and character speed and such (defend's always take effect first, etc)
-    // and THEN execute them.
-    order_actions(field, actions);
-    for action in actions {
-        // If the battle is over, we stop where we are!
-        // Partially 'cause any remaining actions will be invalid.
-        if field.team_victorious(Team::Player) {
-            return BattleStatus::PlayerVictory;
-        } else if field.team_victorious(Team::Monster) {
-            return BattleStatus::MonsterVictory;
+const SAVE_FILE: &str = "rustdragon.sav";
+
+/// Plays through a campaign, saving before every stop.
+fn play_campaign(mut campaign: Campaign, options: &Options) {
+    loop {
+        if let Err(e) = campaign.save_to_file(SAVE_FILE) {
+            println!("Couldn't save the game: {}", e);
+        }
+        let stop = match campaign.stop() {
+            Some(stop) => stop,
+            None => {
+                println!("The Rust Dragon is no more!  Peace returns to the land.");
+                return;
+            }
+        };
+        println!();
+        for chr in &campaign.party {
+            println!("Level {} {}", chr.level, chr);
+        }
+        println!("Gold: {}", campaign.bag.gold);
+        println!();
+        println!("{}", stop);
+        match stop {
+            Stop::Battle | Stop::Boss => {
+                let mut field = campaign.battlefield().unwrap();
+                match fight(&mut field, options) {
+                    Some(BattleStatus::PlayerVictory) => (),
+                    Some(_) => {
+                        println!("Game over.  Continue to try again from your last save.");
+                        return;
+                    }
+                    None => return,
+                }
+                for line in campaign.finish_battle(&field) {
+                    println!("{}", line);
+                }
+            }
+            Stop::Inn => {
+                println!("It's {} gold for the night.", campaign.inn().price(&campaign.party));
+                if choose(&[String::from("Stay the night")]).is_some() {
+                    match campaign.rest() {
+                        Ok(()) => println!("Everyone feels much better."),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                campaign.leave();
+            }
+            Stop::Shop => {
+                shop(&mut campaign);
+                campaign.leave();
+            }
         }
+    }
+}
 
-        run_action(field, &action);
+/// Wanders around the map with a fresh party until they
+/// lose, or the player's had enough.
+fn explore(mut campaign: Campaign, options: &Options) {
+    let map = match options.map {
+        Some(ref path) => Map::load(path),
+        None => Map::parse(DEFAULT_MAP),
+    };
+    let map = match map {
+        Ok(map) => map,
+        Err(e) => {
+            println!("Bad map: {}", e);
+            return;
+        }
+    };
+    let mut world = Overworld::new(map);
+    let directions = Direction::all();
+    let names: Vec<String> = directions.iter().map(|d| d.to_string()).collect();
+    loop {
+        println!();
+        print!("{}", world.view(3));
+        println!("Gold: {}", campaign.bag.gold);
+        let dir = match choose(&names) {
+            Some(n) => directions[n],
+            None => return,
+        };
+        match world.step(dir) {
+            Happening::Walked => (),
+            Happening::Blocked => println!("You can't go that way."),
+            Happening::Encounter(monsters) => {
+                println!("Monsters approach!");
+                let mut field = campaign.encounter(&monsters);
+                match fight(&mut field, options) {
+                    Some(BattleStatus::PlayerVictory) => (),
+                    Some(_) => {
+                        println!("Game over.");
+                        return;
+                    }
+                    None => return,
+                }
+                for line in campaign.take_back(&field) {
+                    println!("{}", line);
+                }
+            }
+            Happening::Town => {
+                println!("You come to a town.");
+                for chr in &campaign.party {
+                    println!("Level {} {}", chr.level, chr);
+                }
+                loop {
+                    let price = campaign.inn().price(&campaign.party);
+                    match choose(&[format!("Inn ({} gold)", price), String::from("Shop")]) {
+                        Some(0) => {
+                            match campaign.rest() {
+                                Ok(()) => println!("Everyone feels much better."),
+                                Err(e) => println!("{}", e),
+                            }
+                        }
+                        Some(_) => shop(&mut campaign),
+                        None => break,
+                    }
+                }
+            }
+            Happening::Stairs(floor) => println!("You take the stairs to floor {}.", floor),
+            Happening::Chest(treasure) => {
+                println!("You open the chest.  Inside is {}!", treasure);
+                treasure.give(&mut campaign.bag);
+            }
+            Happening::EmptyChest => println!("The chest is empty."),
+        }
     }
+}
 
-    // Check again, juuuuust in case that last action finished
-    // something off.
-    if field.team_victorious(Team::Player) {
-        return BattleStatus::PlayerVictory;
-    } else if field.team_victorious(Team::Monster) {
-        return BattleStatus::MonsterVictory;
+fn shop(campaign: &mut Campaign) {
+    let shop = campaign.shop();
+    loop {
+        println!("You have {} gold.  What'll it be?", campaign.bag.gold);
+        match choose(&[String::from("Buy"), String::from("Sell")]) {
+            Some(0) => buy(&shop, campaign),
+            Some(_) => sell(&shop, campaign),
+            None => return,
+        }
     }
+}
 
-    field.increment_round();
-    BattleStatus::Continuing
+fn buy(shop: &Shop, campaign: &mut Campaign) {
+    let items: Vec<String> = shop.stock
+        .iter()
+        .map(|&item| format!("{} - {} gold", item, shop.price(item)))
+        .collect();
+    loop {
+        println!("You have {} gold.  Buy what?", campaign.bag.gold);
+        let item = match choose(&items) {
+            Some(n) => shop.stock[n],
+            None => return,
+        };
+        match shop.buy(&mut campaign.bag, item) {
+            Ok(()) => {
+                println!("Thank you!");
+                if item.slot().is_some() {
+                    offer_to_equip(campaign, item);
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
 }
 
-fn mainloop(mut field: Battlefield) {
-    let mut actions = Vec::new();
+/// Asks who should put on something they just bought, showing
+/// how it'd change things for each of them.
+fn offer_to_equip(campaign: &mut Campaign, item: Item) {
+    println!("Equip the {} now?", item);
+    let slot = item.slot().unwrap();
+    let who: Vec<String> = campaign.party
+        .iter()
+        .map(|chr| {
+            let current = chr.equipped
+                .get(&slot)
+                .map(|i| i.to_string())
+                .unwrap_or_else(|| String::from("nothing"));
+            format!("{} ({}, instead of {})", chr.name, chr.equip_change(item), current)
+        })
+        .collect();
+    if let Some(n) = choose(&who) {
+        equip_from(&mut campaign.bag, &mut campaign.party[n], item);
+        println!("{} puts on the {}.", campaign.party[n].name, item);
+    }
+}
+
+fn sell(shop: &Shop, campaign: &mut Campaign) {
     loop {
-        // This has to happen before printing out the field,
-        // since it happens at the beginning of the turn and we
-        // don't want to print out-of-date info.
-        tick_buffs(&mut field);
+        let items: Vec<(Item, u32)> = campaign.bag.items().to_vec();
+        let labels: Vec<String> = items.iter()
+            .map(|&(item, count)| format!("{} x{} - {} gold", item, count, shop.offer(item)))
+            .collect();
+        println!("You have {} gold.  Sell what?", campaign.bag.gold);
+        let item = match choose(&labels) {
+            Some(n) => items[n].0,
+            None => return,
+        };
+        match shop.sell(&mut campaign.bag, item) {
+            Ok(gold) => println!("Sold for {} gold.", gold),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
 
-        println!("");
-        println!("{}", field);
 
-        actions.clear();
-        read_player_actions(&field, &mut actions);
-        decide_monster_actions(&field, &mut actions);
-        match run_turn(&mut field, &mut actions) {
-            BattleStatus::PlayerVictory => {
-                println!("Victory!\n");
-                break;
+/// Hosts a battle for other people to connect to.
+fn serve(addr: &str, mode: Mode) {
+    let config = ServerConfig {
+        mode,
+        clients: 2,
+        round_timeout: Duration::from_secs(60),
+    };
+    let server = match Server::bind(addr, config) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Can't listen on {}: {}", addr, e);
+            return;
+        }
+    };
+    println!("Waiting for {} players on {}...", config.clients, addr);
+    let mut field = battle_generator::generate();
+    match server.run(&mut field) {
+        Ok(BattleStatus::PlayerVictory) => println!("The players won."),
+        Ok(_) => println!("The players lost."),
+        Err(e) => println!("Battle abandoned: {}", e),
+    }
+}
+
+/// Joins someone else's battle.
+fn connect(addr: &str, name: &str) {
+    let mut client = match Client::connect(addr, name) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Can't connect to {}: {}", addr, e);
+            return;
+        }
+    };
+    println!("Connected!  Waiting for the battle to start...");
+    loop {
+        match client.next_message() {
+            Ok(ServerMessage::Orders(round, chars)) => {
+                println!();
+                println!("{}", client.field);
+                let mut actions = Vec::new();
+                let entry = CommandEntry::for_actors(&client.field, &chars);
+                read_actions(&client.field, entry, &mut actions);
+                let mut messages: Vec<ClientMessage> = actions.into_iter()
+                    .map(|a| ClientMessage::Action(round, a))
+                    .collect();
+                messages.push(ClientMessage::Done(round));
+                for m in &messages {
+                    if let Err(e) = client.send(m) {
+                        println!("Lost connection: {}", e);
+                        return;
+                    }
+                }
+            }
+            Ok(ServerMessage::Event(e)) => println!("{}", e),
+            Ok(ServerMessage::Error(e)) => println!("Server says: {}", e),
+            Ok(ServerMessage::Result(winner)) => {
+                let ours = client.chars
+                    .first()
+                    .and_then(|&c| client.field.get(c))
+                    .map(|c| c.team);
+                if ours.is_some_and(|t| client.field.relations.allied(t, winner)) {
+                    println!("Victory!\n");
+                } else {
+                    println!("Horrible, crushing defeat!\n");
+                }
+                return;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                println!("Lost connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// What to do, going by the first word on the command line.
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Play,
+    Battle,
+    Explore,
+    Replay(String),
+    Server(String, Mode),
+    Connect(String, String),
+    Help,
+}
+
+/// How to show the battle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Output {
+    Plain,
+    Tui,
+    /// One JSON object per line, for other programs to read.
+    Json,
+}
+
+/// Everything that can be set from the command line.
+#[derive(Debug, Clone)]
+struct Options {
+    command: Command,
+    seed: Option<u64>,
+    /// None means the quick battle picks for itself.
+    party_size: Option<usize>,
+    monster_count: Option<usize>,
+    heroes: Vec<String>,
+    monsters: Vec<String>,
+    map: Option<String>,
+    difficulty: Difficulty,
+    /// The computer plays the party too.
+    auto: bool,
+    record: Option<String>,
+    /// None means tui if there's a terminal for it, plain if not.
+    output: Option<Output>,
+    ctb: bool,
+    preview_order: bool,
+}
+
+fn help() {
+    println!("Usage: rustdragon [OPTIONS] [COMMAND]");
+    println!();
+    println!("Commands:");
+    println!("  play                     title menu: new game, continue, quick battle or explore (the default)");
+    println!("  battle                   go straight to a quick battle");
+    println!("  explore                  wander the map with a fresh party");
+    println!("  server [ADDR] [coop]     host a two-player battle (default {})", DEFAULT_ADDR);
+    println!("  connect ADDR [NAME]      join a hosted battle");
+    println!();
+    println!("Options:");
+    println!("  --seed N                 roll the same dice every time");
+    println!("  --party-size N           how many heroes in a quick battle (default {})",
+             DEFAULT_PARTY_SIZE);
+    println!("  --monsters N             how many monsters in a quick battle (default {})",
+             DEFAULT_MONSTERS);
+    println!("  --hero NAME              put this hero in the quick battle; can be given more than once");
+    println!("  --monster NAME           put this monster in the quick battle; can be given more than once");
+    println!("  --map FILE               explore this map instead of the built-in one");
+    println!("  --difficulty LEVEL       easy, normal or hard (default normal)");
+    println!("  --auto                   the computer plays the party too");
+    println!("  --record FILE            write the quick battle down as a scenario file");
+    println!("  --replay FILE            play back a recording or scenario file, and check it");
+    println!("                           comes out the same");
+    println!("  --output MODE            plain, tui or json (default tui if there's a terminal);");
+    println!("                           with json and no --auto, orders are read as JSON lines");
+    println!("                           from stdin, as described in src/machine.rs");
+    println!("  --ctb                    one character at a time instead of in rounds");
+    println!("  --preview-order          show who goes when before each round");
+    println!("  -h, --help               this");
+    println!();
+    println!("For example, `rustdragon battle --seed 5 --auto --output json` fights a");
+    println!("battle on its own and prints what happened as JSON lines.");
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Play,
+        seed: None,
+        party_size: None,
+        monster_count: None,
+        heroes: Vec::new(),
+        monsters: Vec::new(),
+        map: None,
+        difficulty: Difficulty::Normal,
+        auto: false,
+        record: None,
+        output: None,
+        ctb: false,
+        preview_order: false,
+    };
+    let mut replay = None;
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+        let number = |s: String| s.parse::<usize>().map_err(|_| format!("{} isn't a number", s));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Options { command: Command::Help, ..options }),
+            "--seed" => {
+                let seed = value()?;
+                options.seed = Some(seed.parse().map_err(|_| format!("{} isn't a seed", seed))?);
+            }
+            "--party-size" => options.party_size = Some(number(value()?)?),
+            "--monsters" => options.monster_count = Some(number(value()?)?),
+            "--hero" => options.heroes.push(value()?),
+            "--monster" => options.monsters.push(value()?),
+            "--map" => options.map = Some(value()?),
+            "--difficulty" => {
+                let name = value()?;
+                options.difficulty = Difficulty::from_name(&name)
+                    .ok_or_else(|| format!("difficulty should be easy, normal or hard, not {}", name))?;
+            }
+            "--auto" => options.auto = true,
+            "--record" => options.record = Some(value()?),
+            "--replay" => replay = Some(value()?),
+            "--output" => {
+                options.output = Some(match value()?.as_str() {
+                    "plain" => Output::Plain,
+                    "tui" => Output::Tui,
+                    "json" => Output::Json,
+                    other => return Err(format!("output should be plain, tui or json, not {}", other)),
+                })
+            }
+            "--ctb" => options.ctb = true,
+            "--preview-order" => options.preview_order = true,
+            other if other.starts_with('-') => return Err(format!("unknown option {}", other)),
+            other => words.push(other),
+        }
+    }
+
+    options.command = match words.as_slice() {
+        [] if replay.is_some() => Command::Replay(replay.take().unwrap()),
+        [] | ["play"] => Command::Play,
+        ["battle"] => Command::Battle,
+        ["explore"] => Command::Explore,
+        ["server"] => Command::Server(String::from(DEFAULT_ADDR), Mode::Versus),
+        ["server", addr] => Command::Server(addr.to_string(), Mode::Versus),
+        ["server", addr, "coop"] => Command::Server(addr.to_string(), Mode::Coop),
+        ["connect", addr] => Command::Connect(addr.to_string(), String::from("Hero")),
+        ["connect", addr, name] => Command::Connect(addr.to_string(), name.to_string()),
+        _ => return Err(format!("don't know how to {}", words.join(" "))),
+    };
+    if replay.is_some() {
+        return Err(String::from("--replay doesn't go with a command"));
+    }
+
+    // Sort out the combinations that can't work.
+    let replaying = matches!(options.command, Command::Replay(_));
+    let fighting = options.command == Command::Battle || replaying;
+    if options.party_size == Some(0) || options.monster_count == Some(0) {
+        return Err(String::from("somebody has to be on each side"));
+    }
+    // Everyone named by --hero or --monster gets in, even past the count.
+    let heroes = options.heroes.len().max(options.party_size.unwrap_or(DEFAULT_PARTY_SIZE));
+    let monsters = options.monsters.len().max(options.monster_count.unwrap_or(DEFAULT_MONSTERS));
+    if heroes > DEFAULT_CAPACITY || monsters > DEFAULT_CAPACITY {
+        return Err(format!("there's only room for {} on each side", DEFAULT_CAPACITY));
+    }
+    if options.record.is_some() {
+        if options.command != Command::Battle {
+            return Err(String::from("--record only works for a quick battle"));
+        }
+        if options.ctb || options.preview_order {
+            return Err(String::from("--record can't do --ctb or --preview-order battles yet"));
+        }
+    }
+    match options.output {
+        Some(Output::Tui) if !cfg!(feature = "tui") => {
+            return Err(String::from("this rustdragon was built without the tui; \
+                                     rebuild with --features tui"));
+        }
+        Some(Output::Tui) if options.auto || options.record.is_some() || replaying => {
+            return Err(String::from("the tui can't do --auto, --record or --replay"));
+        }
+        Some(Output::Json) if !fighting => {
+            return Err(String::from("json output is only for quick battles and --replay"));
+        }
+        _ => (),
+    }
+    Ok(options)
+}
+
+/// A quick battle, made however the options say.
+fn quick_battle(options: &Options) {
+    let picky = !options.heroes.is_empty() || !options.monsters.is_empty() ||
+                options.party_size.is_some() || options.monster_count.is_some();
+    let mut field = if picky {
+        let field = battle_generator::custom(&options.heroes,
+                                             options.party_size.unwrap_or(DEFAULT_PARTY_SIZE),
+                                             &options.monsters,
+                                             options.monster_count.unwrap_or(DEFAULT_MONSTERS));
+        match field {
+            Ok(field) => field,
+            Err(e) => {
+                eprintln!("Can't set up that battle: {}", e);
+                process::exit(2);
             }
-            BattleStatus::MonsterVictory => {
-                println!("Horrible, crushing defeat!\n");
-                break;
+        }
+    } else {
+        battle_generator::generate()
+    };
+    fight(&mut field, options);
+}
+
+/// Plays back a recorded battle, or any other scenario file,
+/// and complains if it doesn't come out the way it's written down.
+fn replay(path: &str, options: &Options) {
+    let scenario = match Scenario::load(path) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("Can't load {}: {}", path, e);
+            process::exit(2);
+        }
+    };
+    let failures = scenario.play(|field, events| {
+        if options.output != Some(Output::Json) {
+            println!();
+        }
+        show_events(field, events, options);
+    });
+    if options.output == Some(Output::Json) {
+        println!("{}", json!({"type": "replay", "failures": failures}));
+    } else if failures.is_empty() {
+        println!();
+        println!("Everything went the way it was written down.");
+    } else {
+        println!();
+        for failure in &failures {
+            println!("{}", failure);
+        }
+    }
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn title(options: &Options) {
+    println!("Rustdragon");
+    let menu = [String::from("New game"),
+                String::from("Continue"),
+                String::from("Quick battle"),
+                String::from("Explore")];
+    match choose(&menu) {
+        Some(0) => play_campaign(Campaign::new(), options),
+        Some(1) => {
+            match Campaign::load_from_file(SAVE_FILE) {
+                Ok(campaign) => play_campaign(campaign, options),
+                Err(e) => println!("Couldn't load {}: {}", SAVE_FILE, e),
             }
-            _ => (),
         }
+        Some(2) => quick_battle(options),
+        Some(_) => explore(Campaign::new(), options),
+        None => (),
     }
 }
 
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("rustdragon: {}", e);
+            eprintln!("Try `rustdragon --help`.");
+            process::exit(2);
+        }
+    };
+    if let Some(seed) = options.seed {
+        dice::seed(seed);
+    }
+    match options.command {
+        Command::Help => help(),
+        Command::Play => title(&options),
+        Command::Battle => quick_battle(&options),
+        Command::Explore => explore(Campaign::new(), &options),
+        Command::Replay(ref path) => replay(path, &options),
+        Command::Server(ref addr, mode) => serve(addr, mode),
+        Command::Connect(ref addr, ref name) => connect(addr, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let b = battle_generator::generate();
-    mainloop(b);
+    fn parse(line: &str) -> Result<Options, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn commands_and_options() {
+        assert_eq!(parse("").unwrap().command, Command::Play);
+        assert_eq!(parse("--help").unwrap().command, Command::Help);
+        assert_eq!(parse("battle -h").unwrap().command, Command::Help);
+        assert_eq!(parse("server 0.0.0.0:1 coop").unwrap().command,
+                   Command::Server(String::from("0.0.0.0:1"), Mode::Coop));
+        assert_eq!(parse("--replay fight.txt").unwrap().command,
+                   Command::Replay(String::from("fight.txt")));
+
+        let options = parse("battle --seed 7 --party-size 2 --hero Alena --monster Slime \
+                             --difficulty hard --auto --record fight.txt --output json")
+            .unwrap();
+        assert_eq!(options.command, Command::Battle);
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.party_size, Some(2));
+        assert_eq!(options.heroes, vec![String::from("Alena")]);
+        assert_eq!(options.monsters, vec![String::from("Slime")]);
+        assert_eq!(options.difficulty, Difficulty::Hard);
+        assert!(options.auto);
+        assert_eq!(options.record, Some(String::from("fight.txt")));
+        assert_eq!(options.output, Some(Output::Json));
+        assert!(parse("battle --ctb --preview-order --output plain").is_ok());
+    }
+
+    #[test]
+    fn nonsense_gets_turned_away() {
+        assert!(parse("dance").is_err());
+        assert!(parse("--frobnicate").is_err());
+        assert!(parse("battle --seed").is_err());
+        assert!(parse("battle --seed lots").is_err());
+        assert!(parse("battle --difficulty nightmare").is_err());
+        assert!(parse("battle --output html").is_err());
+        assert!(parse("battle --replay fight.txt").is_err());
+    }
+
+    #[test]
+    fn sides_have_to_fit() {
+        assert!(parse("battle --party-size 0").is_err());
+        assert!(parse("battle --monsters 0").is_err());
+        assert!(parse(&format!("battle --monsters {}", DEFAULT_CAPACITY)).is_ok());
+        assert!(parse(&format!("battle --monsters {}", DEFAULT_CAPACITY + 1)).is_err());
+        // Named ones count even past --party-size, or the default.
+        let heroes = " --hero Ragnar".repeat(DEFAULT_CAPACITY + 1);
+        assert!(parse(&format!("battle --party-size 1{}", heroes)).is_err());
+        let slimes = " --monster Slime".repeat(DEFAULT_CAPACITY + 1);
+        assert!(parse(&format!("battle{}", slimes)).is_err());
+    }
+
+    #[test]
+    fn combinations_that_cant_work() {
+        assert!(parse("--record fight.txt").is_err());
+        assert!(parse("explore --record fight.txt").is_err());
+        assert!(parse("battle --record fight.txt --ctb").is_err());
+        assert!(parse("battle --record fight.txt --preview-order").is_err());
+        // Without the tui feature there's no tui at all.
+        assert!(parse("battle --output tui --auto").is_err());
+        assert!(parse("battle --output tui --record fight.txt").is_err());
+        assert!(parse("--replay fight.txt --output tui").is_err());
+        assert_eq!(parse("battle --output tui").is_ok(), cfg!(feature = "tui"));
+        assert!(parse("--replay fight.txt --output json").is_ok());
+        assert!(parse("explore --output json").is_err());
+        assert!(parse("play --output json").is_err());
+    }
 }
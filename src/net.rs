@@ -0,0 +1,1041 @@
+//! Multiplayer battles over TCP.
+//!
+//! A `Server` hosts the `Battlefield` and does all the actual fighting;
+//! clients just get told what's going on and send in orders for
+//! whichever characters they've been handed.  In `Mode::Versus` the
+//! first client gets the players and the second gets the monsters; in
+//! `Mode::Coop` the players get dealt out between the clients and the
+//! monsters are run by the computer as usual.
+//!
+//! # Protocol, version 10
+//!
+//! Plain text, one message per line, fields separated by single spaces.
+//! Names always go last on a line, since they can have spaces in them.
+//! Characters are referred to by their index in the battlefield, and
+//! teams are `PLAYER`, `MONSTER`, or `TEAM<n>` for anybody else.
+//!
+//! When a client connects the server says
+//!
+//! ```text
+//! RUSTDRAGON <version>
+//! ```
+//!
+//! and the client answers with
+//!
+//! ```text
+//! HELLO <version> <player name>
+//! ```
+//!
+//! If the versions don't match the server sends `ERROR <message>` and
+//! hangs up.  Otherwise it says which characters the client controls:
+//!
+//! ```text
+//! WELCOME <client id> <character>...
+//! ```
+//!
+//! Once everyone has joined, at the start of every round each client
+//! gets the whole state of the battle:
+//!
+//! ```text
+//! ROUND <round> <timeout in seconds>
+//! CHAR <character> <team> <FRONT|BACK> <hp> <max hp> <mp> <max mp> <tension> <name>
+//! ...one CHAR line per character...
+//! STANCE <team> <team> <ALLIED|NEUTRAL|HOSTILE>
+//! ...one STANCE line per pair of teams on the field...
+//! ORDERS <round> <character>...
+//! ```
+//!
+//! `ORDERS` lists that client's characters who need orders this round,
+//! which may be none of them.  The client answers with any number of
+//!
+//! ```text
+//! ACTION <round> ATTACK <character> <target>
+//! ACTION <round> DEFEND <character>
+//! ACTION <round> ROW <character>
+//! ACTION <round> CAST <character> <spell> <target>
+//! ACTION <round> ITEM <character> <LEAF> <target>
+//! ACTION <round> SUMMON <character> <which>
+//! ACTION <round> SKILL <character> <skill> <target>
+//! ACTION <round> PSYCHE <character>
+//! ```
+//!
+//! where `<spell>` is `ZING`, `KAZING`, `ACCELERATLE`, `DECELERATLE`,
+//! `SNOOZE` or `KABOOM`, `<skill>` is `FIRE_BREATH`, `TIME_BOMB`,
+//! `GIGASLASH`, `DOUBLE_SLASH`, `SWEEP`, `STEAL` or `WHISTLE`, and
+//! `<target>` is one of
+//!
+//! ```text
+//! <character>
+//! GROUP <character>      everyone in that character's group
+//! ALL_ENEMIES
+//! ALL_ALLIES
+//! SELF
+//! RANDOM <hits>
+//! ```
+//!
+//! and then
+//!
+//! ```text
+//! DONE <round>
+//! ```
+//!
+//! Anything the server doesn't like (including orders for a round that's
+//! already over) gets an `ERROR <message>` back and is otherwise ignored.
+//! Once every client is `DONE`, or the timeout runs out, anyone who
+//! didn't get orders just defends and the round gets fought out.  The
+//! server then tells everyone what happened:
+//!
+//! ```text
+//! EVENT <description>
+//! ```
+//!
+//! and if that finished the battle, after a few more `EVENT` lines for
+//! the loot if the players won,
+//!
+//! ```text
+//! RESULT <winning team>
+//! ```
+//!
+//! after which it hangs up.
+//!
+//! Version 2 added `STANCE`, and `TEAM<n>` teams.
+//! Version 3 added rows to `CHAR`, and `ACTION ROW`.
+//! Version 4 added group targets to `ACTION ATTACK`.
+//! Version 5 added `ACTION CAST` and `ACTION ITEM`.
+//! Version 6 added `ACTION SUMMON`.  Characters can now join partway
+//! through a battle, so the `CHAR` list can grow and `ORDERS` can name
+//! characters that weren't in `WELCOME`.
+//! Version 7 added the `ACCELERATLE` and `DECELERATLE` spells.
+//! Version 8 added `ACTION SKILL`, and the `SNOOZE` and `KABOOM` spells.
+//! Characters who are asleep or winding something up don't get named
+//! in `ORDERS`.
+//! Version 9 added tension to `CHAR`, `ACTION PSYCHE` and `GIGASLASH`.
+//! Version 10 added the `DOUBLE_SLASH`, `SWEEP`, `STEAL` and `WHISTLE`
+//! skills.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::bounded_number::BoundedNumber;
+use super::character::*;
+use super::battlefield::*;
+use super::action::*;
+use super::battle::*;
+use super::target::*;
+use super::spell::*;
+use super::skill::*;
+use super::item::*;
+use super::loot::*;
+
+pub const PROTOCOL_VERSION: u32 = 10;
+
+pub fn team_name(team: Team) -> String {
+    match team {
+        Team::Player => String::from("PLAYER"),
+        Team::Monster => String::from("MONSTER"),
+        Team::Other(n) => format!("TEAM{}", n),
+    }
+}
+
+pub fn parse_team(s: &str) -> Result<Team, String> {
+    match s {
+        "PLAYER" => Ok(Team::Player),
+        "MONSTER" => Ok(Team::Monster),
+        _ if s.starts_with("TEAM") => {
+            s[4..].parse().map(Team::Other).map_err(|_| format!("unknown team {}", s))
+        }
+        _ => Err(format!("unknown team {}", s)),
+    }
+}
+
+pub fn row_name(row: Row) -> &'static str {
+    match row {
+        Row::Front => "FRONT",
+        Row::Back => "BACK",
+    }
+}
+
+pub fn parse_row(s: &str) -> Result<Row, String> {
+    match s {
+        "FRONT" => Ok(Row::Front),
+        "BACK" => Ok(Row::Back),
+        _ => Err(format!("unknown row {}", s)),
+    }
+}
+
+fn stance_name(stance: Stance) -> &'static str {
+    match stance {
+        Stance::Allied => "ALLIED",
+        Stance::Neutral => "NEUTRAL",
+        Stance::Hostile => "HOSTILE",
+    }
+}
+
+fn parse_stance(s: &str) -> Result<Stance, String> {
+    match s {
+        "ALLIED" => Ok(Stance::Allied),
+        "NEUTRAL" => Ok(Stance::Neutral),
+        "HOSTILE" => Ok(Stance::Hostile),
+        _ => Err(format!("unknown stance {}", s)),
+    }
+}
+
+fn parse_num<T: ::std::str::FromStr>(s: Option<&str>) -> Result<T, String> {
+    let s = s.ok_or_else(|| String::from("missing field"))?;
+    s.parse().map_err(|_| format!("bad number {}", s))
+}
+
+fn spell_name(spell: Spell) -> &'static str {
+    match spell {
+        Spell::Zing => "ZING",
+        Spell::Kazing => "KAZING",
+        Spell::Acceleratle => "ACCELERATLE",
+        Spell::Deceleratle => "DECELERATLE",
+        Spell::Snooze => "SNOOZE",
+        Spell::Kaboom => "KABOOM",
+    }
+}
+
+fn parse_spell(s: Option<&str>) -> Result<Spell, String> {
+    let s = s.ok_or_else(|| String::from("missing spell"))?;
+    Spell::all()
+        .into_iter()
+        .find(|&spell| spell_name(spell) == s)
+        .ok_or_else(|| format!("unknown spell {}", s))
+}
+
+fn skill_name(skill: Skill) -> &'static str {
+    match skill {
+        Skill::FireBreath => "FIRE_BREATH",
+        Skill::TimeBomb => "TIME_BOMB",
+        Skill::Gigaslash => "GIGASLASH",
+        Skill::DoubleSlash => "DOUBLE_SLASH",
+        Skill::Sweep => "SWEEP",
+        Skill::Steal => "STEAL",
+        Skill::Whistle => "WHISTLE",
+    }
+}
+
+fn parse_skill(s: Option<&str>) -> Result<Skill, String> {
+    let s = s.ok_or_else(|| String::from("missing skill"))?;
+    Skill::all()
+        .into_iter()
+        .find(|&skill| skill_name(skill) == s)
+        .ok_or_else(|| format!("unknown skill {}", s))
+}
+
+pub fn item_name(item: Item) -> &'static str {
+    match item {
+        Item::WorldTreeLeaf => "LEAF",
+        Item::SmallMedal => "MEDAL",
+        Item::CopperSword => "COPPER_SWORD",
+        Item::IronSword => "IRON_SWORD",
+        Item::LeatherArmor => "LEATHER_ARMOR",
+        Item::ChainMail => "CHAIN_MAIL",
+    }
+}
+
+pub fn parse_item(s: Option<&str>) -> Result<Item, String> {
+    let s = s.ok_or_else(|| String::from("missing item"))?;
+    Item::all()
+        .into_iter()
+        .find(|&item| item_name(item) == s)
+        .ok_or_else(|| format!("unknown item {}", s))
+}
+
+fn parse_target(words: &[&str]) -> Result<Target, String> {
+    match words.first().cloned() {
+        Some("GROUP") => Ok(Target::Group(parse_num(words.get(1).cloned())?)),
+        Some("ALL_ENEMIES") => Ok(Target::AllEnemies),
+        Some("ALL_ALLIES") => Ok(Target::AllAllies),
+        Some("SELF") => Ok(Target::Myself),
+        Some("RANDOM") => Ok(Target::Random(parse_num(words.get(1).cloned())?)),
+        other => Ok(Target::Single(parse_num(other)?)),
+    }
+}
+
+fn target_name(target: Target) -> String {
+    match target {
+        Target::Single(c) => c.to_string(),
+        Target::Group(c) => format!("GROUP {}", c),
+        Target::AllEnemies => String::from("ALL_ENEMIES"),
+        Target::AllAllies => String::from("ALL_ALLIES"),
+        Target::Myself => String::from("SELF"),
+        Target::Random(n) => format!("RANDOM {}", n),
+    }
+}
+
+/// An action, written the way they are in `ACTION` lines,
+/// starting from the `ATTACK`, `DEFEND` or whatever.
+pub fn parse_action(words: &[&str]) -> Result<Action, String> {
+    let action = match words.first().cloned() {
+        Some("ATTACK") => {
+            Action::Attack(parse_num(words.get(1).cloned())?,
+                           parse_target(words.get(2..).unwrap_or(&[]))?)
+        }
+        Some("DEFEND") => Action::Defend(parse_num(words.get(1).cloned())?),
+        Some("ROW") => Action::ChangeRow(parse_num(words.get(1).cloned())?),
+        Some("CAST") => {
+            Action::Cast(parse_num(words.get(1).cloned())?,
+                         parse_spell(words.get(2).cloned())?,
+                         parse_target(words.get(3..).unwrap_or(&[]))?)
+        }
+        Some("ITEM") => {
+            Action::UseItem(parse_num(words.get(1).cloned())?,
+                            parse_item(words.get(2).cloned())?,
+                            parse_target(words.get(3..).unwrap_or(&[]))?)
+        }
+        Some("SUMMON") => {
+            Action::Summon(parse_num(words.get(1).cloned())?,
+                           parse_num(words.get(2).cloned())?)
+        }
+        Some("PSYCHE") => Action::PsycheUp(parse_num(words.get(1).cloned())?),
+        Some("SKILL") => {
+            Action::UseSkill(parse_num(words.get(1).cloned())?,
+                             parse_skill(words.get(2).cloned())?,
+                             parse_target(words.get(3..).unwrap_or(&[]))?)
+        }
+        _ => return Err(format!("unknown action {}", words.join(" "))),
+    };
+    Ok(action)
+}
+
+/// The other way around from `parse_action`.
+pub fn action_words(action: Action) -> String {
+    match action {
+        Action::Attack(from, to) => format!("ATTACK {} {}", from, target_name(to)),
+        Action::Defend(who) => format!("DEFEND {}", who),
+        Action::ChangeRow(who) => format!("ROW {}", who),
+        Action::Cast(who, spell, to) => {
+            format!("CAST {} {} {}", who, spell_name(spell), target_name(to))
+        }
+        Action::UseItem(who, item, to) => {
+            format!("ITEM {} {} {}", who, item_name(item), target_name(to))
+        }
+        Action::Summon(who, n) => format!("SUMMON {} {}", who, n),
+        Action::PsycheUp(who) => format!("PSYCHE {}", who),
+        Action::UseSkill(who, skill, to) => {
+            format!("SKILL {} {} {}", who, skill_name(skill), target_name(to))
+        }
+    }
+}
+
+fn parse_list(words: &[&str]) -> Result<Vec<CharSpecifier>, String> {
+    words.iter().map(|w| parse_num(Some(w))).collect()
+}
+
+fn write_list(f: &mut fmt::Formatter, list: &[CharSpecifier]) -> fmt::Result {
+    for c in list {
+        write!(f, " {}", c)?;
+    }
+    Ok(())
+}
+
+/// Everything the server can say.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    Version(u32),
+    Welcome(usize, Vec<CharSpecifier>),
+    Round(u32, u64),
+    /// Index, team, row, hp, max hp, mp, max mp, tension, name
+    Char(CharSpecifier, Team, Row, u32, u32, u32, u32, u32, String),
+    Stance(Team, Team, Stance),
+    Orders(u32, Vec<CharSpecifier>),
+    Event(String),
+    Result(Team),
+    Error(String),
+}
+
+impl ServerMessage {
+    pub fn parse(line: &str) -> Result<ServerMessage, String> {
+        let line = line.trim_end();
+        let mut words = line.splitn(2, ' ');
+        let keyword = words.next().unwrap_or("");
+        let rest = words.next().unwrap_or("");
+        let fields: Vec<&str> = rest.split(' ').filter(|w| !w.is_empty()).collect();
+        match keyword {
+            "RUSTDRAGON" => Ok(ServerMessage::Version(parse_num(fields.first().cloned())?)),
+            "WELCOME" if !fields.is_empty() => {
+                Ok(ServerMessage::Welcome(parse_num(Some(fields[0]))?, parse_list(&fields[1..])?))
+            }
+            "ROUND" => {
+                Ok(ServerMessage::Round(parse_num(fields.first().cloned())?,
+                                        parse_num(fields.get(1).cloned())?))
+            }
+            "CHAR" => {
+                let f: Vec<&str> = rest.splitn(9, ' ').collect();
+                if f.len() < 9 {
+                    return Err(String::from("not enough fields"));
+                }
+                Ok(ServerMessage::Char(parse_num(Some(f[0]))?,
+                                       parse_team(f[1])?,
+                                       parse_row(f[2])?,
+                                       parse_num(Some(f[3]))?,
+                                       parse_num(Some(f[4]))?,
+                                       parse_num(Some(f[5]))?,
+                                       parse_num(Some(f[6]))?,
+                                       parse_num(Some(f[7]))?,
+                                       String::from(f[8])))
+            }
+            "STANCE" if fields.len() == 3 => {
+                Ok(ServerMessage::Stance(parse_team(fields[0])?,
+                                         parse_team(fields[1])?,
+                                         parse_stance(fields[2])?))
+            }
+            "ORDERS" if !fields.is_empty() => {
+                Ok(ServerMessage::Orders(parse_num(Some(fields[0]))?, parse_list(&fields[1..])?))
+            }
+            "EVENT" => Ok(ServerMessage::Event(String::from(rest))),
+            "RESULT" => Ok(ServerMessage::Result(parse_team(rest)?)),
+            "ERROR" => Ok(ServerMessage::Error(String::from(rest))),
+            _ => Err(format!("unknown message {}", line)),
+        }
+    }
+}
+
+impl fmt::Display for ServerMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ServerMessage::Version(v) => write!(f, "RUSTDRAGON {}", v),
+            ServerMessage::Welcome(id, ref chars) => {
+                write!(f, "WELCOME {}", id)?;
+                write_list(f, chars)
+            }
+            ServerMessage::Round(round, timeout) => write!(f, "ROUND {} {}", round, timeout),
+            ServerMessage::Char(i, team, row, hp, maxhp, mp, maxmp, tension, ref name) => {
+                write!(f,
+                       "CHAR {} {} {} {} {} {} {} {} {}",
+                       i,
+                       team_name(team),
+                       row_name(row),
+                       hp,
+                       maxhp,
+                       mp,
+                       maxmp,
+                       tension,
+                       name)
+            }
+            ServerMessage::Stance(a, b, stance) => {
+                write!(f, "STANCE {} {} {}", team_name(a), team_name(b), stance_name(stance))
+            }
+            ServerMessage::Orders(round, ref chars) => {
+                write!(f, "ORDERS {}", round)?;
+                write_list(f, chars)
+            }
+            ServerMessage::Event(ref s) => write!(f, "EVENT {}", s),
+            ServerMessage::Result(team) => write!(f, "RESULT {}", team_name(team)),
+            ServerMessage::Error(ref s) => write!(f, "ERROR {}", s),
+        }
+    }
+}
+
+/// Everything a client can say.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    Hello(u32, String),
+    Action(u32, Action),
+    Done(u32),
+}
+
+impl ClientMessage {
+    pub fn parse(line: &str) -> Result<ClientMessage, String> {
+        let line = line.trim_end();
+        let words: Vec<&str> = line.split(' ').filter(|w| !w.is_empty()).collect();
+        match words.first().cloned() {
+            Some("HELLO") => {
+                let name = line.splitn(3, ' ').nth(2).unwrap_or("");
+                Ok(ClientMessage::Hello(parse_num(words.get(1).cloned())?, String::from(name)))
+            }
+            Some("ACTION") => {
+                let round = parse_num(words.get(1).cloned())?;
+                let action = parse_action(words.get(2..).unwrap_or(&[]))?;
+                Ok(ClientMessage::Action(round, action))
+            }
+            Some("DONE") => Ok(ClientMessage::Done(parse_num(words.get(1).cloned())?)),
+            _ => Err(format!("unknown message {}", line)),
+        }
+    }
+}
+
+impl fmt::Display for ClientMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientMessage::Hello(v, ref name) => write!(f, "HELLO {} {}", v, name),
+            ClientMessage::Action(round, action) => {
+                write!(f, "ACTION {} {}", round, action_words(action))
+            }
+            ClientMessage::Done(round) => write!(f, "DONE {}", round),
+        }
+    }
+}
+
+/// Who controls what.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Two clients, one for the party and one for everyone else.
+    Versus,
+    /// Any number of clients splitting up the party between them,
+    /// against computer-controlled monsters.
+    Coop,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServerConfig {
+    pub mode: Mode,
+    /// How many clients to wait for before starting.
+    pub clients: usize,
+    /// How long to wait for orders each round.
+    pub round_timeout: Duration,
+}
+
+/// One connected client, from the server's end.
+struct Connection {
+    id: usize,
+    stream: TcpStream,
+    connected: bool,
+    chars: Vec<CharSpecifier>,
+}
+
+impl Connection {
+    fn send(&mut self, msg: &ServerMessage) {
+        if self.connected && writeln!(self.stream, "{}", msg).is_err() {
+            self.connected = false;
+        }
+    }
+}
+
+/// What the reader threads send back: which client, and the line they
+/// sent, or None if they hung up.
+type Incoming = (usize, Option<String>);
+
+pub struct Server {
+    listener: TcpListener,
+    config: ServerConfig,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, config: ServerConfig) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Server { listener, config })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Splits up the characters between the clients.
+    fn assign_characters(&self, field: &Battlefield) -> Vec<Vec<CharSpecifier>> {
+        let mut assignments = vec![Vec::new(); self.config.clients];
+        if assignments.is_empty() {
+            return assignments;
+        }
+        match self.config.mode {
+            Mode::Versus => {
+                // Summoned allies always do their own thing.
+                for (i, chr) in field.chars.iter().enumerate().filter(|&(_, c)| !c.is_temporary()) {
+                    let client = if chr.team == Team::Player { 0 } else { 1 };
+                    if let Some(a) = assignments.get_mut(client) {
+                        a.push(i);
+                    }
+                }
+            }
+            Mode::Coop => {
+                let players = field.get_team_enumerate(Team::Player)
+                    .filter(|&(_, c)| !c.is_temporary())
+                    .map(|(i, _)| i);
+                for (n, i) in players.enumerate() {
+                    let client = n % assignments.len();
+                    assignments[client].push(i);
+                }
+            }
+        }
+        assignments
+    }
+
+    /// Waits for everyone to connect and say hello.
+    fn accept_clients(&self,
+                      field: &Battlefield,
+                      tx: &mpsc::Sender<Incoming>,
+                      rx: &mpsc::Receiver<Incoming>)
+                      -> io::Result<Vec<Connection>> {
+        let assignments = self.assign_characters(field);
+        let mut clients = Vec::new();
+        let mut next_id = 0;
+        while clients.len() < self.config.clients {
+            let (stream, _) = self.listener.accept()?;
+            let id = next_id;
+            next_id += 1;
+            let reader = stream.try_clone()?;
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(reader).lines() {
+                    match line {
+                        Ok(line) => {
+                            if tx.send((id, Some(line))).is_err() {
+                                return;
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                }
+                let _ = tx.send((id, None));
+            });
+
+            let mut conn = Connection {
+                id,
+                stream,
+                connected: true,
+                chars: assignments[clients.len()].clone(),
+            };
+            conn.send(&ServerMessage::Version(PROTOCOL_VERSION));
+            // Wait for this one to say hello.
+            // Nobody else should be saying anything yet.
+            let hello = loop {
+                match rx.recv() {
+                    Ok((from, line)) if from == id => break line,
+                    Ok(_) => (),
+                    Err(_) => return Err(io::Error::other("channel closed")),
+                }
+            };
+            match hello.map(|l| ClientMessage::parse(&l)) {
+                Some(Ok(ClientMessage::Hello(PROTOCOL_VERSION, _))) => {
+                    let welcome = ServerMessage::Welcome(clients.len(), conn.chars.clone());
+                    conn.send(&welcome);
+                    clients.push(conn);
+                }
+                Some(Ok(ClientMessage::Hello(v, _))) => {
+                    conn.send(&ServerMessage::Error(format!("unsupported protocol version {}",
+                                                            v)));
+                    let _ = conn.stream.shutdown(Shutdown::Both);
+                }
+                _ => {
+                    conn.send(&ServerMessage::Error(String::from("expected HELLO")));
+                    let _ = conn.stream.shutdown(Shutdown::Both);
+                }
+            }
+        }
+        Ok(clients)
+    }
+
+    /// Hosts a whole battle: waits for all the clients, then
+    /// runs rounds until somebody wins.
+    /// If every client disconnects partway through, gives up
+    /// with a `ConnectionAborted` error.
+    pub fn run(&self, field: &mut Battlefield) -> io::Result<BattleStatus> {
+        let (tx, rx) = mpsc::channel();
+        let mut clients = self.accept_clients(field, &tx, &rx)?;
+        let result = self.run_rounds(field, &mut clients, &rx);
+        for c in &clients {
+            let _ = c.stream.shutdown(Shutdown::Both);
+        }
+        result
+    }
+
+    fn run_rounds(&self,
+                  field: &mut Battlefield,
+                  clients: &mut [Connection],
+                  rx: &mpsc::Receiver<Incoming>)
+                  -> io::Result<BattleStatus> {
+        let mut actions = Vec::new();
+        let mut events = Vec::new();
+        loop {
+            tick_buffs(field);
+            let round = field.round;
+            actions.clear();
+            events.clear();
+
+            // Anyone who joined last round goes to whoever would've
+            // had them if they'd been there from the start.
+            for (c, assigned) in clients.iter_mut().zip(self.assign_characters(field)) {
+                for i in assigned {
+                    if !c.chars.contains(&i) {
+                        c.chars.push(i);
+                    }
+                }
+            }
+
+            // Tell everyone where things stand, and who they need
+            // to give orders to.
+            let mut waiting: Vec<Vec<CharSpecifier>> = Vec::new();
+            for c in clients.iter_mut() {
+                c.send(&ServerMessage::Round(round, self.config.round_timeout.as_secs()));
+                for (i, chr) in field.chars.iter().enumerate() {
+                    c.send(&ServerMessage::Char(i,
+                                                chr.team,
+                                                chr.row,
+                                                chr.hp.val,
+                                                chr.hp.max,
+                                                chr.mp.val,
+                                                chr.mp.max,
+                                                chr.tension.val,
+                                                chr.name.clone()));
+                }
+                let teams = field.teams();
+                for (n, &a) in teams.iter().enumerate() {
+                    for &b in &teams[n + 1..] {
+                        c.send(&ServerMessage::Stance(a, b, field.relations.stance(a, b)));
+                    }
+                }
+                let orders: Vec<CharSpecifier> = c.chars
+                    .iter()
+                    .cloned()
+                    .filter(|&i| field.get(i).unwrap().can_act())
+                    .collect();
+                c.send(&ServerMessage::Orders(round, orders.clone()));
+                waiting.push(orders);
+            }
+            let mut done: Vec<bool> = clients.iter()
+                .zip(waiting.iter())
+                .map(|(c, w)| !c.connected || w.is_empty())
+                .collect();
+
+            // Collect orders until everyone's done or we run out of time.
+            let deadline = Instant::now() + self.config.round_timeout;
+            while done.iter().any(|d| !d) {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                let (id, line) = match rx.recv_timeout(deadline - now) {
+                    Ok(incoming) => incoming,
+                    Err(_) => break,
+                };
+                let n = match clients.iter().position(|c| c.id == id) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let line = match line {
+                    Some(line) => line,
+                    None => {
+                        clients[n].connected = false;
+                        done[n] = true;
+                        continue;
+                    }
+                };
+                let reply = match ClientMessage::parse(&line) {
+                    Ok(ClientMessage::Done(r)) if r == round => {
+                        done[n] = true;
+                        None
+                    }
+                    Ok(ClientMessage::Action(r, action)) if r == round => {
+                        let source = action.source();
+                        let target = match action {
+                            Action::Attack(_, to) |
+                            Action::Cast(_, _, to) |
+                            Action::UseItem(_, _, to) |
+                            Action::UseSkill(_, _, to) => Some(to),
+                            Action::Defend(_) |
+                            Action::ChangeRow(_) |
+                            Action::Summon(..) |
+                            Action::PsycheUp(_) => None,
+                        };
+                        let problem = match (action, target) {
+                            (_, Some(Target::Single(to))) |
+                            (_, Some(Target::Group(to))) if field.get(to).is_none() => {
+                                Some(String::from("no such target"))
+                            }
+                            (Action::Cast(who, spell, _), _) if !field.get(who)
+                                .is_some_and(|chr| chr.knows_spell(spell)) => {
+                                Some(format!("{} doesn't know {}", who, spell))
+                            }
+                            (Action::UseSkill(who, skill, _), _) if !field.get(who)
+                                .is_some_and(|chr| chr.knows_skill(skill)) => {
+                                Some(format!("{} doesn't know {}", who, skill))
+                            }
+                            (Action::UseItem(_, item, _), _) if !item.usable_in_battle() => {
+                                Some(format!("{} is no use in a fight", item))
+                            }
+                            (Action::Summon(who, n), _) if field.get(who)
+                                .is_none_or(|chr| n >= chr.summons.len()) => {
+                                Some(format!("{} can't summon {}", who, n))
+                            }
+                            _ => None,
+                        };
+                        match waiting[n].iter().position(|&c| c == source) {
+                            Some(_) if problem.is_some() => problem,
+                            Some(w) => {
+                                waiting[n].remove(w);
+                                actions.push(action);
+                                None
+                            }
+                            None => Some(format!("no orders wanted for {}", source)),
+                        }
+                    }
+                    Ok(ClientMessage::Action(r, _)) | Ok(ClientMessage::Done(r)) => {
+                        Some(format!("too late for round {}", r))
+                    }
+                    Ok(ClientMessage::Hello(..)) => Some(String::from("already said hello")),
+                    Err(e) => Some(e),
+                };
+                if let Some(e) = reply {
+                    clients[n].send(&ServerMessage::Error(e));
+                }
+            }
+
+            if clients.iter().all(|c| !c.connected) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                          "everyone disconnected"));
+            }
+
+            // Anyone who didn't get orders in time just hunkers down.
+            for w in &waiting {
+                for &c in w {
+                    actions.push(Action::Defend(c));
+                }
+            }
+            // And the computer runs everyone nobody else is.
+            let unowned: Vec<CharSpecifier> = (0..field.chars.len())
+                .filter(|i| !clients.iter().any(|c| c.chars.contains(i)))
+                .collect();
+            decide_actions(field, &unowned, &mut actions);
+
+            let status = run_turn(field, &mut actions, &mut events);
+            for e in &events {
+                let msg = ServerMessage::Event(e.describe(field));
+                for c in clients.iter_mut() {
+                    c.send(&msg);
+                }
+            }
+            let winner = match status {
+                BattleStatus::PlayerVictory => Team::Player,
+                BattleStatus::MonsterVictory => Team::Monster,
+                BattleStatus::OtherVictory(team) => team,
+                BattleStatus::Continuing => continue,
+            };
+            if winner == Team::Player {
+                for line in claim_rewards(field).describe() {
+                    let msg = ServerMessage::Event(line);
+                    for c in clients.iter_mut() {
+                        c.send(&msg);
+                    }
+                }
+            }
+            for c in clients.iter_mut() {
+                c.send(&ServerMessage::Result(winner));
+            }
+            return Ok(status);
+        }
+    }
+}
+
+/// The client end of things.
+/// Keeps a copy of the battlefield up to date from what the server
+/// sends, so a frontend can show it and use it to build orders.
+pub struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    pub id: usize,
+    pub chars: Vec<CharSpecifier>,
+    pub field: Battlefield,
+}
+
+impl Client {
+    /// Connects and says hello.
+    pub fn connect<A: ToSocketAddrs>(addr: A, name: &str) -> io::Result<Client> {
+        let stream = TcpStream::connect(addr)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut client = Client {
+            stream,
+            reader,
+            id: 0,
+            chars: Vec::new(),
+            field: Battlefield::new(),
+        };
+        match client.next_message()? {
+            ServerMessage::Version(PROTOCOL_VERSION) => (),
+            ServerMessage::Version(v) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("server speaks protocol version {}", v)));
+            }
+            other => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("unexpected message {}", other)));
+            }
+        }
+        client.send(&ClientMessage::Hello(PROTOCOL_VERSION, String::from(name)))?;
+        match client.next_message()? {
+            ServerMessage::Welcome(id, chars) => {
+                client.id = id;
+                client.chars = chars;
+                Ok(client)
+            }
+            other => {
+                Err(io::Error::new(io::ErrorKind::InvalidData,
+                                   format!("unexpected message {}", other)))
+            }
+        }
+    }
+
+    pub fn send(&mut self, msg: &ClientMessage) -> io::Result<()> {
+        writeln!(self.stream, "{}", msg)
+    }
+
+    /// Reads the next message from the server,
+    /// updating our copy of the battlefield if it's about that.
+    pub fn next_message(&mut self) -> io::Result<ServerMessage> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "server hung up"));
+        }
+        let msg = ServerMessage::parse(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match msg {
+            ServerMessage::Round(round, _) => {
+                self.field = Battlefield::new();
+                self.field.round = round;
+            }
+            ServerMessage::Char(_, team, row, hp, maxhp, mp, maxmp, tension, ref name) => {
+                let mut chr = Character::new(name, team);
+                chr.row = row;
+                chr.hp = BoundedNumber::new(maxhp);
+                chr.hp.set(hp);
+                chr.mp = BoundedNumber::new(maxmp);
+                chr.mp.set(mp);
+                chr.tension += tension;
+                chr.knocked_out = hp == 0;
+                self.field.chars.push(chr);
+            }
+            ServerMessage::Stance(a, b, stance) => self.field.relations.set(a, b, stance),
+            _ => (),
+        }
+        Ok(msg)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: Mode, clients: usize, timeout_ms: u64) -> ServerConfig {
+        ServerConfig {
+            mode,
+            clients,
+            round_timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let server = vec![
+            ServerMessage::Version(3),
+            ServerMessage::Welcome(1, vec![0, 2]),
+            ServerMessage::Welcome(0, vec![]),
+            ServerMessage::Round(3, 30),
+            ServerMessage::Char(4, Team::Monster, Row::Front, 3, 10, 0, 10, 0, String::from("King Slime")),
+            ServerMessage::Char(5, Team::Other(3), Row::Back, 1, 1, 0, 0, 100, String::from("Guest")),
+            ServerMessage::Stance(Team::Player, Team::Other(3), Stance::Allied),
+            ServerMessage::Orders(3, vec![1]),
+            ServerMessage::Event(String::from("Slime perished!")),
+            ServerMessage::Result(Team::Player),
+            ServerMessage::Error(String::from("no such target")),
+        ];
+        for msg in server {
+            assert_eq!(ServerMessage::parse(&msg.to_string()), Ok(msg));
+        }
+        let client = vec![
+            ClientMessage::Hello(1, String::from("Simon the Great")),
+            ClientMessage::Action(2, Action::Attack(0, Target::Single(3))),
+            ClientMessage::Action(2, Action::Attack(0, Target::Group(3))),
+            ClientMessage::Action(2, Action::Attack(0, Target::Random(4))),
+            ClientMessage::Action(2, Action::Attack(0, Target::AllEnemies)),
+            ClientMessage::Action(2, Action::Cast(1, Spell::Kazing, Target::Single(0))),
+            ClientMessage::Action(2, Action::UseItem(1, Item::WorldTreeLeaf, Target::AllAllies)),
+            ClientMessage::Action(2, Action::Summon(0, 1)),
+            ClientMessage::Action(2, Action::UseSkill(0, Skill::FireBreath, Target::AllEnemies)),
+            ClientMessage::Action(2, Action::Defend(1)),
+            ClientMessage::Action(2, Action::ChangeRow(0)),
+            ClientMessage::Action(2, Action::PsycheUp(0)),
+            ClientMessage::Done(2),
+        ];
+        for msg in client {
+            assert_eq!(ClientMessage::parse(&msg.to_string()), Ok(msg));
+        }
+        assert!(ClientMessage::parse("ACTION 1 DANCE 0").is_err());
+        assert!(ServerMessage::parse("CHAR 1 PLAYER 10").is_err());
+    }
+
+    #[test]
+    fn coop_battle_on_localhost() {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        b.get_mut(0).unwrap().atk = 1000;
+        // Slow enough that Ragnar always gets the first hit in.
+        b.get_mut(1).unwrap().spd = 1;
+        let server = Server::bind("127.0.0.1:0", config(Mode::Coop, 1, 5000)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || server.run(&mut b));
+
+        let mut client = Client::connect(addr, "Tester").unwrap();
+        assert_eq!(client.chars, vec![0]);
+        let mut events = Vec::new();
+        loop {
+            match client.next_message().unwrap() {
+                ServerMessage::Orders(round, chars) => {
+                    assert_eq!(client.field.chars.len(), 2);
+                    for c in chars {
+                        client.send(&ClientMessage::Action(round, Action::Attack(c, Target::Single(1)))).unwrap();
+                    }
+                    client.send(&ClientMessage::Done(round)).unwrap();
+                }
+                ServerMessage::Event(e) => events.push(e),
+                ServerMessage::Result(team) => {
+                    assert_eq!(team, Team::Player);
+                    break;
+                }
+                ServerMessage::Error(e) => panic!("Server error: {}", e),
+                _ => (),
+            }
+        }
+        assert!(events.contains(&String::from("Slime perished!")));
+        let status = handle.join().unwrap().unwrap();
+        assert_eq!(status, BattleStatus::PlayerVictory);
+    }
+
+    #[test]
+    fn slowpokes_defend() {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        let server = Server::bind("127.0.0.1:0", config(Mode::Versus, 2, 100)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || server.run(&mut b));
+
+        let mut players = Client::connect(addr, "One").unwrap();
+        let monsters = Client::connect(addr, "Two").unwrap();
+        assert_eq!(players.chars, vec![0]);
+        assert_eq!(monsters.chars, vec![1]);
+
+        // Neither of them answer, so after the timeout
+        // the round happens anyway.
+        let mut events = Vec::new();
+        loop {
+            match players.next_message().unwrap() {
+                ServerMessage::Event(e) => events.push(e),
+                ServerMessage::Orders(2, _) => break,
+                _ => (),
+            }
+        }
+        assert!(events.contains(&String::from("Ragnar defended themselves!")));
+        assert!(events.contains(&String::from("Slime defended themselves!")));
+
+        // Orders for a round that's over get turned down.
+        players.send(&ClientMessage::Action(1, Action::Defend(0))).unwrap();
+        loop {
+            if let ServerMessage::Error(e) = players.next_message().unwrap() {
+                assert_eq!(e, "too late for round 1");
+                break;
+            }
+        }
+
+        drop(players);
+        drop(monsters);
+        let err = handle.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
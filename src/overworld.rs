@@ -0,0 +1,521 @@
+//! Walking around between fights.
+//!
+//! A `Map` is a stack of floors, each a grid of tiles with a matching
+//! grid of regions.  Each region has its own list of monsters and how
+//! often they jump out.  An `Overworld` is a party's spot on a map,
+//! and works out what happens every time they take a step; like
+//! `campaign`, it leaves the fighting and shopping to whoever's
+//! running it.
+//!
+//! Maps are plain text:
+//!
+//! ```text
+//! # comments, up until the first FLOOR
+//! REGION <digit> <steps between fights> <monster>, <monster>, ...
+//! FLOOR
+//! <a row of tiles>
+//! ...
+//! REGIONS
+//! <a row of region digits, the same size as the tiles>
+//! ...
+//! CHEST <floor> <x> <y> <item, or an amount of gold>
+//! ```
+//!
+//! with a `FLOOR` section per floor, starting from 0.  Blank lines are
+//! ignored anywhere, but `#` lines only count as comments before the
+//! first `FLOOR`; after that they're rows of wall.  Tiles are
+//!
+//! ```text
+//! .  plains      ,  hills      :  forest
+//! ^  mountains   ~  water      #  wall
+//! T  town        $  chest      @  where the party starts
+//! >  stairs down to the next floor's <
+//! <  stairs up to the previous floor's >
+//! ```
+//!
+//! Mountains, water and walls are in the way.  Region 0, and any
+//! region with no `REGION` line, is safe.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::item::*;
+use super::battle_generator;
+use super::dice;
+
+/// The map that comes built in.
+pub const DEFAULT_MAP: &str = include_str!("../maps/world.txt");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+    Plains,
+    Hills,
+    Forest,
+    Mountains,
+    Water,
+    Wall,
+    Town,
+    Chest,
+    StairsDown,
+    StairsUp,
+}
+
+impl Tile {
+    fn from_char(c: char) -> Option<Tile> {
+        match c {
+            '.' | '@' => Some(Tile::Plains),
+            ',' => Some(Tile::Hills),
+            ':' => Some(Tile::Forest),
+            '^' => Some(Tile::Mountains),
+            '~' => Some(Tile::Water),
+            '#' => Some(Tile::Wall),
+            'T' => Some(Tile::Town),
+            '$' => Some(Tile::Chest),
+            '>' => Some(Tile::StairsDown),
+            '<' => Some(Tile::StairsUp),
+            _ => None,
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            Tile::Plains => '.',
+            Tile::Hills => ',',
+            Tile::Forest => ':',
+            Tile::Mountains => '^',
+            Tile::Water => '~',
+            Tile::Wall => '#',
+            Tile::Town => 'T',
+            Tile::Chest => '$',
+            Tile::StairsDown => '>',
+            Tile::StairsUp => '<',
+        }
+    }
+
+    pub fn passable(&self) -> bool {
+        !matches!(*self, Tile::Mountains | Tile::Water | Tile::Wall)
+    }
+}
+
+/// Who lives in a region, and how often they turn up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    /// Roughly how many steps between fights.
+    pub steps: u32,
+    pub monsters: Vec<String>,
+}
+
+/// What's in a chest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Treasure {
+    Item(Item),
+    Gold(u32),
+}
+
+impl Treasure {
+    /// Puts it in the bag.
+    pub fn give(&self, bag: &mut Inventory) {
+        match *self {
+            Treasure::Item(item) => bag.add(item, 1),
+            Treasure::Gold(n) => bag.gold += n,
+        }
+    }
+}
+
+impl fmt::Display for Treasure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Treasure::Item(item) => write!(f, "a {}", item),
+            Treasure::Gold(n) => write!(f, "{} gold", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Floor {
+    /// Rows of tiles, top to bottom.
+    pub tiles: Vec<Vec<Tile>>,
+    /// Which region each tile's in, same shape as `tiles`.
+    pub regions: Vec<Vec<char>>,
+}
+
+impl Floor {
+    pub fn tile(&self, x: usize, y: usize) -> Option<Tile> {
+        self.tiles.get(y).and_then(|row| row.get(x)).cloned()
+    }
+
+    pub fn region(&self, x: usize, y: usize) -> char {
+        self.regions.get(y).and_then(|row| row.get(x)).cloned().unwrap_or('0')
+    }
+
+    /// Where the first tile of the given kind is, if there is one.
+    fn find(&self, tile: Tile) -> Option<(usize, usize)> {
+        self.tiles.iter().enumerate().find_map(|(y, row)| {
+            row.iter().position(|&t| t == tile).map(|x| (x, y))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map {
+    pub floors: Vec<Floor>,
+    pub regions: HashMap<char, Region>,
+    /// Floor, x, y.
+    pub chests: HashMap<(usize, usize, usize), Treasure>,
+    /// Floor, x, y.
+    pub start: (usize, usize, usize),
+}
+
+impl Map {
+    pub fn parse(text: &str) -> Result<Map, String> {
+        let mut floors: Vec<Floor> = Vec::new();
+        let mut regions = HashMap::new();
+        let mut chests = HashMap::new();
+        let mut start = None;
+        // Whether grid rows are tiles or regions right now.
+        let mut in_regions = false;
+        for (n, line) in text.lines().enumerate() {
+            let err = |msg: &str| format!("line {}: {}", n + 1, msg);
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') && floors.is_empty() {
+                continue;
+            }
+            let mut words = line.splitn(4, ' ');
+            match words.next().unwrap() {
+                "REGION" => {
+                    let id = words.next()
+                        .and_then(|w| w.chars().next())
+                        .ok_or_else(|| err("missing region"))?;
+                    let steps = words.next()
+                        .and_then(|w| w.parse().ok())
+                        .ok_or_else(|| err("bad step count"))?;
+                    let monsters: Vec<String> = words.next()
+                        .unwrap_or("")
+                        .split(',')
+                        .map(|m| m.trim().to_string())
+                        .filter(|m| !m.is_empty())
+                        .collect();
+                    if let Some(m) = monsters.iter().find(|m| battle_generator::template(m).is_none()) {
+                        return Err(err(&format!("no monster called {}", m)));
+                    }
+                    regions.insert(id, Region { steps, monsters });
+                }
+                "FLOOR" => {
+                    floors.push(Floor {
+                        tiles: Vec::new(),
+                        regions: Vec::new(),
+                    });
+                    in_regions = false;
+                }
+                "REGIONS" => in_regions = true,
+                "CHEST" => {
+                    let nums: Vec<&str> = line.split(' ').skip(1).collect();
+                    let num = |i: usize| nums.get(i).and_then(|w| w.parse::<usize>().ok());
+                    let (f, x, y) = match (num(0), num(1), num(2)) {
+                        (Some(f), Some(x), Some(y)) => (f, x, y),
+                        _ => return Err(err("bad chest position")),
+                    };
+                    let what = nums.get(3).cloned().unwrap_or("");
+                    let treasure = match what.parse() {
+                        Ok(gold) => Treasure::Gold(gold),
+                        Err(_) => {
+                            Item::all()
+                                .into_iter()
+                                .find(|i| format!("{:?}", i) == what)
+                                .map(Treasure::Item)
+                                .ok_or_else(|| err(&format!("no item called {}", what)))?
+                        }
+                    };
+                    chests.insert((f, x, y), treasure);
+                }
+                _ => {
+                    let f = floors.len().checked_sub(1).ok_or_else(|| err("expected FLOOR"))?;
+                    let floor = floors.last_mut().unwrap();
+                    if in_regions {
+                        floor.regions.push(line.chars().collect());
+                        continue;
+                    }
+                    let y = floor.tiles.len();
+                    let mut row = Vec::new();
+                    for (x, c) in line.chars().enumerate() {
+                        if c == '@' {
+                            start = Some((f, x, y));
+                        }
+                        row.push(Tile::from_char(c)
+                            .ok_or_else(|| err(&format!("unknown tile {}", c)))?);
+                    }
+                    floor.tiles.push(row);
+                }
+            }
+        }
+        let start = start.ok_or_else(|| String::from("nowhere to start (no @)"))?;
+        for (f, floor) in floors.iter().enumerate() {
+            let width = floor.tiles.first().map(|r| r.len()).unwrap_or(0);
+            if floor.tiles.iter().any(|r| r.len() != width) {
+                return Err(format!("floor {} isn't rectangular", f));
+            }
+            if !floor.regions.is_empty() &&
+               (floor.regions.len() != floor.tiles.len() ||
+                floor.regions.iter().any(|r| r.len() != width)) {
+                return Err(format!("floor {}'s regions don't match its tiles", f));
+            }
+        }
+        Ok(Map {
+            floors,
+            regions,
+            chests,
+            start,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Map, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| e.to_string())?;
+        Map::parse(&text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Direction {
+    pub fn all() -> Vec<Direction> {
+        vec![Direction::North, Direction::South, Direction::East, Direction::West]
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::North => write!(f, "North"),
+            Direction::South => write!(f, "South"),
+            Direction::East => write!(f, "East"),
+            Direction::West => write!(f, "West"),
+        }
+    }
+}
+
+/// What came of taking a step.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Happening {
+    /// Nothing much.
+    Walked,
+    /// Couldn't go that way.
+    Blocked,
+    /// Monsters!  These ones.
+    Encounter(Vec<String>),
+    Town,
+    /// Went up or down to this floor.
+    Stairs(usize),
+    Chest(Treasure),
+    /// Somebody's been here before.
+    EmptyChest,
+}
+
+/// Where the party is on a map, and what they've done there.
+#[derive(Debug, Clone)]
+pub struct Overworld {
+    pub map: Map,
+    pub floor: usize,
+    pub x: usize,
+    pub y: usize,
+    /// Steps left before the next fight.  Only counts down
+    /// in regions where there's something to fight.
+    pub steps_left: u32,
+    /// Chests already emptied, by floor, x and y.
+    pub opened: HashSet<(usize, usize, usize)>,
+}
+
+impl Overworld {
+    pub fn new(map: Map) -> Overworld {
+        let (floor, x, y) = map.start;
+        Overworld {
+            map,
+            floor,
+            x,
+            y,
+            steps_left: 0,
+            opened: HashSet::new(),
+        }
+    }
+
+    fn here(&self) -> &Floor {
+        &self.map.floors[self.floor]
+    }
+
+    /// The region they're in, if anything lives there.
+    pub fn region(&self) -> Option<&Region> {
+        self.map.regions.get(&self.here().region(self.x, self.y))
+    }
+
+    /// Takes a step, and does whatever happens when
+    /// you step there.
+    pub fn step(&mut self, dir: Direction) -> Happening {
+        let (x, y) = match dir {
+            Direction::North => (Some(self.x), self.y.checked_sub(1)),
+            Direction::South => (Some(self.x), Some(self.y + 1)),
+            Direction::East => (Some(self.x + 1), Some(self.y)),
+            Direction::West => (self.x.checked_sub(1), Some(self.y)),
+        };
+        let (x, y, tile) = match (x, y) {
+            (Some(x), Some(y)) => {
+                match self.here().tile(x, y) {
+                    Some(tile) if tile.passable() => (x, y, tile),
+                    _ => return Happening::Blocked,
+                }
+            }
+            _ => return Happening::Blocked,
+        };
+        self.x = x;
+        self.y = y;
+        match tile {
+            Tile::Town => Happening::Town,
+            Tile::StairsDown => self.take_stairs(self.floor + 1, Tile::StairsUp),
+            Tile::StairsUp if self.floor > 0 => self.take_stairs(self.floor - 1, Tile::StairsDown),
+            Tile::Chest => {
+                let key = (self.floor, x, y);
+                match self.map.chests.get(&key) {
+                    Some(&treasure) if self.opened.insert(key) => Happening::Chest(treasure),
+                    _ => Happening::EmptyChest,
+                }
+            }
+            _ => self.count_step(),
+        }
+    }
+
+    /// Goes to the given floor, arriving on the first `arrive` tile
+    /// there.  If there's no such floor they just stay put.
+    fn take_stairs(&mut self, floor: usize, arrive: Tile) -> Happening {
+        let spot = self.map.floors.get(floor).and_then(|f| f.find(arrive));
+        match spot {
+            Some((x, y)) => {
+                self.floor = floor;
+                self.x = x;
+                self.y = y;
+                Happening::Stairs(floor)
+            }
+            None => Happening::Walked,
+        }
+    }
+
+    /// Counts down to the next fight, if there's anything
+    /// around to fight.
+    fn count_step(&mut self) -> Happening {
+        let (steps, monsters) = match self.region() {
+            Some(r) if !r.monsters.is_empty() && r.steps > 0 => (r.steps, r.monsters.clone()),
+            _ => return Happening::Walked,
+        };
+        if self.steps_left == 0 {
+            // Fresh in from somewhere safe; give them a bit of a start.
+            self.steps_left = steps / 2 + dice::random::<u32>() % steps + 1;
+        }
+        self.steps_left -= 1;
+        if self.steps_left > 0 {
+            return Happening::Walked;
+        }
+        let count = 1 + dice::random::<usize>() % 3;
+        let group = (0..count)
+            .map(|_| monsters[dice::random::<usize>() % monsters.len()].clone())
+            .collect();
+        Happening::Encounter(group)
+    }
+
+    /// A bit of the map around the party, with them as `@`.
+    pub fn view(&self, radius: usize) -> String {
+        let floor = self.here();
+        let mut out = String::new();
+        let rows = self.y.saturating_sub(radius)..(self.y + radius + 1).min(floor.tiles.len());
+        for y in rows {
+            let row = &floor.tiles[y];
+            let cols = self.x.saturating_sub(radius * 2)..(self.x + radius * 2 + 1).min(row.len());
+            for x in cols {
+                if (x, y) == (self.x, self.y) {
+                    out.push('@');
+                } else if row[x] == Tile::Chest && self.opened.contains(&(self.floor, x, y)) {
+                    out.push(Tile::Plains.to_char());
+                } else {
+                    out.push(row[x].to_char());
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TINY: &str = "
+REGION 1 1 Slime
+FLOOR
+~~~~~~
+~@.T$~
+~..>.~
+~~~~~~
+REGIONS
+000000
+001000
+011000
+000000
+FLOOR
+####
+#<$#
+####
+CHEST 0 4 1 25
+CHEST 1 2 1 WorldTreeLeaf
+";
+
+    #[test]
+    fn the_default_map_loads() {
+        let map = Map::parse(DEFAULT_MAP).unwrap();
+        assert_eq!(map.floors.len(), 3);
+        assert_eq!(map.chests.len(), 5);
+    }
+
+    #[test]
+    fn bad_maps() {
+        assert!(Map::parse("FLOOR\n...\n").is_err());
+        assert!(Map::parse("FLOOR\n.@.\n..\n").is_err());
+        assert!(Map::parse("FLOOR\n.@?\n").is_err());
+        assert!(Map::parse("REGION 1 5 Nobody\nFLOOR\n.@.\n").is_err());
+    }
+
+    #[test]
+    fn walking_around() {
+        let mut w = Overworld::new(Map::parse(TINY).unwrap());
+        assert_eq!((w.floor, w.x, w.y), (0, 1, 1));
+        assert_eq!(w.step(Direction::North), Happening::Blocked);
+        assert_eq!(w.step(Direction::West), Happening::Blocked);
+        // Every step in region 1 is a fight.
+        match w.step(Direction::East) {
+            Happening::Encounter(ref group) => {
+                assert!(!group.is_empty() && group.iter().all(|m| m == "Slime"))
+            }
+            other => panic!("Wrong happening: {:?}", other),
+        }
+        assert_eq!(w.step(Direction::East), Happening::Town);
+        assert_eq!(w.step(Direction::East), Happening::Chest(Treasure::Gold(25)));
+        assert_eq!(w.step(Direction::West), Happening::Town);
+        assert_eq!(w.step(Direction::East), Happening::EmptyChest);
+        assert_eq!(w.view(1), "~~~~\n.T@~\n.>.~\n");
+        assert_eq!(w.step(Direction::South), Happening::Walked);
+        assert_eq!(w.step(Direction::West), Happening::Stairs(1));
+        assert_eq!((w.floor, w.x, w.y), (1, 1, 1));
+        assert_eq!(w.step(Direction::East), Happening::Chest(Treasure::Item(Item::WorldTreeLeaf)));
+        assert_eq!(w.step(Direction::West), Happening::Stairs(0));
+        assert_eq!((w.floor, w.x, w.y), (0, 3, 2));
+    }
+}
@@ -0,0 +1,291 @@
+//! Things characters do without being told to.
+//!
+//! Reactions go off in response to something happening during
+//! someone else's action: getting hit, an ally getting into trouble,
+//! and so on.  Passives are just always on.
+//!
+//! Reactions never set off more reactions, or two characters who
+//! both counter-attack would be at it until one of them dropped.
+
+use std::cmp;
+
+use super::character::*;
+use super::battlefield::*;
+use super::event::*;
+use super::action::*;
+use super::spell::*;
+
+/// Below this much of their max HP, in percent, a character
+/// counts as in trouble and can get covered.
+pub const COVER_THRESHOLD: u32 = 25;
+
+/// How many rounds poison lasts, if nobody cures it.
+pub const POISON_ROUNDS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Reaction {
+    /// Hits back whoever hit them, as long as they're still standing.
+    Counter,
+    /// Jumps in front of attacks aimed at allies low on HP.
+    Cover,
+    /// Shakes off poison as soon as it sets in.
+    AutoCure,
+    /// Bounces hostile spells back at whoever cast them.
+    Reflect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Passive {
+    /// Gets back this much HP at the end of every round.
+    Regen(u32),
+    /// Always goes first, or starts with a full gauge in
+    /// conditional-turn-based battles.
+    FirstStrike,
+    /// Hostile spells just don't work on them.
+    MagicImmunity,
+    /// Their plain attacks poison whoever they hurt.
+    PoisonTouch,
+}
+
+/// Looks through what just happened during an action and sets off
+/// any reactions it calls for.  Anything the reactions do goes onto
+/// the end of `events`, but doesn't get looked at for more reactions.
+/// Nobody reacts more than once to the same action.
+pub fn react(field: &mut Battlefield,
+             happened: &[BattleEvent],
+             events: &mut Vec<BattleEvent>) {
+    let mut reacted = Vec::new();
+    for e in happened {
+        match *e {
+            BattleEvent::Attacked(from, to) => {
+                if reacted.contains(&to) || !field.hostile(from, to) {
+                    continue;
+                }
+                let can_counter = |c: CharSpecifier| {
+                    field.get(c).is_some_and(|chr| chr.is_alive())
+                };
+                let countering = field.get(to).is_some_and(|chr| chr.has_reaction(Reaction::Counter));
+                if countering && can_counter(to) && can_counter(from) {
+                    reacted.push(to);
+                    events.push(BattleEvent::Countered(to));
+                    // A counter is just a quick swing; any tension
+                    // they've built up stays saved for later.
+                    attack_one(field, to, from, events);
+                }
+            }
+            BattleEvent::Buffed(who, BuffType::Poison) => {
+                let chr = match field.get_mut(who) {
+                    Some(chr) => chr,
+                    None => continue,
+                };
+                if chr.has_reaction(Reaction::AutoCure) && chr.buffs.remove(&BuffType::Poison).is_some() {
+                    events.push(BattleEvent::Cured(who));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Works out who actually takes a hit aimed at `defender`:
+/// if they're in trouble and someone on their side (or an
+/// allied side) covers for them, it's that someone instead.
+pub fn cover(field: &Battlefield,
+             attacker: CharSpecifier,
+             defender: CharSpecifier,
+             events: &mut Vec<BattleEvent>)
+             -> CharSpecifier {
+    let chr = match field.get(defender) {
+        Some(chr) => chr,
+        None => return defender,
+    };
+    if chr.hp.val * 100 > chr.hp.max * COVER_THRESHOLD {
+        return defender;
+    }
+    let coverer = field.chars
+        .iter()
+        .enumerate()
+        .find(|&(i, o)| {
+            i != defender && i != attacker && o.is_alive() &&
+            field.relations.allied(o.team, chr.team) && o.has_reaction(Reaction::Cover)
+        });
+    match coverer {
+        Some((i, _)) => {
+            events.push(BattleEvent::Covered(i, defender));
+            i
+        }
+        None => defender,
+    }
+}
+
+/// Works out who a spell aimed at `target` actually lands on,
+/// after reflection and immunity.  A reflected spell doesn't
+/// get reflected again.  Returns None if it fizzles.
+pub fn spell_lands_on(field: &Battlefield,
+                      caster: CharSpecifier,
+                      target: CharSpecifier,
+                      spell: Spell,
+                      events: &mut Vec<BattleEvent>)
+                      -> Option<CharSpecifier> {
+    if !spell.hostile() {
+        return Some(target);
+    }
+    let mut target = target;
+    if target != caster && field.get(target)?.has_reaction(Reaction::Reflect) {
+        events.push(BattleEvent::Reflected(target));
+        target = caster;
+    }
+    if field.get(target)?.has_passive(Passive::MagicImmunity) {
+        events.push(BattleEvent::Immune(target));
+        return None;
+    }
+    Some(target)
+}
+
+/// The end-of-round bits: poison hurts, regeneration heals.
+pub fn tick_statuses(field: &mut Battlefield, events: &mut Vec<BattleEvent>) {
+    for (i, chr) in field.chars.iter_mut().enumerate() {
+        if !chr.is_alive() {
+            continue;
+        }
+        if chr.has_buff(BuffType::Poison) {
+            let hit = chr.take_damage(cmp::max(1, chr.hp.max / 8));
+            events.push(BattleEvent::PoisonDamage(i, hit.applied));
+            if !chr.is_alive() {
+                events.push(BattleEvent::Died(i));
+                continue;
+            }
+        }
+        let regen = chr.regen();
+        if regen > 0 && !chr.hp.is_full() {
+            let healed = chr.hp.gain(regen);
+            events.push(BattleEvent::Regenerated(i, healed.applied));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bounded_number::BoundedNumber;
+    use target::*;
+
+    fn duel() -> Battlefield {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new_with_stats("Alena", Team::Player, 50, 0, 10, 1, 10, 10));
+        b.chars.push(Character::new_with_stats("Slime", Team::Monster, 50, 10, 10, 1, 10, 10));
+        b
+    }
+
+    #[test]
+    fn counters_dont_go_on_forever() {
+        let mut b = duel();
+        b.get_mut(0).unwrap().reactions.push(Reaction::Counter);
+        b.get_mut(1).unwrap().reactions.push(Reaction::Counter);
+        let mut events = Vec::new();
+        run_action(&mut b, &Action::Attack(0, Target::Random(3)), &mut events);
+        let attacks: Vec<BattleEvent> = events.iter()
+            .cloned()
+            .filter(|e| matches!(*e, BattleEvent::Attacked(..)))
+            .collect();
+        assert_eq!(attacks,
+                   vec![BattleEvent::Attacked(0, 1),
+                        BattleEvent::Attacked(0, 1),
+                        BattleEvent::Attacked(0, 1),
+                        BattleEvent::Attacked(1, 0)]);
+        assert_eq!(events.iter().filter(|&&e| e == BattleEvent::Countered(1)).count(), 1);
+
+        // Hitting back doesn't use up a full meter they were saving.
+        // Patch them up first, so there's still someone to hit back.
+        b.get_mut(1).unwrap().hp.val = 50;
+        b.get_mut(1).unwrap().tension.val = TENSION_MAX;
+        events.clear();
+        run_action(&mut b, &Action::Attack(0, Target::Single(1)), &mut events);
+        assert!(events.contains(&BattleEvent::Countered(1)));
+        assert!(!events.contains(&BattleEvent::Unleashed(1)));
+        assert!(b.get(1).unwrap().fully_psyched());
+    }
+
+    #[test]
+    fn covering_for_friends() {
+        let mut b = duel();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.get_mut(2).unwrap().reactions.push(Reaction::Cover);
+        let mut events = Vec::new();
+        assert_eq!(cover(&b, 1, 0, &mut events), 0);
+        b.get_mut(0).unwrap().hp.val = 5;
+        assert_eq!(cover(&b, 1, 0, &mut events), 2);
+        assert_eq!(events, vec![BattleEvent::Covered(2, 0)]);
+
+        // Friends from another team will do it too, but not just anybody.
+        b.get_mut(2).unwrap().team = Team::Other(1);
+        events.clear();
+        assert_eq!(cover(&b, 1, 0, &mut events), 0);
+        b.relations.set(Team::Player, Team::Other(1), Stance::Allied);
+        assert_eq!(cover(&b, 1, 0, &mut events), 2);
+    }
+
+    #[test]
+    fn reflection_and_immunity() {
+        let mut b = duel();
+        b.get_mut(1).unwrap().spells.push(Spell::Deceleratle);
+        b.get_mut(0).unwrap().reactions.push(Reaction::Reflect);
+        let mut events = Vec::new();
+        run_action(&mut b, &Action::Cast(1, Spell::Deceleratle, Target::Single(0)), &mut events);
+        assert_eq!(events,
+                   vec![BattleEvent::CastSpell(1, Spell::Deceleratle),
+                        BattleEvent::Reflected(0),
+                        BattleEvent::Buffed(1, BuffType::Slow)]);
+
+        b.get_mut(1).unwrap().passives.push(Passive::MagicImmunity);
+        events.clear();
+        run_action(&mut b, &Action::Cast(1, Spell::Deceleratle, Target::Single(0)), &mut events);
+        assert_eq!(events[2], BattleEvent::Immune(1));
+    }
+
+    #[test]
+    fn kaboom_gets_reflected_too() {
+        let mut b = duel();
+        b.get_mut(0).unwrap().reactions.push(Reaction::Reflect);
+        b.get_mut(1).unwrap().mp = BoundedNumber::new(50);
+        let mut events = Vec::new();
+        do_cast(&mut b, 1, Spell::Kaboom, Target::AllEnemies, &mut events);
+        assert_eq!(events[..2],
+                   [BattleEvent::CastSpell(1, Spell::Kaboom), BattleEvent::Reflected(0)]);
+        assert_eq!(b.get(0).unwrap().hp.val, 50);
+        assert!(b.get(1).unwrap().hp.val < 50);
+
+        b.get_mut(1).unwrap().passives.push(Passive::MagicImmunity);
+        let hp = b.get(1).unwrap().hp.val;
+        events.clear();
+        do_cast(&mut b, 1, Spell::Kaboom, Target::AllEnemies, &mut events);
+        assert_eq!(events,
+                   vec![BattleEvent::CastSpell(1, Spell::Kaboom),
+                        BattleEvent::Reflected(0),
+                        BattleEvent::Immune(1)]);
+        assert_eq!(b.get(1).unwrap().hp.val, hp);
+    }
+
+    #[test]
+    fn poison_and_regen() {
+        let mut b = duel();
+        b.get_mut(1).unwrap().passives.push(Passive::PoisonTouch);
+        b.get_mut(0).unwrap().passives.push(Passive::Regen(3));
+        b.get_mut(0).unwrap().add_buff(BuffType::Poison, POISON_ROUNDS);
+        let mut events = Vec::new();
+        tick_statuses(&mut b, &mut events);
+        assert_eq!(events,
+                   vec![BattleEvent::PoisonDamage(0, 6), BattleEvent::Regenerated(0, 3)]);
+        assert_eq!(b.get(0).unwrap().hp.val, 47);
+
+        // Poison doesn't stick to someone who cures it right away.
+        b.get_mut(0).unwrap().buffs.clear();
+        b.get_mut(0).unwrap().reactions.push(Reaction::AutoCure);
+        b.get_mut(1).unwrap().atk = 30;
+        events.clear();
+        run_action(&mut b, &Action::Attack(1, Target::Single(0)), &mut events);
+        assert!(events.contains(&BattleEvent::Cured(0)));
+        assert!(!b.get(0).unwrap().has_buff(BuffType::Poison));
+    }
+}
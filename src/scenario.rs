@@ -0,0 +1,496 @@
+//! Battles written down ahead of time, for testing.
+//!
+//! A scenario sets up a fight exactly, says what everyone does each
+//! round, and says what ought to happen; `run` plays it out and
+//! reports anything that didn't go to plan.  The files in
+//! `tests/scenarios` all get run as part of the tests, so anybody
+//! can add a regression case without touching any Rust.
+//!
+//! They're plain text, one thing per line:
+//!
+//! ```text
+//! # comments, and blank lines, are ignored
+//! SEED <number>
+//! CHAR <team> <FRONT|BACK> <hp> <max hp> <mp> <max mp> <atk> <def> <spd> <lck> <name>
+//! BUFF <buff> <rounds>
+//! BAG <team> <item> <count>
+//! ROUND [seed]
+//! <action>
+//! EVENT <what happened>
+//! QUIET
+//! RESULT <winning team, or NONE>
+//! EXPECT <character> HP <hp>
+//! EXPECT <character> MP <mp>
+//! EXPECT <character> TENSION <tension>
+//! EXPECT <character> <KO|UP>
+//! EXPECT <character> ROW <FRONT|BACK>
+//! EXPECT <character> BUFF <buff> <rounds>
+//! EXPECT <character> NO <buff>
+//! ```
+//!
+//! Teams, rows, items, actions and characters are written the same
+//! way as in the network protocol (see `net`), so an action is
+//! something like `ATTACK 0 2` or `CAST 1 ZING 0`.  Anyone whose name
+//! is in `battle_generator` gets their spells and such from there, but
+//! the stats always come from the `CHAR` line, where attack and
+//! defense have to be at least 1.  `BUFF` goes on the last
+//! character, and buffs are `Defend`, `Haste`, `Slow`, `Poison` or
+//! `Sleep`.
+//!
+//! Each `ROUND` is followed by whatever gets done that round;
+//! anyone without an action just stands there.  Buffs tick down at
+//! the start of every round, like in the game.  If the `ROUND` has a
+//! seed, the dice get seeded with it just before the fighting starts;
+//! recorded battles (see `starting_from`) are just scenarios with a
+//! seed for every round.  If there are any `EVENT` lines after the
+//! `ROUND`, what happened that round has to match
+//! them exactly, in order, as the player would see it; `QUIET` means
+//! nothing should happen at all.  `RESULT` and the `EXPECT`s get
+//! checked once all the rounds are over.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::bounded_number::BoundedNumber;
+use super::character::*;
+use super::battlefield::*;
+use super::action::*;
+use super::battle::*;
+use super::battle_generator;
+use super::event::BattleEvent;
+use super::dice;
+use super::net::{action_words, item_name, row_name, team_name};
+use super::net::{parse_action, parse_item, parse_row, parse_team};
+
+const BUFFS: [BuffType; 5] = [BuffType::Defend,
+                              BuffType::Haste,
+                              BuffType::Slow,
+                              BuffType::Poison,
+                              BuffType::Sleep];
+
+/// One round's worth of orders, and what should come of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Round {
+    /// What to seed the dice with before the round, if anything.
+    pub seed: Option<u64>,
+    pub actions: Vec<Action>,
+    /// What the player should see, line by line.
+    /// None if it doesn't matter.
+    pub events: Option<Vec<String>>,
+}
+
+/// Something about a character to check at the end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Check {
+    Hp(CharSpecifier, u32),
+    Mp(CharSpecifier, u32),
+    Tension(CharSpecifier, u32),
+    KnockedOut(CharSpecifier, bool),
+    Row(CharSpecifier, Row),
+    Buff(CharSpecifier, BuffType, u32),
+    NoBuff(CharSpecifier, BuffType),
+}
+
+impl Check {
+    /// None if it's fine, otherwise what's wrong.
+    fn check(&self, field: &Battlefield) -> Option<String> {
+        let who = match *self {
+            Check::Hp(c, _) |
+            Check::Mp(c, _) |
+            Check::Tension(c, _) |
+            Check::KnockedOut(c, _) |
+            Check::Row(c, _) |
+            Check::Buff(c, _, _) |
+            Check::NoBuff(c, _) => c,
+        };
+        let chr = match field.get(who) {
+            Some(chr) => chr,
+            None => return Some(format!("there's no character {}", who)),
+        };
+        let (expected, got) = match *self {
+            Check::Hp(_, hp) => (hp.to_string(), chr.hp.val.to_string()),
+            Check::Mp(_, mp) => (mp.to_string(), chr.mp.val.to_string()),
+            Check::Tension(_, t) => (t.to_string(), chr.tension.val.to_string()),
+            Check::KnockedOut(_, ko) => (ko.to_string(), (!chr.is_alive()).to_string()),
+            Check::Row(_, row) => (format!("{:?}", row), format!("{:?}", chr.row)),
+            Check::Buff(_, buff, rounds) => {
+                (format!("{:?} {}", buff, rounds), format!("{:?} {:?}", buff, chr.buffs.get(&buff)))
+            }
+            Check::NoBuff(_, buff) => {
+                (format!("no {:?}", buff), format!("{:?} {:?}", buff, chr.buffs.get(&buff)))
+            }
+        };
+        let ok = match *self {
+            Check::Buff(_, buff, rounds) => chr.buffs.get(&buff) == Some(&rounds),
+            Check::NoBuff(_, buff) => !chr.has_buff(buff),
+            _ => expected == got,
+        };
+        if ok {
+            None
+        } else {
+            Some(format!("{} ({}): expected {}, got {}", who, chr.name, expected, got))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub seed: u64,
+    /// How things stand before the first round.
+    pub field: Battlefield,
+    pub rounds: Vec<Round>,
+    /// None if nobody's said who should win.
+    pub result: Option<BattleStatus>,
+    pub checks: Vec<Check>,
+}
+
+fn parse_buff(s: Option<&str>) -> Result<BuffType, String> {
+    let s = s.ok_or_else(|| String::from("missing buff"))?;
+    BUFFS.iter()
+        .cloned()
+        .find(|b| format!("{:?}", b) == s)
+        .ok_or_else(|| format!("unknown buff {}", s))
+}
+
+fn parse_num<T: ::std::str::FromStr>(s: Option<&str>) -> Result<T, String> {
+    let s = s.ok_or_else(|| String::from("missing number"))?;
+    s.parse().map_err(|_| format!("bad number {}", s))
+}
+
+fn parse_char(words: &[&str]) -> Result<Character, String> {
+    if words.len() < 12 {
+        return Err(String::from("not enough fields for a CHAR"));
+    }
+    let name = words[11..].join(" ");
+    let team = parse_team(words[1])?;
+    let mut chr = battle_generator::template(&name)
+        .unwrap_or_else(|| Character::new(&name, team));
+    chr.team = team;
+    chr.row = parse_row(words[2])?;
+    let num = |i: usize| parse_num(words.get(i).cloned());
+    chr.hp = BoundedNumber::new(num(4)?);
+    chr.hp.set(num(3)?);
+    chr.mp = BoundedNumber::new(num(6)?);
+    chr.mp.set(num(5)?);
+    chr.atk = num(7)?;
+    chr.def = num(8)?;
+    chr.spd = num(9)?;
+    chr.lck = num(10)?;
+    if chr.atk == 0 || chr.def == 0 {
+        return Err(format!("{} needs at least 1 attack and defense", name));
+    }
+    chr.knocked_out = chr.hp.val == 0;
+    Ok(chr)
+}
+
+fn parse_check(words: &[&str]) -> Result<Check, String> {
+    let who = parse_num(words.get(1).cloned())?;
+    let num = |i: usize| parse_num(words.get(i).cloned());
+    Ok(match words.get(2).cloned() {
+        Some("HP") => Check::Hp(who, num(3)?),
+        Some("MP") => Check::Mp(who, num(3)?),
+        Some("TENSION") => Check::Tension(who, num(3)?),
+        Some("KO") => Check::KnockedOut(who, true),
+        Some("UP") => Check::KnockedOut(who, false),
+        Some("ROW") => Check::Row(who, parse_row(words.get(3).cloned().unwrap_or(""))?),
+        Some("BUFF") => Check::Buff(who, parse_buff(words.get(3).cloned())?, num(4)?),
+        Some("NO") => Check::NoBuff(who, parse_buff(words.get(3).cloned())?),
+        _ => return Err(format!("don't know how to check {}", words.join(" "))),
+    })
+}
+
+fn parse_result(s: Option<&str>) -> Result<BattleStatus, String> {
+    match s {
+        Some("NONE") => Ok(BattleStatus::Continuing),
+        Some(team) => {
+            Ok(match parse_team(team)? {
+                Team::Player => BattleStatus::PlayerVictory,
+                Team::Monster => BattleStatus::MonsterVictory,
+                other => BattleStatus::OtherVictory(other),
+            })
+        }
+        None => Err(String::from("missing result")),
+    }
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut scenario = Scenario {
+            seed: 0,
+            field: Battlefield::new(),
+            rounds: Vec::new(),
+            result: None,
+            checks: Vec::new(),
+        };
+        for (n, line) in text.lines().enumerate() {
+            let err = |e: String| format!("line {}: {}", n + 1, e);
+            let line = line.trim_end();
+            let words: Vec<&str> = line.split(' ').filter(|w| !w.is_empty()).collect();
+            match words.first().cloned() {
+                None => (),
+                Some(w) if w.starts_with('#') => (),
+                Some("SEED") => scenario.seed = parse_num(words.get(1).cloned()).map_err(err)?,
+                Some("CHAR") => scenario.field.chars.push(parse_char(&words).map_err(err)?),
+                Some("BUFF") => {
+                    let buff = parse_buff(words.get(1).cloned()).map_err(err)?;
+                    let rounds = parse_num(words.get(2).cloned()).map_err(err)?;
+                    scenario.field
+                        .chars
+                        .last_mut()
+                        .ok_or_else(|| err(String::from("BUFF before any CHAR")))?
+                        .add_buff(buff, rounds);
+                }
+                Some("BAG") => {
+                    let team = parse_team(words.get(1).cloned().unwrap_or("")).map_err(err)?;
+                    let item = parse_item(words.get(2).cloned()).map_err(err)?;
+                    let count = parse_num(words.get(3).cloned()).map_err(err)?;
+                    scenario.field.inventory_mut(team).add(item, count);
+                }
+                Some("ROUND") => {
+                    let seed = match words.get(1) {
+                        Some(&w) => Some(parse_num(Some(w)).map_err(err)?),
+                        None => None,
+                    };
+                    scenario.rounds.push(Round { seed, ..Round::default() });
+                }
+                Some("EVENT") | Some("QUIET") => {
+                    let events = scenario.rounds
+                        .last_mut()
+                        .ok_or_else(|| err(format!("{} before any ROUND", words[0])))?
+                        .events
+                        .get_or_insert_with(Vec::new);
+                    if words[0] == "EVENT" {
+                        // Keep the spacing exactly as it is.
+                        events.push(String::from(line.split_once(' ').map_or("", |(_, e)| e)));
+                    }
+                }
+                Some("RESULT") => scenario.result = Some(parse_result(words.get(1).cloned()).map_err(err)?),
+                Some("EXPECT") => scenario.checks.push(parse_check(&words).map_err(err)?),
+                Some(_) => {
+                    let action = parse_action(&words).map_err(err)?;
+                    scenario.rounds
+                        .last_mut()
+                        .ok_or_else(|| err(String::from("action before any ROUND")))?
+                        .actions
+                        .push(action);
+                }
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scenario, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| e.to_string())?;
+        Scenario::parse(&text)
+    }
+
+    /// A scenario that starts off like the given battle, with nothing
+    /// happening in it yet.  Add rounds to it as they're fought, each
+    /// with its own seed, and it makes a replay.
+    pub fn starting_from(seed: u64, field: &Battlefield) -> Scenario {
+        Scenario {
+            seed,
+            field: field.clone(),
+            rounds: Vec::new(),
+            result: None,
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "SEED {}", self.seed)?;
+        for chr in &self.field.chars {
+            writeln!(w,
+                     "CHAR {} {} {} {} {} {} {} {} {} {} {}",
+                     team_name(chr.team),
+                     row_name(chr.row),
+                     chr.hp.val,
+                     chr.hp.max,
+                     chr.mp.val,
+                     chr.mp.max,
+                     chr.atk,
+                     chr.def,
+                     chr.spd,
+                     chr.lck,
+                     chr.name)?;
+            // Sorted, so the same battle always saves the same way.
+            let mut buffs: Vec<_> = chr.buffs.iter().map(|(b, n)| (format!("{:?}", b), n)).collect();
+            buffs.sort();
+            for (buff, rounds) in buffs {
+                writeln!(w, "BUFF {} {}", buff, rounds)?;
+            }
+        }
+        let mut bags: Vec<_> = self.field.inventories.iter().map(|(&t, bag)| (team_name(t), bag)).collect();
+        bags.sort_by(|a, b| a.0.cmp(&b.0));
+        for (team, bag) in bags {
+            for &(item, count) in bag.items() {
+                writeln!(w, "BAG {} {} {}", team, item_name(item), count)?;
+            }
+        }
+        for round in &self.rounds {
+            writeln!(w)?;
+            match round.seed {
+                Some(seed) => writeln!(w, "ROUND {}", seed)?,
+                None => writeln!(w, "ROUND")?,
+            }
+            for &action in &round.actions {
+                writeln!(w, "{}", action_words(action))?;
+            }
+            match round.events {
+                Some(ref events) if events.is_empty() => writeln!(w, "QUIET")?,
+                Some(ref events) => {
+                    for e in events {
+                        writeln!(w, "EVENT {}", e)?;
+                    }
+                }
+                None => (),
+            }
+        }
+        if let Some(result) = self.result {
+            writeln!(w)?;
+            match result {
+                BattleStatus::Continuing => writeln!(w, "RESULT NONE")?,
+                BattleStatus::PlayerVictory => writeln!(w, "RESULT {}", team_name(Team::Player))?,
+                BattleStatus::MonsterVictory => writeln!(w, "RESULT {}", team_name(Team::Monster))?,
+                BattleStatus::OtherVictory(team) => writeln!(w, "RESULT {}", team_name(team))?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        self.save(&mut f)
+    }
+
+    /// Plays it out.  Returns everything that didn't go the way
+    /// the scenario said it would, so empty means it passed.
+    pub fn run(&self) -> Vec<String> {
+        self.play(|_, _| ())
+    }
+
+    /// Like `run`, but shows `watch` what happened after every round.
+    pub fn play<F: FnMut(&Battlefield, &[BattleEvent])>(&self, mut watch: F) -> Vec<String> {
+        dice::seed(self.seed);
+        let mut field = self.field.clone();
+        let mut failures = Vec::new();
+        let mut status = battle_status(&field);
+        for (i, round) in self.rounds.iter().enumerate() {
+            if status != BattleStatus::Continuing {
+                failures.push(format!("the battle was already over by round {}", i + 1));
+                break;
+            }
+            tick_buffs(&mut field);
+            if let Some(seed) = round.seed {
+                dice::seed(seed);
+            }
+            let mut actions = round.actions.clone();
+            let mut events = Vec::new();
+            status = run_turn(&mut field, &mut actions, &mut events);
+            watch(&field, &events);
+            let seen: Vec<String> = events.iter().map(|e| e.describe(&field)).collect();
+            if round.events.as_ref().is_some_and(|expected| *expected != seen) {
+                failures.push(format!("round {} went differently; what happened was:\n{}",
+                                      i + 1,
+                                      seen.iter()
+                                          .map(|e| format!("EVENT {}", e))
+                                          .collect::<Vec<_>>()
+                                          .join("\n")));
+            }
+        }
+        match self.result {
+            Some(result) if result != status => {
+                failures.push(format!("expected {:?}, got {:?}", result, status))
+            }
+            _ => (),
+        }
+        failures.extend(self.checks.iter().filter_map(|c| c.check(&field)));
+        failures
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn bad_scenarios() {
+        assert!(Scenario::parse("CHAR PLAYER FRONT 1 1 1 1 1 1 1 Ragnar").is_err());
+        assert!(Scenario::parse("BUFF Haste 2").is_err());
+        assert!(Scenario::parse("ATTACK 0 1").is_err());
+        assert!(Scenario::parse("ROUND\nJUMP 0").is_err());
+        assert!(Scenario::parse("EXPECT 0 SMELL bad").is_err());
+        assert!(Scenario::parse("CHAR PLAYER FRONT 10 10 0 0 0 1 1 0 Ragnar").is_err());
+        assert!(Scenario::parse("CHAR MONSTER FRONT 10 10 0 0 5 0 1 0 Slime").is_err());
+    }
+
+    #[test]
+    fn failures_get_reported() {
+        let s = Scenario::parse("
+CHAR PLAYER FRONT 10 10 0 0 5 1 10 0 Ragnar
+CHAR MONSTER FRONT 10 10 0 0 5 1 1 0 Slime
+ROUND
+DEFEND 0
+EVENT Ragnar went home.
+RESULT PLAYER
+EXPECT 0 HP 3
+EXPECT 1 BUFF Haste 2
+").unwrap();
+        let failures = s.run();
+        assert_eq!(failures.len(), 4, "{:?}", failures);
+        assert!(failures[0].contains("EVENT Ragnar defended themselves!"));
+        assert_eq!(failures[2], "0 (Ragnar): expected 3, got 10");
+    }
+
+    #[test]
+    fn recordings_play_back() {
+        dice::seed(9);
+        let field = battle_generator::generate();
+        let mut recording = Scenario::starting_from(1, &field);
+        let mut field = field.clone();
+        let mut status = BattleStatus::Continuing;
+        while status == BattleStatus::Continuing {
+            tick_buffs(&mut field);
+            let seed = dice::random();
+            let mut actions = Vec::new();
+            decide_actions(&field, &(0..field.chars.len()).collect::<Vec<_>>(), &mut actions);
+            dice::seed(seed);
+            let mut events = Vec::new();
+            status = run_turn(&mut field, &mut actions.clone(), &mut events);
+            recording.rounds.push(Round {
+                seed: Some(seed),
+                actions,
+                events: Some(events.iter().map(|e| e.describe(&field)).collect()),
+            });
+        }
+        recording.result = Some(status);
+        let mut saved = Vec::new();
+        recording.save(&mut saved).unwrap();
+        let loaded = Scenario::parse(&String::from_utf8(saved).unwrap()).unwrap();
+        assert_eq!(loaded.rounds, recording.rounds);
+        let failures = loaded.run();
+        assert!(failures.is_empty(), "{:?}", failures);
+    }
+
+    #[test]
+    fn scenario_files() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scenarios");
+        let mut paths: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        let mut failures = Vec::new();
+        for path in paths {
+            match Scenario::load(&path) {
+                Ok(s) => failures.extend(s.run().into_iter().map(|f| format!("{}: {}", path.display(), f))),
+                Err(e) => failures.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+}
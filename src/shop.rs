@@ -0,0 +1,188 @@
+//! Places to spend gold between fights.
+//!
+//! A `Shop` has its own stock list and its own idea of how much to
+//! mark things up and how little to pay for them, both as a percent of
+//! what the item's worth.  An `Inn` just charges by the head.  Like
+//! `campaign`, all the talking to the player happens somewhere else.
+
+use std::fmt;
+
+use super::character::*;
+use super::item::*;
+
+/// Why a deal fell through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShopError {
+    NotForSale,
+    CantAfford,
+    /// They haven't got one to sell.
+    NothingToSell,
+    /// The shop won't pay anything for it.
+    Worthless,
+}
+
+impl fmt::Display for ShopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShopError::NotForSale => write!(f, "We don't sell that here."),
+            ShopError::CantAfford => write!(f, "You can't afford that."),
+            ShopError::NothingToSell => write!(f, "You haven't got one of those."),
+            ShopError::Worthless => write!(f, "I couldn't give you anything for that."),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shop {
+    /// What's for sale, in menu order.
+    pub stock: Vec<Item>,
+    /// What they charge, in percent of what things are worth.
+    pub buy_percent: u32,
+    /// What they'll pay, in percent of what things are worth.
+    pub sell_percent: u32,
+}
+
+impl Shop {
+    pub fn new(stock: &[Item]) -> Shop {
+        Shop {
+            stock: stock.to_vec(),
+            buy_percent: 100,
+            sell_percent: 50,
+        }
+    }
+
+    /// What they charge for one.
+    pub fn price(&self, item: Item) -> u32 {
+        item.price() * self.buy_percent / 100
+    }
+
+    /// What they'll pay for one.
+    pub fn offer(&self, item: Item) -> u32 {
+        item.price() * self.sell_percent / 100
+    }
+
+    /// Buys one, out of the bag's gold and into the bag.
+    pub fn buy(&self, bag: &mut Inventory, item: Item) -> Result<(), ShopError> {
+        if !self.stock.contains(&item) {
+            return Err(ShopError::NotForSale);
+        }
+        let price = self.price(item);
+        if bag.gold < price {
+            return Err(ShopError::CantAfford);
+        }
+        bag.gold -= price;
+        bag.add(item, 1);
+        Ok(())
+    }
+
+    /// Sells one out of the bag.  Shops will buy anything that's
+    /// worth something, whether or not they stock it.
+    /// Returns what it went for.
+    pub fn sell(&self, bag: &mut Inventory, item: Item) -> Result<u32, ShopError> {
+        let offer = self.offer(item);
+        if offer == 0 {
+            return Err(ShopError::Worthless);
+        }
+        if !bag.take(item) {
+            return Err(ShopError::NothingToSell);
+        }
+        bag.gold += offer;
+        Ok(offer)
+    }
+}
+
+/// Puts a party member into some gear out of the bag.
+/// Whatever they had on goes back in the bag.
+pub fn equip_from(bag: &mut Inventory, chr: &mut Character, item: Item) -> bool {
+    if item.slot().is_none() || !bag.take(item) {
+        return false;
+    }
+    if let Some(old) = chr.equip(item) {
+        bag.add(old, 1);
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inn {
+    pub price_per_head: u32,
+}
+
+impl Inn {
+    /// What a night costs for the whole party.
+    pub fn price(&self, party: &[Character]) -> u32 {
+        self.price_per_head * party.len() as u32
+    }
+
+    /// Everyone gets back up with full HP and MP, if the
+    /// bag's got the gold for it.
+    pub fn stay(&self, party: &mut [Character], bag: &mut Inventory) -> Result<(), ShopError> {
+        let price = self.price(party);
+        if bag.gold < price {
+            return Err(ShopError::CantAfford);
+        }
+        bag.gold -= price;
+        for chr in party {
+            chr.rest();
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buying_and_selling() {
+        let mut shop = Shop::new(&[Item::WorldTreeLeaf, Item::CopperSword]);
+        shop.buy_percent = 120;
+        let mut bag = Inventory::new();
+        bag.gold = 100;
+        assert_eq!(shop.buy(&mut bag, Item::IronSword), Err(ShopError::NotForSale));
+        assert_eq!(shop.buy(&mut bag, Item::CopperSword), Ok(()));
+        assert_eq!(bag.gold, 40);
+        assert_eq!(shop.buy(&mut bag, Item::CopperSword), Err(ShopError::CantAfford));
+        assert_eq!(shop.sell(&mut bag, Item::CopperSword), Ok(25));
+        assert_eq!(shop.sell(&mut bag, Item::CopperSword), Err(ShopError::NothingToSell));
+        bag.add(Item::SmallMedal, 1);
+        assert_eq!(shop.sell(&mut bag, Item::SmallMedal), Err(ShopError::Worthless));
+        assert_eq!(bag.gold, 65);
+    }
+
+    #[test]
+    fn equipping_what_you_bought() {
+        let mut bag = Inventory::new();
+        let mut ragnar = Character::new("Ragnar", Team::Player);
+        assert!(!equip_from(&mut bag, &mut ragnar, Item::CopperSword));
+        bag.add(Item::CopperSword, 1);
+        bag.add(Item::IronSword, 1);
+        bag.add(Item::WorldTreeLeaf, 1);
+        assert!(!equip_from(&mut bag, &mut ragnar, Item::WorldTreeLeaf));
+        assert!(equip_from(&mut bag, &mut ragnar, Item::CopperSword));
+        assert_eq!(ragnar.equip_change(Item::IronSword).to_string(), "+4 ATK");
+        assert!(equip_from(&mut bag, &mut ragnar, Item::IronSword));
+        assert_eq!(ragnar.atk, 17);
+        assert_eq!(bag.count(Item::CopperSword), 1);
+        assert_eq!(bag.count(Item::IronSword), 0);
+    }
+
+    #[test]
+    fn a_night_at_the_inn() {
+        let inn = Inn { price_per_head: 5 };
+        let mut party = vec![Character::new("Ragnar", Team::Player),
+                             Character::new("Alena", Team::Player)];
+        party[0].knock_out();
+        party[1].mp -= 4;
+        let mut bag = Inventory::new();
+        bag.gold = 9;
+        assert_eq!(inn.stay(&mut party, &mut bag), Err(ShopError::CantAfford));
+        assert!(!party[0].is_alive());
+        bag.gold = 10;
+        assert_eq!(inn.stay(&mut party, &mut bag), Ok(()));
+        assert_eq!(bag.gold, 0);
+        assert!(party[0].is_alive() && party[0].hp.val == party[0].hp.max);
+        assert_eq!(party[1].mp.val, party[1].mp.max);
+    }
+}
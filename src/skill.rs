@@ -0,0 +1,151 @@
+//! Things characters can do that aren't magic.
+//!
+//! Each skill says what it costs (MP, HP, tension, or nothing), how
+//! many rounds it needs to cool down before it can be used again, and
+//! how it's aimed.  Who gets which skills is up to whoever makes the
+//! characters; see `battle_generator`.
+
+use std::fmt;
+
+use super::element::Element;
+use super::target::Target;
+use super::character::TENSION_MAX;
+
+/// What using a skill takes out of the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cost {
+    Free,
+    Mp(u32),
+    /// They have to have more HP than this, so it can't
+    /// knock them out.
+    Hp(u32),
+    Tension(u32),
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cost::Free => write!(f, "free"),
+            Cost::Mp(n) => write!(f, "{} MP", n),
+            Cost::Hp(n) => write!(f, "{} HP", n),
+            Cost::Tension(n) => write!(f, "{} tension", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Skill {
+    /// Takes a round to breathe in, then roasts everyone.
+    FireBreath,
+    /// Lobs a bomb that goes off a couple of rounds later.
+    TimeBomb,
+    /// A lightning-charged slash at all enemies.
+    /// Needs full tension, and uses it all up.
+    Gigaslash,
+    /// Two attacks at the same enemy, at a bit of a cost to the user.
+    DoubleSlash,
+    /// One swing at everyone within reach.
+    Sweep,
+    /// Tries to swipe an item from the enemy's bag.
+    Steal,
+    /// Lures another monster into the fight.
+    Whistle,
+}
+
+impl Skill {
+    pub fn all() -> Vec<Skill> {
+        vec![Skill::FireBreath,
+             Skill::TimeBomb,
+             Skill::Gigaslash,
+             Skill::DoubleSlash,
+             Skill::Sweep,
+             Skill::Steal,
+             Skill::Whistle]
+    }
+
+    pub fn cost(&self) -> Cost {
+        match *self {
+            Skill::Gigaslash => Cost::Tension(TENSION_MAX),
+            Skill::DoubleSlash => Cost::Hp(2),
+            Skill::Sweep => Cost::Mp(3),
+            Skill::FireBreath | Skill::TimeBomb | Skill::Steal | Skill::Whistle => Cost::Free,
+        }
+    }
+
+    /// How many rounds after using it before it can be used again.
+    pub fn cooldown(&self) -> u32 {
+        match *self {
+            Skill::FireBreath => 2,
+            Skill::TimeBomb => 3,
+            Skill::Whistle => 3,
+            Skill::DoubleSlash | Skill::Steal => 1,
+            Skill::Gigaslash | Skill::Sweep => 0,
+        }
+    }
+
+    /// Who it's aimed at, if that's fixed.
+    /// None means the user picks an enemy.
+    pub fn area(&self) -> Option<Target> {
+        match *self {
+            Skill::DoubleSlash | Skill::Steal => None,
+            Skill::Whistle => Some(Target::Myself),
+            Skill::FireBreath | Skill::TimeBomb | Skill::Gigaslash | Skill::Sweep => {
+                Some(Target::AllEnemies)
+            }
+        }
+    }
+
+    /// How many rounds the user has to spend getting ready
+    /// before it goes off.  They can't do anything else meanwhile.
+    pub fn windup(&self) -> u32 {
+        match *self {
+            Skill::FireBreath => 1,
+            _ => 0,
+        }
+    }
+
+    /// How many rounds after it's used that it actually lands.
+    /// The user's free to get on with other things meanwhile.
+    pub fn delay(&self) -> u32 {
+        match *self {
+            Skill::TimeBomb => 2,
+            _ => 0,
+        }
+    }
+
+    /// How hard it hits, and with what, for skills that aren't
+    /// just fancy plain attacks.
+    /// Works like attack power: the damage ends up somewhere
+    /// between half and one and a half times this.
+    pub fn damage(&self) -> Option<(u32, Element)> {
+        match *self {
+            Skill::FireBreath => Some((8, Element::Fire)),
+            Skill::TimeBomb => Some((10, Element::Fire)),
+            Skill::Gigaslash => Some((20, Element::Lightning)),
+            _ => None,
+        }
+    }
+
+    /// What everyone gets told while the user's winding up,
+    /// so they've got a chance to do something about it.
+    pub fn telegraph(&self) -> &'static str {
+        match *self {
+            Skill::FireBreath => "is taking a deep breath...",
+            _ => "is getting ready...",
+        }
+    }
+}
+
+impl fmt::Display for Skill {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Skill::FireBreath => write!(f, "Fire Breath"),
+            Skill::TimeBomb => write!(f, "Time Bomb"),
+            Skill::Gigaslash => write!(f, "Gigaslash"),
+            Skill::DoubleSlash => write!(f, "Double Slash"),
+            Skill::Sweep => write!(f, "Sweep"),
+            Skill::Steal => write!(f, "Steal"),
+            Skill::Whistle => write!(f, "Whistle"),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Magic.
+//!
+//! There's not much here yet besides getting people back up,
+//! fiddling with speed and one big explosion, but everything that
+//! costs MP should end up in here.
+
+use std::fmt;
+
+use super::character::BuffType;
+use super::element::Element;
+
+/// How well something brings the fallen back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revival {
+    /// Percent chance that it works at all.
+    pub chance: u32,
+    /// How much of their max HP they get back, in percent.
+    pub hp_percent: u32,
+}
+
+impl Revival {
+    /// Rolls to see whether it works this time.
+    pub fn succeeds(&self) -> bool {
+        ::dice::random::<u32>() % 100 < self.chance
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Spell {
+    /// Might revive someone, with half their HP.
+    Zing,
+    /// Always revives someone, with all their HP.
+    Kazing,
+    /// Hastes the whole party.
+    Acceleratle,
+    /// Slows down an enemy.
+    Deceleratle,
+    /// Puts an enemy to sleep.
+    Snooze,
+    /// Blows up all the enemies, after a round of chanting.
+    Kaboom,
+}
+
+impl Spell {
+    pub fn all() -> Vec<Spell> {
+        vec![Spell::Zing,
+             Spell::Kazing,
+             Spell::Acceleratle,
+             Spell::Deceleratle,
+             Spell::Snooze,
+             Spell::Kaboom]
+    }
+
+    pub fn mp_cost(&self) -> u32 {
+        match *self {
+            Spell::Zing => 8,
+            Spell::Kazing => 15,
+            Spell::Acceleratle => 3,
+            Spell::Deceleratle => 3,
+            Spell::Snooze => 4,
+            Spell::Kaboom => 15,
+        }
+    }
+
+    /// How many rounds it takes to chant before it goes off.
+    pub fn chant_rounds(&self) -> u32 {
+        match *self {
+            Spell::Kaboom => 1,
+            _ => 0,
+        }
+    }
+
+    /// Whether it hits the whole other side at once,
+    /// rather than being aimed at someone.
+    pub fn hits_all(&self) -> bool {
+        *self == Spell::Kaboom
+    }
+
+    /// How hard it hits and with what, if it's an attack spell.
+    /// Same idea as `Skill::damage`.
+    pub fn damage(&self) -> Option<(u32, Element)> {
+        match *self {
+            Spell::Kaboom => Some((12, Element::Fire)),
+            _ => None,
+        }
+    }
+
+    /// Whether it's meant for enemies rather than friends.
+    pub fn hostile(&self) -> bool {
+        match *self {
+            Spell::Deceleratle | Spell::Snooze | Spell::Kaboom => true,
+            Spell::Zing | Spell::Kazing | Spell::Acceleratle => false,
+        }
+    }
+
+    /// The buff it puts on whoever it hits, and for how many
+    /// of their turns, if it's that sort of spell.
+    pub fn buff(&self) -> Option<(BuffType, u32)> {
+        match *self {
+            Spell::Acceleratle => Some((BuffType::Haste, 3)),
+            Spell::Deceleratle => Some((BuffType::Slow, 3)),
+            Spell::Snooze => Some((BuffType::Sleep, 2)),
+            Spell::Zing | Spell::Kazing | Spell::Kaboom => None,
+        }
+    }
+
+    /// What it does for the fallen, if it's a revival spell.
+    pub fn revival(&self) -> Option<Revival> {
+        match *self {
+            Spell::Zing => {
+                Some(Revival {
+                    chance: 50,
+                    hp_percent: 50,
+                })
+            }
+            Spell::Kazing => {
+                Some(Revival {
+                    chance: 100,
+                    hp_percent: 100,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Spell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Spell::Zing => write!(f, "Zing"),
+            Spell::Kazing => write!(f, "Kazing"),
+            Spell::Acceleratle => write!(f, "Acceleratle"),
+            Spell::Deceleratle => write!(f, "Deceleratle"),
+            Spell::Snooze => write!(f, "Snooze"),
+            Spell::Kaboom => write!(f, "Kaboom"),
+        }
+    }
+}
+
+#[test]
+fn sure_things_always_work() {
+    let kazing = Spell::Kazing.revival().unwrap();
+    assert!((0..100).all(|_| kazing.succeeds()));
+    let never = Revival {
+        chance: 0,
+        hp_percent: 100,
+    };
+    assert!((0..100).all(|_| !never.succeeds()));
+}
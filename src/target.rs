@@ -0,0 +1,198 @@
+use super::dice;
+
+use super::battlefield::*;
+
+/// Who an action is aimed at.
+///
+/// Actions hang on to one of these rather than a list of characters,
+/// and it only gets turned into actual characters when the action
+/// happens, since by then some of them may well be dead.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Target {
+    /// Just the one character.
+    /// If they're dead by the time it happens, somebody else
+    /// on the same side gets picked instead.
+    Single(CharSpecifier),
+    /// Everyone (still alive) on the same side with the same name
+    /// as the given character, ie. all the Slimes.
+    Group(CharSpecifier),
+    /// Everyone the actor is hostile to.
+    AllEnemies,
+    /// Everyone on the actor's side, including themselves.
+    AllAllies,
+    /// Just the actor.
+    Myself,
+    /// This many hits, each at a random enemy.
+    /// The same one can get hit more than once.
+    Random(u32),
+}
+
+impl Target {
+    /// Whether a hit aimed at someone who's already dead should
+    /// go to somebody else instead of just fizzling.
+    pub fn retargets(&self) -> bool {
+        matches!(*self, Target::Single(_) | Target::Random(_))
+    }
+
+    /// Works out who actually gets hit, out of who's still alive.
+    /// Characters may show up more than once, for `Random`.
+    pub fn resolve(&self, field: &Battlefield, actor: CharSpecifier) -> Vec<CharSpecifier> {
+        let team = match field.get(actor) {
+            Some(chr) => chr.team,
+            None => return vec![],
+        };
+        let living_enemies: Vec<CharSpecifier> = field.get_opponents_enumerate(team)
+            .filter(|&(_, chr)| chr.is_alive())
+            .map(|(i, _)| i)
+            .collect();
+        match *self {
+            Target::Single(c) => {
+                match field.get(c) {
+                    Some(chr) if chr.is_alive() => vec![c],
+                    Some(chr) => {
+                        // Pick someone else from the same side.
+                        let others: Vec<CharSpecifier> = field.get_team_enumerate(chr.team)
+                            .filter(|&(_, o)| o.is_alive())
+                            .map(|(i, _)| i)
+                            .collect();
+                        dice::choose(&others).into_iter().cloned().collect()
+                    }
+                    None => vec![],
+                }
+            }
+            Target::Group(c) => {
+                let leader = match field.get(c) {
+                    Some(chr) => chr,
+                    None => return vec![],
+                };
+                let group: Vec<CharSpecifier> = field.chars
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, chr)| {
+                        chr.is_alive() && chr.team == leader.team && chr.name == leader.name
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
+                if !group.is_empty() {
+                    return group;
+                }
+                // The whole group's gone, so go after some other bunch.
+                match dice::choose(&living_enemies) {
+                    Some(&other) => Target::Group(other).resolve(field, actor),
+                    None => vec![],
+                }
+            }
+            Target::AllEnemies => living_enemies,
+            Target::AllAllies => {
+                field.chars
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, chr)| chr.is_alive() && field.relations.allied(team, chr.team))
+                    .map(|(i, _)| i)
+                    .collect()
+            }
+            Target::Myself => {
+                if field.get(actor).unwrap().is_alive() {
+                    vec![actor]
+                } else {
+                    vec![]
+                }
+            }
+            Target::Random(n) => {
+                if living_enemies.is_empty() {
+                    return vec![];
+                }
+                (0..n).map(|_| *dice::choose(&living_enemies).unwrap()).collect()
+            }
+        }
+    }
+
+    /// Like `resolve`, but for things that only work on the knocked-out,
+    /// like revival spells.  Nobody gets swapped in for a target that's
+    /// already back up; it just does nothing.
+    pub fn resolve_fallen(&self, field: &Battlefield, actor: CharSpecifier) -> Vec<CharSpecifier> {
+        let team = match field.get(actor) {
+            Some(chr) => chr.team,
+            None => return vec![],
+        };
+        let fallen = |c: CharSpecifier| field.get(c).is_some_and(|chr| !chr.is_alive());
+        match *self {
+            Target::Single(c) if fallen(c) => vec![c],
+            Target::AllAllies => {
+                (0..field.chars.len())
+                    .filter(|&c| fallen(c) && field.relations.allied(team, field.chars[c].team))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// A name for it to put in menus.
+    pub fn describe(&self, field: &Battlefield) -> String {
+        match *self {
+            Target::Single(c) => {
+                field.get(c).map(|chr| chr.name.clone()).unwrap_or_else(|| String::from("???"))
+            }
+            Target::Group(c) => {
+                field.get(c)
+                    .map(|chr| format!("All {}", chr.name))
+                    .unwrap_or_else(|| String::from("???"))
+            }
+            Target::AllEnemies => String::from("All enemies"),
+            Target::AllAllies => String::from("All allies"),
+            Target::Myself => String::from("Self"),
+            Target::Random(n) => format!("{} random enemies", n),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use character::*;
+
+    fn test_field() -> Battlefield {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.chars.push(Character::new("Alena", Team::Player));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        b.chars.push(Character::new("Drakee", Team::Monster));
+        b
+    }
+
+    #[test]
+    fn targets_resolve() {
+        let mut b = test_field();
+        assert_eq!(Target::Single(4).resolve(&b, 0), vec![4]);
+        assert_eq!(Target::Group(3).resolve(&b, 0), vec![2, 3]);
+        assert_eq!(Target::AllEnemies.resolve(&b, 0), vec![2, 3, 4]);
+        assert_eq!(Target::AllAllies.resolve(&b, 0), vec![0, 1]);
+        assert_eq!(Target::Myself.resolve(&b, 1), vec![1]);
+        let hits = Target::Random(5).resolve(&b, 0);
+        assert_eq!(hits.len(), 5);
+        assert!(hits.iter().all(|&h| h >= 2));
+
+        b.get_mut(3).unwrap().take_damage(1_000_000);
+        assert_eq!(Target::Group(3).resolve(&b, 0), vec![2]);
+        assert_eq!(Target::AllEnemies.resolve(&b, 0), vec![2, 4]);
+    }
+
+    #[test]
+    fn dead_targets_get_replaced() {
+        let mut b = test_field();
+        b.get_mut(2).unwrap().take_damage(1_000_000);
+        b.get_mut(3).unwrap().take_damage(1_000_000);
+        // The drakee is the only one left to take the hits.
+        assert_eq!(Target::Single(2).resolve(&b, 0), vec![4]);
+        assert_eq!(Target::Group(2).resolve(&b, 0), vec![4]);
+        // Dead allies get swapped for live ones too.
+        b.get_mut(1).unwrap().take_damage(1_000_000);
+        assert_eq!(Target::Single(1).resolve(&b, 4), vec![0]);
+        // Unless it's a revival.
+        assert_eq!(Target::Single(1).resolve_fallen(&b, 0), vec![1]);
+        assert_eq!(Target::Single(0).resolve_fallen(&b, 0), Vec::<CharSpecifier>::new());
+        assert_eq!(Target::AllAllies.resolve_fallen(&b, 0), vec![1]);
+    }
+}
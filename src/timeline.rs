@@ -0,0 +1,311 @@
+//! Conditional-turn-based battles, as opposed to the usual rounds.
+//!
+//! Instead of everyone picking and then everything happening at once,
+//! each character has a gauge that fills up according to their speed,
+//! and whoever fills theirs first gets to act, on their own.  Time
+//! stands still while they're deciding what to do.  Faster characters
+//! just plain get more turns, and haste and slow change how often
+//! someone comes up.
+//!
+//! Actions still go through `run_action`, so everything works the same
+//! either way; it's only the pacing that's different.
+
+use std::cmp;
+
+use super::dice;
+
+use super::battlefield::*;
+use super::action::*;
+use super::battle::*;
+use super::event::*;
+use super::reaction::*;
+
+/// How full a gauge has to be to act.
+pub const GAUGE_FULL: u32 = 1000;
+
+/// How much time makes up a round, for the things that still count
+/// in rounds, like summons and reinforcements.
+/// Someone with speed 10 gets one turn a round.
+pub const ROUND_TICKS: u32 = 100;
+
+/// Everyone's gauges.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    /// Indexed by `CharSpecifier`.
+    gauges: Vec<u32>,
+    /// Ticks gone by since the start of the current round.
+    ticks: u32,
+}
+
+impl Timeline {
+    /// Starts everyone with a little random head start,
+    /// so it's not always the fastest character first.
+    /// Anyone with first strike starts out ready to go.
+    pub fn new(field: &Battlefield) -> Timeline {
+        let gauges = field.chars
+            .iter()
+            .map(|chr| {
+                if chr.has_passive(Passive::FirstStrike) {
+                    GAUGE_FULL
+                } else {
+                    dice::random::<u32>() % (chr.speed() * 10 + 1)
+                }
+            })
+            .collect();
+        Timeline {
+            gauges,
+            ticks: 0,
+        }
+    }
+
+    pub fn gauge(&self, c: CharSpecifier) -> u32 {
+        self.gauges.get(c).cloned().unwrap_or(0)
+    }
+
+    /// Makes room for anyone who showed up since last time.
+    /// New arrivals start from empty.
+    fn sync(&mut self, field: &Battlefield) {
+        self.gauges.resize(field.chars.len(), 0);
+    }
+
+    /// How long until the character's gauge is full,
+    /// or None if they're knocked out.
+    /// Time still passes for the sleeping and the busy.
+    fn ticks_until_ready(&self, field: &Battlefield, c: CharSpecifier) -> Option<u32> {
+        let chr = field.get(c)?;
+        if !chr.is_alive() {
+            return None;
+        }
+        let needed = GAUGE_FULL.saturating_sub(self.gauge(c));
+        let spd = chr.speed();
+        Some(needed.div_ceil(spd))
+    }
+
+    /// Lets time pass for everyone still standing.
+    fn advance(&mut self, field: &Battlefield, ticks: u32) {
+        for (c, chr) in field.chars.iter().enumerate() {
+            if chr.is_alive() {
+                self.gauges[c] += chr.speed() * ticks;
+            }
+        }
+        self.ticks += ticks;
+    }
+
+    /// Runs time forward until somebody's gauge fills up, and
+    /// returns who.  Their gauge gets emptied, ready for next time.
+    /// Round-based things (summons leaving, reinforcements) happen
+    /// along the way, and anything that causes goes into `events`.
+    /// Returns None if nobody at all can act, or if the end of a
+    /// round has settled the battle by itself.
+    pub fn next_actor(&mut self,
+                      field: &mut Battlefield,
+                      events: &mut Vec<BattleEvent>)
+                      -> Option<CharSpecifier> {
+        loop {
+            self.sync(field);
+            let soonest = (0..field.chars.len())
+                .filter_map(|c| self.ticks_until_ready(field, c))
+                .min()?;
+            let round_left = ROUND_TICKS - self.ticks;
+            if soonest >= round_left {
+                // The round runs out first.
+                self.advance(field, round_left);
+                self.ticks = 0;
+                set_off_delayed(field, events);
+                tick_statuses(field, events);
+                tick_summons(field, events);
+                field.increment_round();
+                call_reinforcements(field, events);
+                if battle_status(field) != BattleStatus::Continuing {
+                    return None;
+                }
+                if soonest > round_left {
+                    continue;
+                }
+            } else {
+                self.advance(field, soonest);
+            }
+            let ready: Vec<CharSpecifier> = (0..field.chars.len())
+                .filter(|&c| self.ticks_until_ready(field, c) == Some(0))
+                .collect();
+            // Ties go to whoever's furthest over, then a coin flip.
+            let most = ready.iter().map(|&c| self.gauge(c)).max()?;
+            let ready: Vec<CharSpecifier> = ready.into_iter()
+                .filter(|&c| self.gauge(c) == most)
+                .collect();
+            let actor = *dice::choose(&ready)?;
+            self.gauges[actor] -= GAUGE_FULL;
+            // Sleepers lose their turn, but it still counts
+            // towards waking up.
+            let chr = field.get_mut(actor).unwrap();
+            if chr.is_asleep() {
+                chr.tick_buffs();
+                events.push(BattleEvent::Asleep(actor));
+                continue;
+            }
+            return Some(actor);
+        }
+    }
+
+    /// Who's coming up next, assuming nothing changes
+    /// (nobody gets hasted or killed, say).
+    /// Good for showing a turn list.
+    pub fn upcoming(&self, field: &Battlefield, n: usize) -> Vec<CharSpecifier> {
+        let mut sim = self.clone();
+        sim.sync(field);
+        let mut upcoming = Vec::with_capacity(n);
+        while upcoming.len() < n {
+            let next = (0..field.chars.len())
+                .filter_map(|c| sim.ticks_until_ready(field, c).map(|t| (t, c)))
+                .min_by_key(|&(t, c)| (t, cmp::Reverse(sim.gauge(c))));
+            let (ticks, c) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            sim.advance(field, ticks);
+            sim.gauges[c] -= GAUGE_FULL;
+            upcoming.push(c);
+        }
+        upcoming
+    }
+}
+
+/// Takes one character's turn: ticks down their buffs, since
+/// it's their turn that buffs count in, then does what they
+/// picked.  Returns how the battle stands afterwards.
+pub fn take_turn(field: &mut Battlefield,
+                 action: &Action,
+                 events: &mut Vec<BattleEvent>)
+                 -> BattleStatus {
+    if let Some(chr) = field.get_mut(action.source()) {
+        chr.tick_buffs();
+    }
+    run_action(field, action, events);
+    call_reinforcements(field, events);
+    battle_status(field)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use character::*;
+
+    fn race() -> Battlefield {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new_with_stats("Hare", Team::Player, 10, 10, 10, 10, 20, 10));
+        b.chars.push(Character::new_with_stats("Tortoise", Team::Monster, 10, 10, 10, 10, 10, 10));
+        b
+    }
+
+    fn count_turns(b: &mut Battlefield, turns: usize) -> usize {
+        let mut t = Timeline::new(b);
+        let mut events = Vec::new();
+        (0..turns).filter(|_| t.next_actor(b, &mut events) == Some(0)).count()
+    }
+
+    #[test]
+    fn faster_gets_more_turns() {
+        let mut b = race();
+        let hare = count_turns(&mut b, 30);
+        // Should be 20, give or take the head start.
+        assert!((18..=22).contains(&hare), "hare got {} of 30", hare);
+        // Roughly a round for every three turns.
+        assert!(b.round >= 9 && b.round <= 12, "round {}", b.round);
+    }
+
+    #[test]
+    fn haste_and_slow_change_cadence() {
+        let mut b = race();
+        b.get_mut(1).unwrap().add_buff(BuffType::Haste, 100);
+        let hare = count_turns(&mut b, 30);
+        assert!((13..=17).contains(&hare), "hare got {} of 30", hare);
+
+        b.get_mut(1).unwrap().buffs.clear();
+        b.get_mut(0).unwrap().add_buff(BuffType::Slow, 100);
+        let hare = count_turns(&mut b, 30);
+        assert!((13..=17).contains(&hare), "hare got {} of 30", hare);
+    }
+
+    #[test]
+    fn upcoming_matches_what_happens() {
+        let mut b = race();
+        let mut t = Timeline::new(&b);
+        let upcoming = t.upcoming(&b, 6);
+        let mut events = Vec::new();
+        let actual: Vec<CharSpecifier> = (0..6)
+            .map(|_| t.next_actor(&mut b, &mut events).unwrap())
+            .collect();
+        assert_eq!(upcoming.len(), 6);
+        // Ties can go either way, so the last one might not match,
+        // but otherwise who goes how often shouldn't change.
+        let hares = |v: &[CharSpecifier]| v.iter().filter(|&&c| c == 0).count() as i32;
+        assert!((hares(&upcoming) - hares(&actual)).abs() <= 1);
+    }
+
+    #[test]
+    fn the_fallen_dont_get_turns() {
+        let mut b = race();
+        b.get_mut(0).unwrap().take_damage(1_000_000);
+        assert_eq!(count_turns(&mut b, 10), 0);
+        b.get_mut(1).unwrap().take_damage(1_000_000);
+        let mut t = Timeline::new(&b);
+        let mut events = Vec::new();
+        assert_eq!(t.next_actor(&mut b, &mut events), None);
+    }
+
+    #[test]
+    fn summons_leaving_can_end_it_between_turns() {
+        let mut b = race();
+        b.get_mut(1).unwrap().lifetime = Some(1);
+        let mut t = Timeline::new(&b);
+        let mut events = Vec::new();
+        let mut actors = Vec::new();
+        while !events.contains(&BattleEvent::Departed(1)) {
+            actors.push(t.next_actor(&mut b, &mut events));
+        }
+        assert_eq!(battle_status(&b), BattleStatus::PlayerVictory);
+        // Nobody gets a turn in a fight that's already over.
+        assert_eq!(actors.last(), Some(&None));
+    }
+
+    #[test]
+    fn poison_can_end_it_between_turns() {
+        let mut b = race();
+        b.get_mut(1).unwrap().hp.val = 1;
+        b.get_mut(1).unwrap().add_buff(BuffType::Poison, 3);
+        let mut t = Timeline::new(&b);
+        let mut events = Vec::new();
+        let mut actors = Vec::new();
+        while !events.contains(&BattleEvent::Died(1)) {
+            actors.push(t.next_actor(&mut b, &mut events));
+        }
+        assert!(events.contains(&BattleEvent::PoisonDamage(1, 1)));
+        assert_eq!(battle_status(&b), BattleStatus::PlayerVictory);
+        assert_eq!(actors.last(), Some(&None));
+    }
+
+    #[test]
+    fn sleepers_miss_turns() {
+        let mut b = race();
+        b.get_mut(0).unwrap().add_buff(BuffType::Sleep, 1);
+        let mut t = Timeline::new(&b);
+        let mut events = Vec::new();
+        while t.next_actor(&mut b, &mut events) != Some(0) {}
+        let missed = events.iter().filter(|&&e| e == BattleEvent::Asleep(0)).count();
+        assert_eq!(missed, 2);
+        assert!(b.get(0).unwrap().can_act());
+    }
+
+    #[test]
+    fn turns_run_actions() {
+        let mut b = race();
+        let mut events = Vec::new();
+        b.get_mut(0).unwrap().add_buff(BuffType::Defend, 0);
+        let status = take_turn(&mut b, &Action::Defend(1), &mut events);
+        assert_eq!(status, BattleStatus::Continuing);
+        assert_eq!(events, vec![BattleEvent::Defended(1)]);
+        // Only the one taking their turn has their buffs tick.
+        assert!(b.get(0).unwrap().has_buff(BuffType::Defend));
+    }
+}
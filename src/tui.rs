@@ -0,0 +1,688 @@
+//! A full-screen terminal frontend for battles.
+//!
+//! Everything here draws into a `Screen`, which then gets handed off
+//! to a `Backend` to actually put on a terminal.  The only real backend
+//! is `TermionBackend`, which is behind the `tui` feature; `FakeBackend`
+//! just records frames and plays back canned keypresses, so we can
+//! poke at the whole thing in tests without a terminal.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+
+use super::bounded_number::BoundedNumber;
+use super::character::*;
+use super::battlefield::*;
+use super::action::*;
+use super::battle::*;
+use super::command::*;
+use super::event::*;
+use super::loot::*;
+use super::timeline::*;
+
+/// The keys the UI cares about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Cancel,
+    Quit,
+    Other,
+}
+
+/// A grid of characters that gets drawn all at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+    cells: Vec<char>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Screen {
+        Screen {
+            width,
+            height,
+            cells: vec![' '; width * height],
+        }
+    }
+
+    /// Writes a string starting at the given position.
+    /// Anything that falls off the edge is just dropped.
+    pub fn put_str(&mut self, x: usize, y: usize, s: &str) {
+        if y >= self.height {
+            return;
+        }
+        for (i, c) in s.chars().enumerate() {
+            if x + i >= self.width {
+                break;
+            }
+            self.cells[y * self.width + x + i] = c;
+        }
+    }
+
+    /// Draws a box with the title set into the top edge.
+    pub fn draw_box(&mut self, x: usize, y: usize, w: usize, h: usize, title: &str) {
+        if w < 2 || h < 2 {
+            return;
+        }
+        let horiz: String = (0..w - 2).map(|_| '-').collect();
+        self.put_str(x, y, &format!("+{}+", horiz));
+        self.put_str(x, y + h - 1, &format!("+{}+", horiz));
+        for row in y + 1..y + h - 1 {
+            self.put_str(x, row, "|");
+            self.put_str(x + w - 1, row, "|");
+        }
+        if !title.is_empty() {
+            let title: String = title.chars().take(w.saturating_sub(4)).collect();
+            self.put_str(x + 1, y, &format!("[{}]", title));
+        }
+    }
+
+    /// Returns a single row as a string, trailing spaces and all.
+    pub fn row(&self, y: usize) -> String {
+        self.cells[y * self.width..(y + 1) * self.width].iter().cloned().collect()
+    }
+
+    /// Whether the given text appears anywhere on screen.
+    /// Mostly for tests.
+    pub fn contains(&self, s: &str) -> bool {
+        (0..self.height).any(|y| self.row(y).contains(s))
+    }
+}
+
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in 0..self.height {
+            writeln!(f, "{}", self.row(y).trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Something that can show a `Screen` and read keys.
+pub trait Backend {
+    /// Width and height, in characters.
+    fn size(&self) -> (usize, usize);
+    fn draw(&mut self, screen: &Screen) -> io::Result<()>;
+    fn read_key(&mut self) -> io::Result<Key>;
+}
+
+/// A backend that doesn't touch any terminal.
+/// Keys come out of `keys` in order, and every frame drawn
+/// is kept in `frames`.  Running out of keys is an error,
+/// so a test can't accidentally hang waiting for input.
+#[derive(Debug, Clone)]
+pub struct FakeBackend {
+    pub width: usize,
+    pub height: usize,
+    pub keys: VecDeque<Key>,
+    pub frames: Vec<Screen>,
+}
+
+impl FakeBackend {
+    pub fn new(width: usize, height: usize, keys: &[Key]) -> FakeBackend {
+        FakeBackend {
+            width,
+            height,
+            keys: keys.iter().cloned().collect(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn last_frame(&self) -> Option<&Screen> {
+        self.frames.last()
+    }
+}
+
+impl Backend for FakeBackend {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn draw(&mut self, screen: &Screen) -> io::Result<()> {
+        self.frames.push(screen.clone());
+        Ok(())
+    }
+
+    fn read_key(&mut self) -> io::Result<Key> {
+        self.keys
+            .pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "out of keys"))
+    }
+}
+
+#[cfg(feature = "tui")]
+pub use self::termion_backend::TermionBackend;
+
+#[cfg(feature = "tui")]
+mod termion_backend {
+    use std::io::{self, Write};
+
+    use termion;
+    use termion::event::Key as TKey;
+    use termion::input::{Keys, TermRead};
+    use termion::raw::{IntoRawMode, RawTerminal};
+
+    use super::{Backend, Key, Screen};
+
+    /// Draws to the real terminal, in raw mode.
+    /// The terminal gets put back the way it was when this is dropped.
+    pub struct TermionBackend {
+        stdout: RawTerminal<io::Stdout>,
+        keys: Keys<io::Stdin>,
+    }
+
+    impl TermionBackend {
+        pub fn new() -> io::Result<TermionBackend> {
+            let mut stdout = io::stdout().into_raw_mode()?;
+            write!(stdout, "{}", termion::cursor::Hide)?;
+            Ok(TermionBackend {
+                stdout,
+                keys: io::stdin().keys(),
+            })
+        }
+    }
+
+    impl Drop for TermionBackend {
+        fn drop(&mut self) {
+            let _ = write!(self.stdout,
+                           "{}{}{}",
+                           termion::clear::All,
+                           termion::cursor::Goto(1, 1),
+                           termion::cursor::Show);
+            let _ = self.stdout.flush();
+        }
+    }
+
+    impl Backend for TermionBackend {
+        fn size(&self) -> (usize, usize) {
+            let (w, h) = termion::terminal_size().unwrap_or((80, 24));
+            (w as usize, h as usize)
+        }
+
+        fn draw(&mut self, screen: &Screen) -> io::Result<()> {
+            write!(self.stdout, "{}", termion::clear::All)?;
+            for y in 0..screen.height {
+                write!(self.stdout,
+                       "{}{}",
+                       termion::cursor::Goto(1, y as u16 + 1),
+                       screen.row(y))?;
+            }
+            self.stdout.flush()
+        }
+
+        fn read_key(&mut self) -> io::Result<Key> {
+            match self.keys.next() {
+                Some(Ok(k)) => {
+                    Ok(match k {
+                        TKey::Up | TKey::Char('k') => Key::Up,
+                        TKey::Down | TKey::Char('j') => Key::Down,
+                        TKey::Left => Key::Left,
+                        TKey::Right => Key::Right,
+                        TKey::Char('\n') | TKey::Char(' ') => Key::Enter,
+                        TKey::Esc | TKey::Backspace | TKey::Char('x') => Key::Cancel,
+                        TKey::Char('q') | TKey::Ctrl('c') => Key::Quit,
+                        _ => Key::Other,
+                    })
+                }
+                Some(Err(e)) => Err(e),
+                None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stdin closed")),
+            }
+        }
+    }
+}
+
+/// Makes a little `[####----]` gauge out of a BoundedNumber.
+/// Anything above zero always shows at least one notch,
+/// so you can tell a character who's hanging on from one who's dead.
+pub fn bar(n: &BoundedNumber, width: usize) -> String {
+    let inner = width.saturating_sub(2);
+    let mut filled = if n.max == 0 {
+        0
+    } else {
+        (n.val as usize * inner) / n.max as usize
+    };
+    if filled == 0 && n.val > 0 && inner > 0 {
+        filled = 1;
+    }
+    let mut s = String::from("[");
+    for i in 0..inner {
+        s.push(if i < filled { '#' } else { '-' });
+    }
+    s.push(']');
+    s
+}
+
+/// A list of choices with a cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Menu {
+    pub title: String,
+    pub items: Vec<String>,
+    pub cursor: usize,
+}
+
+impl Menu {
+    pub fn new(title: &str, items: Vec<String>) -> Menu {
+        Menu {
+            title: String::from(title),
+            items,
+            cursor: 0,
+        }
+    }
+
+    pub fn up(&mut self) {
+        if !self.items.is_empty() {
+            self.cursor = (self.cursor + self.items.len() - 1) % self.items.len();
+        }
+    }
+
+    pub fn down(&mut self) {
+        if !self.items.is_empty() {
+            self.cursor = (self.cursor + 1) % self.items.len();
+        }
+    }
+}
+
+/// How many lines of the message log we hang on to.
+const LOG_LENGTH: usize = 100;
+
+/// Width of the command menu window.
+const MENU_WIDTH: usize = 24;
+
+/// Height of a party member's status box.
+const STATUS_HEIGHT: usize = 6;
+
+pub struct Tui<B: Backend> {
+    backend: B,
+    log: VecDeque<String>,
+}
+
+impl<B: Backend> Tui<B> {
+    pub fn new(backend: B) -> Tui<B> {
+        Tui {
+            backend,
+            log: VecDeque::new(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Adds a line to the message log, dropping the oldest
+    /// ones if it gets too long.
+    pub fn message(&mut self, msg: &str) {
+        self.log.push_back(String::from(msg));
+        while self.log.len() > LOG_LENGTH {
+            self.log.pop_front();
+        }
+    }
+
+    pub fn show_events(&mut self, field: &Battlefield, events: &[BattleEvent]) {
+        for e in events {
+            let msg = e.describe(field);
+            self.message(&msg);
+        }
+    }
+
+    /// Lays out the whole screen:
+    /// party status along the top, monsters under that,
+    /// and the command menu and message log along the bottom.
+    pub fn render(&self, field: &Battlefield, menu: Option<&Menu>) -> Screen {
+        let (width, height) = self.backend.size();
+        let mut screen = Screen::new(width, height);
+
+        // Party, and anyone fighting alongside them
+        let players: Vec<&Character> = field.chars
+            .iter()
+            .filter(|c| field.relations.allied(Team::Player, c.team) && !c.has_left())
+            .collect();
+        if !players.is_empty() {
+            let boxwidth = width / players.len();
+            for (i, p) in players.iter().enumerate() {
+                let x = i * boxwidth;
+                let inner = boxwidth.saturating_sub(2);
+                let title = match p.row {
+                    Row::Front => p.name.clone(),
+                    Row::Back => format!("{} (back)", p.name),
+                };
+                screen.draw_box(x, 0, boxwidth, STATUS_HEIGHT, &title);
+                screen.put_str(x + 1, 1, &format!("HP {}", p.hp));
+                screen.put_str(x + 1, 2, &bar(&p.hp, inner));
+                let mp = if p.tension.val > 0 {
+                    format!("MP {}  T{}", p.mp, p.tension.val)
+                } else {
+                    format!("MP {}", p.mp)
+                };
+                screen.put_str(x + 1, 3, &mp);
+                screen.put_str(x + 1, 4, &bar(&p.mp, inner));
+            }
+        }
+
+        // Monsters, and anyone else who's around
+        let monsters: Vec<&Character> = field.chars
+            .iter()
+            .filter(|c| !field.relations.allied(Team::Player, c.team) && c.is_alive())
+            .collect();
+        let monster_y = STATUS_HEIGHT;
+        let monster_height = monsters.len() + 2;
+        screen.draw_box(0, monster_y, width, monster_height, "Monsters");
+        for (i, m) in monsters.iter().enumerate() {
+            let label = match m.row {
+                Row::Front => m.name.clone(),
+                Row::Back => format!("{} (back)", m.name),
+            };
+            screen.put_str(2, monster_y + 1 + i, &label);
+        }
+
+        // Menu and log
+        let bottom_y = monster_y + monster_height;
+        let bottom_height = height.saturating_sub(bottom_y);
+        let log_x = if menu.is_some() { MENU_WIDTH } else { 0 };
+        if let Some(menu) = menu {
+            screen.draw_box(0, bottom_y, MENU_WIDTH, bottom_height, &menu.title);
+            for (i, item) in menu.items.iter().enumerate() {
+                let marker = if i == menu.cursor { ">" } else { " " };
+                screen.put_str(1, bottom_y + 1 + i, &format!("{} {}", marker, item));
+            }
+        }
+        let log_title = format!("Round {}", field.round);
+        screen.draw_box(log_x, bottom_y, width.saturating_sub(log_x), bottom_height, &log_title);
+        let log_lines = bottom_height.saturating_sub(2);
+        let skip = self.log.len().saturating_sub(log_lines);
+        for (i, line) in self.log.iter().skip(skip).enumerate() {
+            screen.put_str(log_x + 2, bottom_y + 1 + i, line);
+        }
+
+        screen
+    }
+
+    pub fn draw(&mut self, field: &Battlefield, menu: Option<&Menu>) -> io::Result<()> {
+        let screen = self.render(field, menu);
+        self.backend.draw(&screen)
+    }
+
+    /// Waits for a key, redrawing the screen first.
+    /// Quitting comes back as an `Interrupted` error.
+    pub fn wait_for_key(&mut self, field: &Battlefield, menu: Option<&Menu>) -> io::Result<Key> {
+        self.draw(field, menu)?;
+        match self.backend.read_key()? {
+            Key::Quit => Err(io::Error::new(io::ErrorKind::Interrupted, "quit")),
+            k => Ok(k),
+        }
+    }
+
+    /// Lets the player move around a menu until they pick something.
+    /// Returns None if they back out of it.
+    pub fn choose(&mut self, field: &Battlefield, menu: &mut Menu) -> io::Result<Option<usize>> {
+        loop {
+            match self.wait_for_key(field, Some(menu))? {
+                Key::Up => menu.up(),
+                Key::Down => menu.down(),
+                Key::Enter if !menu.items.is_empty() => return Ok(Some(menu.cursor)),
+                Key::Cancel => return Ok(None),
+                _ => (),
+            }
+        }
+    }
+
+    /// Gets an action for every party member who can act,
+    /// by driving a `CommandEntry` with menus.
+    /// Cancelling out of the command menu goes back to
+    /// the previous party member and lets them choose again.
+    pub fn read_player_actions(&mut self,
+                               field: &Battlefield,
+                               actions: &mut Vec<Action>)
+                               -> io::Result<()> {
+        self.read_actions(field, CommandEntry::new(field, Team::Player), actions)
+    }
+
+    /// Like `read_player_actions`, but for whoever `entry` is for.
+    pub fn read_actions(&mut self,
+                        field: &Battlefield,
+                        mut entry: CommandEntry,
+                        actions: &mut Vec<Action>)
+                        -> io::Result<()> {
+        loop {
+            let mut menu = match entry.prompt(field) {
+                Prompt::Command(actor, commands) => {
+                    Menu::new(&field.get(actor).unwrap().name,
+                              commands.iter().map(|c| c.to_string()).collect())
+                }
+                Prompt::SubMenu(_, cmd, options) => Menu::new(&cmd.to_string(), options),
+                Prompt::Target(_, cmd, targets) => {
+                    Menu::new(&format!("{} what?", cmd),
+                              targets.iter()
+                                  .map(|t| t.describe(field))
+                                  .collect())
+                }
+                Prompt::Confirm => Menu::new("Ready?", vec![String::from("Fight!")]),
+                Prompt::Done => break,
+            };
+            let input = match self.choose(field, &mut menu)? {
+                Some(n) => Input::Choose(n),
+                None => Input::Cancel,
+            };
+            entry.input(field, input);
+        }
+        actions.extend_from_slice(entry.actions());
+        Ok(())
+    }
+
+    /// Runs a whole battle to the end, a character at a time
+    /// rather than in rounds.  See the `timeline` module.
+    pub fn run_battle_ctb(&mut self, field: &mut Battlefield) -> io::Result<BattleStatus> {
+        let mut timeline = Timeline::new(field);
+        let mut actions = Vec::new();
+        let mut events = Vec::new();
+        loop {
+            actions.clear();
+            events.clear();
+            let actor = timeline.next_actor(field, &mut events);
+            self.show_events(field, &events);
+            events.clear();
+            let actor = match actor {
+                Some(actor) => actor,
+                // Either the end of the round settled it, or nobody can do
+                // anything, which is as over as it's going to get.
+                None => {
+                    let status = battle_status(field);
+                    self.show_result(field, status);
+                    return Ok(status);
+                }
+            };
+            let upcoming: Vec<String> = timeline.upcoming(field, 5)
+                .iter()
+                .map(|&c| field.get(c).unwrap().name.clone())
+                .collect();
+            self.message(&format!("Next up: {}", upcoming.join(", ")));
+            let chr = field.get(actor).unwrap();
+            if let Some(action) = charged_action(field, actor) {
+                actions.push(action);
+            } else if chr.team == Team::Player && !chr.is_temporary() {
+                self.read_actions(field, CommandEntry::for_actors(field, &[actor]), &mut actions)?;
+            } else {
+                decide_actions(field, &[actor], &mut actions);
+            }
+            let mut status = battle_status(field);
+            for action in &actions {
+                status = take_turn(field, action, &mut events);
+            }
+            self.show_events(field, &events);
+            if status != BattleStatus::Continuing {
+                self.show_result(field, status);
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Says who won, and hands out the loot if it was the party.
+    fn show_result(&mut self, field: &mut Battlefield, status: BattleStatus) {
+        match status {
+            BattleStatus::PlayerVictory => {
+                self.message("Victory!");
+                for line in claim_rewards(field).describe() {
+                    self.message(&line);
+                }
+            }
+            BattleStatus::MonsterVictory | BattleStatus::OtherVictory(_) => {
+                self.message("Horrible, crushing defeat!")
+            }
+            BattleStatus::Continuing => (),
+        }
+    }
+
+    /// Runs a whole battle to the end.
+    pub fn run_battle(&mut self, field: &mut Battlefield) -> io::Result<BattleStatus> {
+        let mut actions = Vec::new();
+        let mut events = Vec::new();
+        loop {
+            tick_buffs(field);
+
+            actions.clear();
+            events.clear();
+            if field.preview_turn_order {
+                let order = preview_turn_order(field).describe(field);
+                self.message(&order);
+            }
+            self.read_player_actions(field, &mut actions)?;
+            decide_monster_actions(field, &mut actions);
+            let status = run_turn(field, &mut actions, &mut events);
+            self.show_events(field, &events);
+            if status != BattleStatus::Continuing {
+                self.show_result(field, status);
+                return Ok(status);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use target::*;
+
+    fn test_field() -> Battlefield {
+        let mut b = Battlefield::new();
+        b.chars.push(Character::new("Ragnar", Team::Player));
+        b.chars.push(Character::new("Alena", Team::Player));
+        b.chars.push(Character::new("Slime", Team::Monster));
+        b.chars.push(Character::new("Drakee", Team::Monster));
+        b
+    }
+
+    #[test]
+    fn bars_are_sized_right() {
+        let mut n = BoundedNumber::new(10);
+        assert_eq!(bar(&n, 12), "[##########]");
+        n -= 5;
+        assert_eq!(bar(&n, 12), "[#####-----]");
+        n -= 4;
+        assert_eq!(bar(&n, 7), "[#----]");
+        n -= 1;
+        assert_eq!(bar(&n, 7), "[-----]");
+    }
+
+    #[test]
+    fn render_shows_everything() {
+        let b = test_field();
+        let mut tui = Tui::new(FakeBackend::new(80, 24, &[]));
+        tui.message("Slime draws near!");
+        let menu = Menu::new("Ragnar", vec![String::from("Attack"), String::from("Defend")]);
+        let screen = tui.render(&b, Some(&menu));
+        assert!(screen.contains("[Ragnar]"));
+        assert!(screen.contains("[Alena]"));
+        assert!(screen.contains("HP 10/10"));
+        assert!(screen.contains("Drakee"));
+        assert!(screen.contains("> Attack"));
+        assert!(screen.contains("  Defend"));
+        assert!(screen.contains("Slime draws near!"));
+    }
+
+    #[test]
+    fn cancel_goes_back_a_hero() {
+        let b = test_field();
+        let keys = [
+            // Ragnar defends
+            Key::Down, Key::Enter,
+            // Alena goes to attack, changes her mind,
+            // then backs up to Ragnar
+            Key::Enter, Key::Cancel, Key::Cancel,
+            // Ragnar attacks the Drakee instead
+            Key::Enter, Key::Down, Key::Enter,
+            // Alena defends
+            Key::Down, Key::Enter,
+            // And off we go
+            Key::Enter,
+        ];
+        let mut tui = Tui::new(FakeBackend::new(80, 24, &keys));
+        let mut actions = Vec::new();
+        tui.read_player_actions(&b, &mut actions).unwrap();
+        assert_eq!(actions.len(), 2);
+        match actions[0] {
+            Action::Attack(0, Target::Single(3)) => (),
+            other => panic!("Wrong action: {:?}", other),
+        }
+        match actions[1] {
+            Action::Defend(1) => (),
+            other => panic!("Wrong action: {:?}", other),
+        }
+        assert!(tui.backend().keys.is_empty());
+    }
+
+    #[test]
+    fn battle_runs_headless() {
+        let mut b = test_field();
+        // Make the monsters slow pushovers so the battle's over
+        // in a single round.
+        for m in 2..4 {
+            b.get_mut(m).unwrap().hp = BoundedNumber::new(1);
+            b.get_mut(m).unwrap().spd = 1;
+        }
+        b.get_mut(0).unwrap().atk = 1000;
+        b.get_mut(1).unwrap().atk = 1000;
+        let keys = [Key::Enter, Key::Enter, Key::Enter, Key::Down, Key::Enter, Key::Enter];
+        let mut tui = Tui::new(FakeBackend::new(80, 24, &keys));
+        let status = tui.run_battle(&mut b).unwrap();
+        assert_eq!(status, BattleStatus::PlayerVictory);
+        let last = tui.render(&b, None);
+        assert!(last.contains("Victory!"));
+        assert!(last.contains("perished!"));
+    }
+
+    #[test]
+    fn ctb_battle_runs_headless() {
+        let mut b = test_field();
+        for m in 2..4 {
+            b.get_mut(m).unwrap().hp = BoundedNumber::new(1);
+            b.get_mut(m).unwrap().spd = 1;
+        }
+        b.get_mut(0).unwrap().atk = 1000;
+        b.get_mut(1).unwrap().atk = 1000;
+        // Each hero gets a turn of their own, with their own
+        // attack, target and confirm.
+        let keys = [Key::Enter; 6];
+        let mut tui = Tui::new(FakeBackend::new(80, 24, &keys));
+        let status = tui.run_battle_ctb(&mut b).unwrap();
+        assert_eq!(status, BattleStatus::PlayerVictory);
+        assert!(tui.render(&b, None).contains("Next up:"));
+    }
+
+    #[test]
+    fn quitting_is_an_error() {
+        let b = test_field();
+        let mut tui = Tui::new(FakeBackend::new(80, 24, &[Key::Quit]));
+        let mut actions = Vec::new();
+        let err = tui.read_player_actions(&b, &mut actions).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+}